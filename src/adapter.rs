@@ -0,0 +1,163 @@
+use crate::battery::POWER_SUPPLY_PATH;
+use crate::sysfs::SysfsRoot;
+use crate::utils::{is_transient_io_error, PersFd, PersFdError};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io;
+
+#[derive(Debug)]
+pub enum AdapterStatesError {
+  PersFdErr(PersFdError),
+  GeneralIoErr(io::Error),
+}
+
+impl fmt::Display for AdapterStatesError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AdapterStatesError::PersFdErr(e) => write!(f, "{e}"),
+      AdapterStatesError::GeneralIoErr(e) => write!(f, "General io error: {e}"),
+    }
+  }
+}
+
+impl AdapterStatesError {
+  pub fn is_transient(&self) -> bool {
+    match self {
+      AdapterStatesError::PersFdErr(e) => e.is_transient(),
+      AdapterStatesError::GeneralIoErr(e) => is_transient_io_error(e),
+    }
+  }
+}
+
+impl From<PersFdError> for AdapterStatesError {
+  fn from(error: PersFdError) -> Self {
+    AdapterStatesError::PersFdErr(error)
+  }
+}
+
+impl From<io::Error> for AdapterStatesError {
+  fn from(error: io::Error) -> Self {
+    AdapterStatesError::GeneralIoErr(error)
+  }
+}
+
+pub struct Adapter {
+  pub name: String,
+  pub supply_type: String,
+
+  online: RefCell<PersFd>,
+}
+
+impl Adapter {
+  pub fn init(root: &SysfsRoot, name: &str, supply_type: &str) -> Result<Self, AdapterStatesError> {
+    Ok(Self {
+      name: name.to_string(),
+      supply_type: supply_type.to_string(),
+
+      online: RefCell::new(PersFd::new(
+        &root.path(&format!("{}/{}/online", POWER_SUPPLY_PATH, name)),
+        false,
+      )?),
+    })
+  }
+
+  pub fn read_online(&self) -> Result<bool, AdapterStatesError> {
+    Ok(self.online.borrow_mut().read_value()? == "1")
+  }
+}
+
+pub struct AdapterStates {
+  adapters: Vec<Adapter>,
+}
+
+impl fmt::Display for AdapterStates {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "AC adapter:")?;
+
+    if self.adapters.is_empty() {
+      return write!(f, "\n    no adapter detected");
+    }
+
+    for adapter in &self.adapters {
+      write!(
+        f,
+        "\n    {} ({}): {}",
+        adapter.name,
+        adapter.supply_type,
+        match adapter.read_online() {
+          Ok(true) => "online",
+          Ok(false) => "offline",
+          Err(_) => "unknown",
+        }
+      )?;
+    }
+
+    Ok(())
+  }
+}
+
+impl AdapterStates {
+  const MAINS: &str = "Mains";
+  const USB: &str = "USB";
+
+  pub fn init(root: &SysfsRoot) -> Result<Self, AdapterStatesError> {
+    let adapters = Self::detect_adapters(root)?
+      .iter()
+      .map(|(name, supply_type)| Adapter::init(root, name, supply_type))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Self { adapters })
+  }
+
+  /// Whether a power_supply `type` describes something that can power the machine
+  /// (ACAD, AC0, ADP1, but also USB-C sources like ucsi-source-psy-*).
+  pub fn is_adapter_type(supply_type: &str) -> bool {
+    supply_type == Self::MAINS || supply_type == Self::USB
+  }
+
+  /// (name, type) of every power_supply device that can report `online`.
+  pub fn detect_adapters(root: &SysfsRoot) -> Result<Vec<(String, String)>, AdapterStatesError> {
+    let mut adapters = vec![];
+
+    let entries = match fs::read_dir(root.path(POWER_SUPPLY_PATH)) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(adapters),
+      Err(e) => return Err(AdapterStatesError::GeneralIoErr(e)),
+    };
+
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let supply_type = fs::read_to_string(path.join("type")).unwrap_or_default();
+      let supply_type = supply_type.trim();
+
+      if Self::is_adapter_type(supply_type) && path.join("online").exists() {
+        adapters.push((
+          entry.file_name().to_string_lossy().to_string(),
+          supply_type.to_string(),
+        ));
+      }
+    }
+
+    adapters.sort();
+    Ok(adapters)
+  }
+
+  pub fn adapters(&self) -> &[Adapter] {
+    &self.adapters
+  }
+
+  pub fn has_adapter(&self) -> bool {
+    !self.adapters.is_empty()
+  }
+
+  /// On ac power if any adapter is online.
+  pub fn read_online(&self) -> Result<bool, AdapterStatesError> {
+    for adapter in &self.adapters {
+      if adapter.read_online()? {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+}
@@ -1,12 +1,23 @@
-use crate::utils::{PersFd, PersFdError};
-use std::cell::RefCell;
+use crate::battery_backend::{probe_battery_backend, BatteryBackend, ChargeCapabilities};
+use crate::sysfs::SysfsRoot;
+use crate::utils::{is_transient_io_error, PersFd, PersFdError};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
 use std::fmt;
+use std::fs;
+use std::io;
 use std::num;
 
 #[derive(Debug, PartialEq)]
 pub enum ACPIType {
   ThinkPad,
-  //IdeaPad,
+  IdeaPad,
+  Asus,
+  Dell,
+  Framework,
+  Huawei,
+  LG,
+  Samsung,
   Unknown,
 }
 
@@ -15,6 +26,7 @@ pub enum ChargingStatus {
   Charging,
   NotCharging,
   DisCharging,
+  Full,
   Unknown,
 }
 
@@ -22,17 +34,55 @@ impl ChargingStatus {
   const CHARGING: &str = "Charging";
   const NOT_CHARGING: &str = "Not charging";
   const DISCHARGING: &str = "Discharging";
+  const FULL: &str = "Full";
 
   pub fn from_string(s: &str) -> Self {
     match s {
       ChargingStatus::CHARGING => Self::Charging,
       ChargingStatus::NOT_CHARGING => Self::NotCharging,
       ChargingStatus::DISCHARGING => Self::DisCharging,
+      ChargingStatus::FULL => Self::Full,
       _ => Self::Unknown,
     }
   }
 }
 
+/// `charge_behaviour` of a battery, what it does while on ac.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ChargeBehaviour {
+  /// charge up to the thresholds
+  Auto,
+  /// run from ac without charging
+  InhibitCharge,
+  /// run from the battery even though ac is connected
+  ForceDischarge,
+}
+
+impl ChargeBehaviour {
+  const AUTO: &str = "auto";
+  const INHIBIT_CHARGE: &str = "inhibit-charge";
+  const FORCE_DISCHARGE: &str = "force-discharge";
+
+  pub fn from_string(s: &str) -> Option<Self> {
+    match s {
+      ChargeBehaviour::AUTO => Some(Self::Auto),
+      ChargeBehaviour::INHIBIT_CHARGE => Some(Self::InhibitCharge),
+      ChargeBehaviour::FORCE_DISCHARGE => Some(Self::ForceDischarge),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for ChargeBehaviour {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Auto => write!(f, "{}", ChargeBehaviour::AUTO),
+      Self::InhibitCharge => write!(f, "{}", ChargeBehaviour::INHIBIT_CHARGE),
+      Self::ForceDischarge => write!(f, "{}", ChargeBehaviour::FORCE_DISCHARGE),
+    }
+  }
+}
+
 #[derive(Debug)]
 pub enum PlatformProfile {
   LowPower,
@@ -69,15 +119,32 @@ impl fmt::Display for PlatformProfile {
 
 #[derive(Debug)]
 pub enum BatteryStatesError {
+  NoBattery,
+  Unsupported,
   PersFdErr(PersFdError),
   ParseIntErr(num::ParseIntError),
+  GeneralIoErr(io::Error),
 }
 
 impl fmt::Display for BatteryStatesError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
+      BatteryStatesError::NoBattery => write!(f, "No battery detected"),
+      BatteryStatesError::Unsupported => write!(f, "Not supported by this battery"),
       BatteryStatesError::PersFdErr(e) => write!(f, "{e}"),
       BatteryStatesError::ParseIntErr(e) => write!(f, "Failed parsing integer: {e}"),
+      BatteryStatesError::GeneralIoErr(e) => write!(f, "General io error: {e}"),
+    }
+  }
+}
+
+impl BatteryStatesError {
+  pub fn is_transient(&self) -> bool {
+    match self {
+      BatteryStatesError::NoBattery | BatteryStatesError::Unsupported => false,
+      BatteryStatesError::ParseIntErr(_) => true,
+      BatteryStatesError::PersFdErr(e) => e.is_transient(),
+      BatteryStatesError::GeneralIoErr(e) => is_transient_io_error(e),
     }
   }
 }
@@ -94,26 +161,261 @@ impl From<num::ParseIntError> for BatteryStatesError {
   }
 }
 
+pub const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// Weight of the newest reading in the smoothed power draw.
+const POWER_SMOOTHING: f64 = 0.3;
+
+/// How long until the battery is empty, or charged up to its stop threshold.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TimeEstimate {
+  pub secs: u64,
+  pub charging: bool,
+  /// percent charging stops at, 0 while discharging
+  pub target: usize,
+}
+
+impl fmt::Display for TimeEstimate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (hours, minutes) = (self.secs / 3600, self.secs % 3600 / 60);
+    let duration = match hours {
+      0 => format!("{minutes}m"),
+      _ => format!("{hours}h {minutes}m"),
+    };
+    match self.charging {
+      true => write!(f, "{} to {}%", duration, self.target),
+      false => write!(f, "{} to empty", duration),
+    }
+  }
+}
+
+pub struct Battery {
+  pub name: String,
+
+  status: RefCell<PersFd>,
+  capacity: RefCell<PersFd>,
+  charge_control: Option<Box<dyn BatteryBackend>>,
+  charge_behaviour: Option<RefCell<PersFd>>,
+  power_now: Option<RefCell<PersFd>>,
+  energy_now: Option<RefCell<PersFd>>,
+  energy_full: Option<RefCell<PersFd>>,
+  // batteries reporting charge (µAh, µA) instead of energy
+  current_now: Option<RefCell<PersFd>>,
+  charge_now: Option<RefCell<PersFd>>,
+  charge_full: Option<RefCell<PersFd>>,
+  voltage_now: Option<RefCell<PersFd>>,
+  voltage_min_design: Option<RefCell<PersFd>>,
+}
+
+impl Battery {
+  pub fn init(
+    root: &SysfsRoot,
+    name: &str,
+    acpi_type: &ACPIType,
+  ) -> Result<Self, BatteryStatesError> {
+    let dir = root.path(&format!("{}/{}", POWER_SUPPLY_PATH, name));
+
+    Ok(Self {
+      name: name.to_string(),
+
+      status: RefCell::new(PersFd::new(&format!("{}/status", dir), false)?),
+      capacity: RefCell::new(PersFd::new(&format!("{}/capacity", dir), false)?),
+      charge_control: probe_battery_backend(root, &dir, acpi_type),
+      charge_behaviour: Self::open_optional(&dir, "charge_behaviour", true),
+      power_now: Self::open_optional(&dir, "power_now", false),
+      energy_now: Self::open_optional(&dir, "energy_now", false),
+      energy_full: Self::open_optional(&dir, "energy_full", false),
+      current_now: Self::open_optional(&dir, "current_now", false),
+      charge_now: Self::open_optional(&dir, "charge_now", false),
+      charge_full: Self::open_optional(&dir, "charge_full", false),
+      voltage_now: Self::open_optional(&dir, "voltage_now", false),
+      voltage_min_design: Self::open_optional(&dir, "voltage_min_design", false),
+    })
+  }
+
+  fn open_optional(dir: &str, file: &str, write: bool) -> Option<RefCell<PersFd>> {
+    PersFd::new(&format!("{}/{}", dir, file), write)
+      .ok()
+      .map(RefCell::new)
+  }
+
+  fn read_optional(fd: &Option<RefCell<PersFd>>) -> Result<u64, BatteryStatesError> {
+    match fd {
+      Some(fd) => Ok(fd.borrow_mut().read_value()?.parse()?),
+      None => Err(BatteryStatesError::Unsupported),
+    }
+  }
+
+  pub fn read_charging_status(&self) -> Result<ChargingStatus, BatteryStatesError> {
+    Ok(ChargingStatus::from_string(
+      &self.status.borrow_mut().read_value()?,
+    ))
+  }
+
+  pub fn read_capacity(&self) -> Result<usize, BatteryStatesError> {
+    Ok(self.capacity.borrow_mut().read_value()?.parse()?)
+  }
+
+  pub fn charge_control_name(&self) -> Option<&'static str> {
+    self.charge_control.as_ref().map(|c| c.name())
+  }
+
+  pub fn charge_capabilities(&self) -> Option<ChargeCapabilities> {
+    self.charge_control.as_ref().map(|c| c.capabilities())
+  }
+
+  fn charge_control(&self) -> Result<&dyn BatteryBackend, BatteryStatesError> {
+    self
+      .charge_control
+      .as_deref()
+      .ok_or(BatteryStatesError::Unsupported)
+  }
+
+  pub fn read_charge_start_threshold(&self) -> Result<usize, BatteryStatesError> {
+    self.charge_control()?.read_charge_start_threshold()
+  }
+
+  pub fn set_charge_start_threshold(&self, start: usize) -> Result<(), BatteryStatesError> {
+    self.charge_control()?.set_charge_start_threshold(start)
+  }
+
+  pub fn read_charge_stop_threshold(&self) -> Result<usize, BatteryStatesError> {
+    self.charge_control()?.read_charge_stop_threshold()
+  }
+
+  pub fn set_charge_stop_threshold(&self, stop: usize) -> Result<(), BatteryStatesError> {
+    self.charge_control()?.set_charge_stop_threshold(stop)
+  }
+
+  /// The active behaviour and every one the driver offers, read as
+  /// "[auto] inhibit-charge force-discharge".
+  fn read_charge_behaviours(
+    &self,
+  ) -> Result<(ChargeBehaviour, Vec<ChargeBehaviour>), BatteryStatesError> {
+    let fd = self
+      .charge_behaviour
+      .as_ref()
+      .ok_or(BatteryStatesError::Unsupported)?;
+    let value = fd.borrow_mut().read_value()?;
+
+    let mut active = None;
+    let mut available = vec![];
+    for word in value.split_whitespace() {
+      let name = word.trim_start_matches('[').trim_end_matches(']');
+      let Some(behaviour) = ChargeBehaviour::from_string(name) else {
+        continue;
+      };
+      // a single value without brackets is the active one too
+      if word.starts_with('[') || value.split_whitespace().count() == 1 {
+        active = Some(behaviour);
+      }
+      available.push(behaviour);
+    }
+
+    Ok((active.ok_or(BatteryStatesError::Unsupported)?, available))
+  }
+
+  pub fn read_charge_behaviour(&self) -> Result<ChargeBehaviour, BatteryStatesError> {
+    Ok(self.read_charge_behaviours()?.0)
+  }
+
+  pub fn available_charge_behaviours(&self) -> Vec<ChargeBehaviour> {
+    self
+      .read_charge_behaviours()
+      .map(|(_, available)| available)
+      .unwrap_or_default()
+  }
+
+  pub fn set_charge_behaviour(&self, behaviour: ChargeBehaviour) -> Result<(), BatteryStatesError> {
+    let fd = self
+      .charge_behaviour
+      .as_ref()
+      .ok_or(BatteryStatesError::Unsupported)?;
+    Ok(fd.borrow_mut().set_value(&behaviour.to_string())?)
+  }
+
+  /// W, from `current_now` and `voltage_now` without `power_now`
+  pub fn read_power_draw(&self) -> Result<f32, BatteryStatesError> {
+    if self.power_now.is_some() {
+      let power_uw = Self::read_optional(&self.power_now)?;
+      return Ok(power_uw as f32 / 1_000_000.0);
+    }
+
+    // signed on some drivers, negative while discharging
+    let current_ua: i64 = match &self.current_now {
+      Some(fd) => fd.borrow_mut().read_value()?.parse()?,
+      None => return Err(BatteryStatesError::Unsupported),
+    };
+    let voltage_uv = Self::read_optional(&self.voltage_now)?;
+    Ok((current_ua.unsigned_abs() as f64 * voltage_uv as f64 / 1e12) as f32)
+  }
+
+  /// µV to convert charge to energy: the design voltage, which doesn't move with the charge
+  /// level, or the current one.
+  fn read_conversion_voltage(&self) -> Result<u64, BatteryStatesError> {
+    Self::read_optional(&self.voltage_min_design)
+      .or_else(|_| Self::read_optional(&self.voltage_now))
+  }
+
+  /// µWh, converted from `charge_now` if that's what the battery reports
+  pub fn read_energy_now(&self) -> Result<u64, BatteryStatesError> {
+    if self.energy_now.is_some() {
+      return Self::read_optional(&self.energy_now);
+    }
+    let charge_uah = Self::read_optional(&self.charge_now)?;
+    Ok(charge_uah * self.read_conversion_voltage()? / 1_000_000)
+  }
+
+  /// µWh, converted from `charge_full` if that's what the battery reports
+  pub fn read_energy_full(&self) -> Result<u64, BatteryStatesError> {
+    if self.energy_full.is_some() {
+      return Self::read_optional(&self.energy_full);
+    }
+    let charge_uah = Self::read_optional(&self.charge_full)?;
+    Ok(charge_uah * self.read_conversion_voltage()? / 1_000_000)
+  }
+}
+
 pub struct BatteryStates {
-  battery_charging_status: RefCell<PersFd>,
-  battery_capacity: RefCell<PersFd>,
-  charge_start_threshold: RefCell<PersFd>,
-  charge_stop_threshold: RefCell<PersFd>,
-  total_power_draw: RefCell<PersFd>,
-  platform_profile: RefCell<PersFd>,
+  batteries: Vec<Battery>,
+  /// None on machines without ACPI platform profiles
+  platform_profile: Option<RefCell<PersFd>>,
+  /// whether it was charging and the average power draw (W) over the daemon's ticks
+  power_average: Cell<Option<(bool, f64)>>,
 }
 
 impl fmt::Display for BatteryStates {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if !self.has_battery() {
+      write!(
+        f,
+        "Battery:
+    no battery detected"
+      )?;
+      return self.fmt_platform_profile(f);
+    }
+
     write!(
       f,
       "Battery:
+    batteries: {}
+    charge control: {}
     charging status: {:?}
     battery capacity: {}%
     charge start threshold: {}%
     charge stop threshold: {}%
-    total power draw: {:.2} W
-    platform profile: {}",
+    total power draw: {:.2} W",
+      self
+        .batteries
+        .iter()
+        .map(|b| b.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", "),
+      self
+        .threshold_battery()
+        .ok()
+        .and_then(|b| b.charge_control_name())
+        .unwrap_or("unsupported"),
       self
         .read_charging_status()
         .unwrap_or(ChargingStatus::Unknown),
@@ -121,132 +423,321 @@ impl fmt::Display for BatteryStates {
       self.read_charge_start_threshold().unwrap_or(0),
       self.read_charge_stop_threshold().unwrap_or(0),
       self.read_total_power_draw().unwrap_or(0.0),
-      self
-        .read_platform_profile()
-        .unwrap_or(PlatformProfile::Unknown),
-    )
+    )?;
+    self.fmt_platform_profile(f)?;
+
+    if let Ok(behaviour) = self.read_charge_behaviour() {
+      write!(f, "\n    charge behaviour: {}", behaviour)?;
+    }
+
+    if self.batteries.len() > 1 {
+      for battery in &self.batteries {
+        write!(
+          f,
+          "\n    {}: {}% {:?}, thresholds {}-{}%",
+          battery.name,
+          battery.read_capacity().unwrap_or(0),
+          battery
+            .read_charging_status()
+            .unwrap_or(ChargingStatus::Unknown),
+          battery.read_charge_start_threshold().unwrap_or(0),
+          battery.read_charge_stop_threshold().unwrap_or(0),
+        )?;
+      }
+    }
+
+    Ok(())
   }
 }
 
 impl BatteryStates {
-  // TODO: search /sys/class/power_supply/ and search for BAT* and choose the highest number
-  //      note: doesn't work for something like thinkpad with 2 batteries
-  //      note: if no BAT* then means on desktop and should crash program with according error
-  pub fn init() -> Result<Self, BatteryStatesError> {
+  pub fn init(root: &SysfsRoot, acpi_type: &ACPIType) -> Result<Self, BatteryStatesError> {
+    let batteries = Self::detect_batteries(root)?
+      .iter()
+      .map(|name| Battery::init(root, name, acpi_type))
+      .collect::<Result<Vec<_>, _>>()?;
+
     Ok(Self {
-      battery_charging_status: RefCell::new(PersFd::new(
-        "/sys/class/power_supply/BAT0/status",
-        false,
-      )?),
-      battery_capacity: RefCell::new(PersFd::new("/sys/class/power_supply/BAT0/capacity", false)?),
-      charge_start_threshold: RefCell::new(PersFd::new(
-        "/sys/class/power_supply/BAT0/charge_start_threshold",
+      batteries,
+      platform_profile: Battery::open_optional(
+        &root.path("/sys/firmware/acpi"),
+        "platform_profile",
         true,
-      )?),
-      charge_stop_threshold: RefCell::new(PersFd::new(
-        "/sys/class/power_supply/BAT0/charge_stop_threshold",
-        true,
-      )?),
-      total_power_draw: RefCell::new(PersFd::new(
-        "/sys/class/power_supply/BAT0/power_now",
-        false,
-      )?),
-      platform_profile: RefCell::new(
-        PersFd::new("/sys/firmware/acpi/platform_profile", true).unwrap(),
       ),
+      power_average: Cell::new(None),
     })
   }
 
-  /*
-  pub fn load_charging_status() -> Result<RefCell<PersFd>, BatteryStatesError> {
-      let power_supply_path = Path::new("/sys/class/power_supply");
-      if let Ok(entries) = fs::read_dir(power_supply_path) {
-          for entry in entries.flatten() {
-              let name = entry.file_name();
-              let name_str = name.to_string_lossy();
-              if name_str.starts_with("AC") || name_str.starts_with("ACAD") {
-                  let online_path = entry.path().join("online");
-                  if online_path.exists() {
-                      return Ok(RefCell::new(PersFd::new(
-                          online_path.to_str().unwrap(),
-                          false,
-                      )?));
-                  }
-              }
-          }
+  /// Names of every system battery under /sys/class/power_supply, sorted (BAT0, BAT1, ...).
+  /// Batteries of peripherals (wireless mice, keyboards) report scope "Device" and are skipped.
+  pub fn detect_batteries(root: &SysfsRoot) -> Result<Vec<String>, BatteryStatesError> {
+    let mut names = vec![];
+
+    let entries = match fs::read_dir(root.path(POWER_SUPPLY_PATH)) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(names),
+      Err(e) => return Err(BatteryStatesError::GeneralIoErr(e)),
+    };
+
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let supply_type = fs::read_to_string(path.join("type")).unwrap_or_default();
+      if supply_type.trim() != "Battery" {
+        continue;
       }
 
-      Ok(RefCell::new(PersFd::new("", false)?))
+      let scope = fs::read_to_string(path.join("scope")).unwrap_or_default();
+      if scope.trim() == "Device" {
+        continue;
+      }
+
+      names.push(entry.file_name().to_string_lossy().to_string());
+    }
+
+    names.sort();
+    Ok(names)
   }
-  */
 
+  pub fn batteries(&self) -> &[Battery] {
+    &self.batteries
+  }
+
+  pub fn has_battery(&self) -> bool {
+    !self.batteries.is_empty()
+  }
+
+  /// Discharging if any pack is discharging, otherwise charging if any pack is charging. Full
+  /// only when every pack is.
   pub fn read_charging_status(&self) -> Result<ChargingStatus, BatteryStatesError> {
-    Ok(ChargingStatus::from_string(
-      &self.battery_charging_status.borrow_mut().read_value()?,
-    ))
+    let mut status = ChargingStatus::Unknown;
+
+    for battery in &self.batteries {
+      match battery.read_charging_status()? {
+        ChargingStatus::DisCharging => return Ok(ChargingStatus::DisCharging),
+        ChargingStatus::Charging => status = ChargingStatus::Charging,
+        ChargingStatus::NotCharging
+          if matches!(status, ChargingStatus::Unknown | ChargingStatus::Full) =>
+        {
+          status = ChargingStatus::NotCharging
+        }
+        ChargingStatus::Full if status == ChargingStatus::Unknown => status = ChargingStatus::Full,
+        _ => {}
+      }
+    }
+
+    Ok(status)
+  }
+
+  /// At 100% or reported full, some packs stop charging a bit short of 100%.
+  pub fn is_full(&self) -> Result<bool, BatteryStatesError> {
+    Ok(self.read_battery_capacity()? >= 100 || self.read_charging_status()? == ChargingStatus::Full)
   }
 
+  /// Combined capacity of all packs, weighted by energy when every pack reports it.
   pub fn read_battery_capacity(&self) -> Result<usize, BatteryStatesError> {
-    Ok(self.battery_capacity.borrow_mut().read_value()?.parse()?)
+    if !self.has_battery() {
+      return Err(BatteryStatesError::NoBattery);
+    }
+
+    if let Ok((now, full)) = self.read_energy()
+      && let Some(capacity) = (now * 100).checked_div(full)
+    {
+      return Ok(capacity.min(100) as usize);
+    }
+
+    let mut total = 0;
+    for battery in &self.batteries {
+      total += battery.read_capacity()?;
+    }
+    Ok(total / self.batteries.len())
+  }
+
+  /// µWh now and when full, summed over all packs
+  fn read_energy(&self) -> Result<(u64, u64), BatteryStatesError> {
+    let mut energy = (0, 0);
+    for battery in &self.batteries {
+      energy.0 += battery.read_energy_now()?;
+      energy.1 += battery.read_energy_full()?;
+    }
+    Ok(energy)
+  }
+
+  /// Feeds the power draw into the moving average, once per daemon tick. Starts over when the
+  /// battery switches between charging and discharging, or can't be read.
+  pub fn sample(&self) {
+    let charging = match self.read_charging_status() {
+      Ok(ChargingStatus::Charging) => true,
+      Ok(ChargingStatus::DisCharging) => false,
+      _ => {
+        self.power_average.set(None);
+        return;
+      }
+    };
+    let Ok(watts) = self.read_total_power_draw() else {
+      self.power_average.set(None);
+      return;
+    };
+
+    let watts = f64::from(watts);
+    let average = match self.power_average.get() {
+      Some((was_charging, average)) if was_charging == charging => {
+        average + POWER_SMOOTHING * (watts - average)
+      }
+      _ => watts,
+    };
+    self.power_average.set(Some((charging, average)));
+  }
+
+  /// From the smoothed power draw, None before the first `sample`, while neither charging nor
+  /// discharging, or once the stop threshold is reached.
+  pub fn estimate_time(&self) -> Option<TimeEstimate> {
+    let (charging, watts) = self.power_average.get()?;
+    if watts <= 0.0 {
+      return None;
+    }
+    let (now, full) = self.read_energy().ok()?;
+
+    let (target, remaining) = match charging {
+      true => {
+        let target = self.read_charge_stop_threshold().unwrap_or(100);
+        (target, (full * target as u64 / 100).checked_sub(now)?)
+      }
+      false => (0, now),
+    };
+
+    Some(TimeEstimate {
+      secs: (remaining as f64 / 1_000_000.0 / watts * 3600.0) as u64,
+      charging,
+      target,
+    })
+  }
+
+  fn threshold_battery(&self) -> Result<&Battery, BatteryStatesError> {
+    self
+      .batteries
+      .iter()
+      .find(|b| b.charge_control.is_some())
+      .ok_or(BatteryStatesError::Unsupported)
+  }
+
+  pub fn charge_control_name(&self) -> Option<&'static str> {
+    self.threshold_battery().ok()?.charge_control_name()
+  }
+
+  /// Capabilities shared by every pack that has charge control.
+  pub fn charge_capabilities(&self) -> Option<ChargeCapabilities> {
+    self
+      .batteries
+      .iter()
+      .filter_map(|b| b.charge_capabilities())
+      .reduce(|a, b| a.intersect(&b))
   }
 
   pub fn read_charge_start_threshold(&self) -> Result<usize, BatteryStatesError> {
-    Ok(
-      self
-        .charge_start_threshold
-        .borrow_mut()
-        .read_value()?
-        .parse()?,
-    )
+    self.threshold_battery()?.read_charge_start_threshold()
   }
 
   pub fn set_charge_start_threshold(&self, start: usize) -> Result<(), BatteryStatesError> {
-    Ok(
-      self
-        .charge_start_threshold
-        .borrow_mut()
-        .set_value(&start.to_string())?,
-    )
+    self.threshold_battery()?;
+    for battery in self.batteries.iter().filter(|b| b.charge_control.is_some()) {
+      battery.set_charge_start_threshold(start)?;
+    }
+    Ok(())
   }
 
   pub fn read_charge_stop_threshold(&self) -> Result<usize, BatteryStatesError> {
-    Ok(
-      self
-        .charge_stop_threshold
-        .borrow_mut()
-        .read_value()?
-        .parse()?,
-    )
+    self.threshold_battery()?.read_charge_stop_threshold()
   }
 
   pub fn set_charge_stop_threshold(&self, stop: usize) -> Result<(), BatteryStatesError> {
-    Ok(
-      self
-        .charge_stop_threshold
-        .borrow_mut()
-        .set_value(&stop.to_string())?,
-    )
+    self.threshold_battery()?;
+    for battery in self.batteries.iter().filter(|b| b.charge_control.is_some()) {
+      battery.set_charge_stop_threshold(stop)?;
+    }
+    Ok(())
   }
 
-  pub fn read_total_power_draw(&self) -> Result<f32, BatteryStatesError> {
-    let power_uw: u64 = self.total_power_draw.borrow_mut().read_value()?.parse()?;
+  /// Of the first pack that has a `charge_behaviour`.
+  pub fn read_charge_behaviour(&self) -> Result<ChargeBehaviour, BatteryStatesError> {
+    self
+      .batteries
+      .iter()
+      .find(|b| b.charge_behaviour.is_some())
+      .ok_or(BatteryStatesError::Unsupported)?
+      .read_charge_behaviour()
+  }
+
+  pub fn supports_charge_behaviour(&self, behaviour: ChargeBehaviour) -> bool {
+    self
+      .batteries
+      .iter()
+      .any(|b| b.available_charge_behaviours().contains(&behaviour))
+  }
 
-    let watts = power_uw as f32 / 1_000_000.0;
+  /// Sets every pack that has a `charge_behaviour`.
+  pub fn set_charge_behaviour(&self, behaviour: ChargeBehaviour) -> Result<(), BatteryStatesError> {
+    let batteries: Vec<&Battery> = self
+      .batteries
+      .iter()
+      .filter(|b| b.charge_behaviour.is_some())
+      .collect();
+    if batteries.is_empty() {
+      return Err(BatteryStatesError::Unsupported);
+    }
+    for battery in batteries {
+      battery.set_charge_behaviour(behaviour)?;
+    }
+    Ok(())
+  }
+
+  /// W, summed over all packs
+  pub fn read_total_power_draw(&self) -> Result<f32, BatteryStatesError> {
+    let mut watts = 0.0;
+    for battery in &self.batteries {
+      watts += battery.read_power_draw()?;
+    }
     Ok(watts)
   }
 
+  /// Files powereg writes to (platform profile and charge thresholds), for `Snapshot`.
+  pub fn knob_paths(&self) -> Vec<String> {
+    let mut paths: Vec<String> = self
+      .platform_profile
+      .iter()
+      .map(|fd| fd.borrow().path().to_string())
+      .collect();
+    for battery in &self.batteries {
+      if let Some(charge_control) = &battery.charge_control {
+        paths.extend(charge_control.knob_paths());
+      }
+    }
+    paths
+  }
+
+  pub fn has_platform_profile(&self) -> bool {
+    self.platform_profile.is_some()
+  }
+
   pub fn read_platform_profile(&self) -> Result<PlatformProfile, BatteryStatesError> {
-    Ok(PlatformProfile::from_string(
-      &self.platform_profile.borrow_mut().read_value()?,
-    ))
+    match &self.platform_profile {
+      Some(fd) => Ok(PlatformProfile::from_string(&fd.borrow_mut().read_value()?)),
+      None => Err(BatteryStatesError::Unsupported),
+    }
   }
 
   pub fn set_platform_profile(&self, pp: &PlatformProfile) -> Result<(), BatteryStatesError> {
-    Ok(
-      self
-        .platform_profile
-        .borrow_mut()
-        .set_value(&pp.to_string())?,
-    )
+    match &self.platform_profile {
+      Some(fd) => Ok(fd.borrow_mut().set_value(&pp.to_string())?),
+      None => Err(BatteryStatesError::Unsupported),
+    }
+  }
+
+  /// Left out where there's no platform profile.
+  fn fmt_platform_profile(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.read_platform_profile() {
+      Ok(pp) => write!(f, "\n    platform profile: {}", pp),
+      Err(BatteryStatesError::Unsupported) => Ok(()),
+      Err(_) => write!(f, "\n    platform profile: {}", PlatformProfile::Unknown),
+    }
   }
 }
@@ -0,0 +1,449 @@
+use crate::battery::{ACPIType, BatteryStatesError};
+use crate::sysfs::SysfsRoot;
+use crate::utils::PersFd;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Which values a charge threshold accepts, percent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdRange {
+  Unsupported,
+  /// inclusive
+  Range(usize, usize),
+  /// only these, e.g. a conservation mode that is either on or off
+  Fixed(&'static [usize]),
+}
+
+impl ThresholdRange {
+  pub fn is_supported(&self) -> bool {
+    *self != ThresholdRange::Unsupported
+  }
+
+  pub fn allows(&self, value: usize) -> bool {
+    match self {
+      ThresholdRange::Unsupported => false,
+      ThresholdRange::Range(min, max) => (*min..=*max).contains(&value),
+      ThresholdRange::Fixed(values) => values.contains(&value),
+    }
+  }
+
+  pub fn max(&self) -> Option<usize> {
+    match self {
+      ThresholdRange::Unsupported => None,
+      ThresholdRange::Range(_, max) => Some(*max),
+      ThresholdRange::Fixed(values) => values.iter().max().copied(),
+    }
+  }
+
+  /// What both allow, for batteries that get the same thresholds.
+  pub fn intersect(&self, other: &ThresholdRange) -> ThresholdRange {
+    match (self, other) {
+      (ThresholdRange::Range(a_min, a_max), ThresholdRange::Range(b_min, b_max)) => {
+        let (min, max) = (*a_min.max(b_min), *a_max.min(b_max));
+        match min <= max {
+          true => ThresholdRange::Range(min, max),
+          false => ThresholdRange::Unsupported,
+        }
+      }
+      (ThresholdRange::Unsupported, _) | (_, ThresholdRange::Unsupported) => {
+        ThresholdRange::Unsupported
+      }
+      // fixed levels come from machine wide switches, every battery has the same
+      (ThresholdRange::Fixed(_), _) => *self,
+      (_, ThresholdRange::Fixed(_)) => *other,
+    }
+  }
+}
+
+impl fmt::Display for ThresholdRange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ThresholdRange::Unsupported => write!(f, "not supported"),
+      ThresholdRange::Range(min, max) => write!(f, "{min}-{max}"),
+      ThresholdRange::Fixed(values) => {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        write!(f, "{}", values.join(" or "))
+      }
+    }
+  }
+}
+
+/// Which charge thresholds a charge control backend can set, and to what.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargeCapabilities {
+  pub start: ThresholdRange,
+  pub stop: ThresholdRange,
+  /// how far the start threshold has to stay below the stop threshold
+  pub min_gap: usize,
+}
+
+impl ChargeCapabilities {
+  /// Whether the hardware takes these thresholds, explaining what it allows if not.
+  pub fn check(&self, start: Option<usize>, stop: Option<usize>) -> Result<(), String> {
+    if start.is_some() && !self.start.is_supported() {
+      return Err("start_threshold is not supported, only a stop_threshold can be set".to_string());
+    }
+    if let Some(start) = start
+      && !self.start.allows(start)
+    {
+      return Err(format!(
+        "start_threshold {start} is not supported, allowed: {}",
+        self.start
+      ));
+    }
+    if let Some(stop) = stop
+      && !self.stop.allows(stop)
+    {
+      return Err(format!(
+        "stop_threshold {stop} is not supported, allowed: {}",
+        self.stop
+      ));
+    }
+    if let (Some(start), Some(stop)) = (start, stop)
+      && stop < start + self.min_gap
+    {
+      return Err(format!(
+        "stop_threshold has to be at least {} above start_threshold",
+        self.min_gap
+      ));
+    }
+    Ok(())
+  }
+
+  /// The highest thresholds the hardware takes, None for one it doesn't have. Charging starts
+  /// right away and only stops once full.
+  pub fn full_charge(&self) -> (Option<usize>, Option<usize>) {
+    let stop = self.stop.max();
+    let start = self
+      .start
+      .max()
+      .map(|start| start.min(stop.unwrap_or(100).saturating_sub(self.min_gap)));
+    (start, stop)
+  }
+
+  pub fn intersect(&self, other: &ChargeCapabilities) -> ChargeCapabilities {
+    ChargeCapabilities {
+      start: self.start.intersect(&other.start),
+      stop: self.stop.intersect(&other.stop),
+      min_gap: self.min_gap.max(other.min_gap),
+    }
+  }
+}
+
+pub trait BatteryBackend {
+  fn name(&self) -> &'static str;
+  fn capabilities(&self) -> ChargeCapabilities;
+
+  fn read_charge_start_threshold(&self) -> Result<usize, BatteryStatesError> {
+    Err(BatteryStatesError::Unsupported)
+  }
+
+  fn set_charge_start_threshold(&self, _start: usize) -> Result<(), BatteryStatesError> {
+    Err(BatteryStatesError::Unsupported)
+  }
+
+  fn read_charge_stop_threshold(&self) -> Result<usize, BatteryStatesError>;
+  fn set_charge_stop_threshold(&self, stop: usize) -> Result<(), BatteryStatesError>;
+
+  /// Files the backend writes to, for `Snapshot`.
+  fn knob_paths(&self) -> Vec<String>;
+}
+
+fn open_first(dir: &str, files: &[&str]) -> Option<RefCell<PersFd>> {
+  files
+    .iter()
+    .map(|file| format!("{}/{}", dir, file))
+    .filter(|path| Path::new(path).exists())
+    .find_map(|path| PersFd::new(&path, true).ok())
+    .map(RefCell::new)
+}
+
+fn read_threshold(fd: &RefCell<PersFd>) -> Result<usize, BatteryStatesError> {
+  Ok(fd.borrow_mut().read_value()?.parse()?)
+}
+
+fn set_threshold(fd: &RefCell<PersFd>, value: usize) -> Result<(), BatteryStatesError> {
+  Ok(fd.borrow_mut().set_value(&value.to_string())?)
+}
+
+/// thinkpad_acpi, which also exposes the legacy `charge_{start,stop}_threshold` names.
+pub struct ThinkPadChargeControl {
+  start: RefCell<PersFd>,
+  stop: RefCell<PersFd>,
+}
+
+impl ThinkPadChargeControl {
+  pub fn probe(battery_dir: &str, acpi_type: &ACPIType) -> Option<Self> {
+    if *acpi_type != ACPIType::ThinkPad {
+      return None;
+    }
+
+    Some(Self {
+      start: open_first(
+        battery_dir,
+        &["charge_control_start_threshold", "charge_start_threshold"],
+      )?,
+      stop: open_first(
+        battery_dir,
+        &["charge_control_end_threshold", "charge_stop_threshold"],
+      )?,
+    })
+  }
+}
+
+impl BatteryBackend for ThinkPadChargeControl {
+  fn name(&self) -> &'static str {
+    "thinkpad_acpi"
+  }
+
+  fn capabilities(&self) -> ChargeCapabilities {
+    ChargeCapabilities {
+      start: ThresholdRange::Range(0, 99),
+      stop: ThresholdRange::Range(1, 100),
+      min_gap: 1,
+    }
+  }
+
+  fn read_charge_start_threshold(&self) -> Result<usize, BatteryStatesError> {
+    read_threshold(&self.start)
+  }
+
+  fn set_charge_start_threshold(&self, start: usize) -> Result<(), BatteryStatesError> {
+    set_threshold(&self.start, start)
+  }
+
+  fn read_charge_stop_threshold(&self) -> Result<usize, BatteryStatesError> {
+    read_threshold(&self.stop)
+  }
+
+  fn set_charge_stop_threshold(&self, stop: usize) -> Result<(), BatteryStatesError> {
+    set_threshold(&self.stop, stop)
+  }
+
+  fn knob_paths(&self) -> Vec<String> {
+    vec![
+      self.start.borrow().path().to_string(),
+      self.stop.borrow().path().to_string(),
+    ]
+  }
+}
+
+/// What a vendor's driver accepts through the generic attributes.
+struct VendorLimits {
+  name: &'static str,
+  start: ThresholdRange,
+  stop: ThresholdRange,
+  min_gap: usize,
+}
+
+impl VendorLimits {
+  fn for_acpi_type(acpi_type: &ACPIType) -> Self {
+    let (name, start, stop, min_gap) = match acpi_type {
+      ACPIType::Asus => (
+        "asus_wmi",
+        ThresholdRange::Unsupported,
+        ThresholdRange::Range(0, 100),
+        1,
+      ),
+      ACPIType::Dell => (
+        "dell_laptop",
+        ThresholdRange::Range(50, 95),
+        ThresholdRange::Range(55, 100),
+        5,
+      ),
+      ACPIType::Huawei => (
+        "huawei_wmi",
+        ThresholdRange::Range(0, 99),
+        ThresholdRange::Range(1, 100),
+        1,
+      ),
+      ACPIType::LG => (
+        "lg_laptop",
+        ThresholdRange::Unsupported,
+        ThresholdRange::Fixed(&[80, 100]),
+        1,
+      ),
+      ACPIType::Framework => (
+        "framework_laptop",
+        ThresholdRange::Range(0, 99),
+        ThresholdRange::Range(1, 100),
+        1,
+      ),
+      _ => (
+        "charge_control",
+        ThresholdRange::Range(0, 99),
+        ThresholdRange::Range(1, 100),
+        1,
+      ),
+    };
+    Self {
+      name,
+      start,
+      stop,
+      min_gap,
+    }
+  }
+}
+
+/// The generic power_supply `charge_control_*_threshold` attributes, which most vendor drivers
+/// (asus-wmi, dell-laptop, huawei-wmi, lg-laptop, framework) implement with their own limits.
+/// Start is optional.
+pub struct GenericChargeControl {
+  limits: VendorLimits,
+  start: Option<RefCell<PersFd>>,
+  stop: RefCell<PersFd>,
+}
+
+impl GenericChargeControl {
+  pub fn probe(battery_dir: &str, acpi_type: &ACPIType) -> Option<Self> {
+    let limits = VendorLimits::for_acpi_type(acpi_type);
+    let start = match limits.start.is_supported() {
+      true => open_first(battery_dir, &["charge_control_start_threshold"]),
+      false => None,
+    };
+
+    Some(Self {
+      limits,
+      start,
+      stop: open_first(battery_dir, &["charge_control_end_threshold"])?,
+    })
+  }
+}
+
+impl BatteryBackend for GenericChargeControl {
+  fn name(&self) -> &'static str {
+    self.limits.name
+  }
+
+  fn capabilities(&self) -> ChargeCapabilities {
+    ChargeCapabilities {
+      start: match self.start {
+        Some(_) => self.limits.start,
+        None => ThresholdRange::Unsupported,
+      },
+      stop: self.limits.stop,
+      min_gap: self.limits.min_gap,
+    }
+  }
+
+  fn read_charge_start_threshold(&self) -> Result<usize, BatteryStatesError> {
+    match &self.start {
+      Some(fd) => read_threshold(fd),
+      None => Err(BatteryStatesError::Unsupported),
+    }
+  }
+
+  fn set_charge_start_threshold(&self, start: usize) -> Result<(), BatteryStatesError> {
+    match &self.start {
+      Some(fd) => set_threshold(fd, start),
+      None => Err(BatteryStatesError::Unsupported),
+    }
+  }
+
+  fn read_charge_stop_threshold(&self) -> Result<usize, BatteryStatesError> {
+    read_threshold(&self.stop)
+  }
+
+  fn set_charge_stop_threshold(&self, stop: usize) -> Result<(), BatteryStatesError> {
+    set_threshold(&self.stop, stop)
+  }
+
+  fn knob_paths(&self) -> Vec<String> {
+    self
+      .start
+      .iter()
+      .chain([&self.stop])
+      .map(|fd| fd.borrow().path().to_string())
+      .collect()
+  }
+}
+
+/// An on/off switch that stops charging at a fixed level: ideapad_acpi's `conservation_mode`
+/// and samsung-laptop's `battery_life_extender`.
+pub struct ConservationMode {
+  name: &'static str,
+  mode: RefCell<PersFd>,
+  stops: &'static [usize],
+}
+
+impl ConservationMode {
+  const IDEAPAD_PATH: &str = "/sys/bus/platform/drivers/ideapad_acpi";
+  const SAMSUNG_PATH: &str = "/sys/devices/platform/samsung/battery_life_extender";
+
+  pub fn probe(root: &SysfsRoot, acpi_type: &ACPIType) -> Option<Self> {
+    let (name, path, stops): (_, _, &'static [usize]) = match acpi_type {
+      // the driver's device is named after its ACPI id, `VPC2004:00`
+      ACPIType::IdeaPad => (
+        "ideapad_acpi",
+        fs::read_dir(root.path(Self::IDEAPAD_PATH))
+          .ok()?
+          .filter_map(|entry| Some(entry.ok()?.path().join("conservation_mode")))
+          .find(|path| path.exists())?
+          .to_string_lossy()
+          .to_string(),
+        &[60, 100],
+      ),
+      ACPIType::Samsung => ("samsung_laptop", root.path(Self::SAMSUNG_PATH), &[80, 100]),
+      _ => return None,
+    };
+
+    Some(Self {
+      name,
+      mode: RefCell::new(PersFd::new(&path, true).ok()?),
+      stops,
+    })
+  }
+}
+
+impl BatteryBackend for ConservationMode {
+  fn name(&self) -> &'static str {
+    self.name
+  }
+
+  fn capabilities(&self) -> ChargeCapabilities {
+    ChargeCapabilities {
+      start: ThresholdRange::Unsupported,
+      stop: ThresholdRange::Fixed(self.stops),
+      min_gap: 1,
+    }
+  }
+
+  fn read_charge_stop_threshold(&self) -> Result<usize, BatteryStatesError> {
+    match read_threshold(&self.mode)? {
+      0 => Ok(100),
+      _ => Ok(self.stops[0]),
+    }
+  }
+
+  fn set_charge_stop_threshold(&self, stop: usize) -> Result<(), BatteryStatesError> {
+    if !self.stops.contains(&stop) {
+      return Err(BatteryStatesError::Unsupported);
+    }
+    set_threshold(&self.mode, usize::from(stop < 100))
+  }
+
+  fn knob_paths(&self) -> Vec<String> {
+    vec![self.mode.borrow().path().to_string()]
+  }
+}
+
+/// Vendor specific backends first, then the generic kernel interface.
+pub fn probe_battery_backend(
+  root: &SysfsRoot,
+  battery_dir: &str,
+  acpi_type: &ACPIType,
+) -> Option<Box<dyn BatteryBackend>> {
+  if let Some(backend) = ThinkPadChargeControl::probe(battery_dir, acpi_type) {
+    return Some(Box::new(backend));
+  }
+  if let Some(backend) = ConservationMode::probe(root, acpi_type) {
+    return Some(Box::new(backend));
+  }
+  if let Some(backend) = GenericChargeControl::probe(battery_dir, acpi_type) {
+    return Some(Box::new(backend));
+  }
+
+  None
+}
@@ -0,0 +1,150 @@
+use crate::battery::{BatteryStates, ChargeBehaviour};
+use crate::system_state::SystemStateError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const CALIBRATION_PATH: &str = "/var/lib/powereg/calibration.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+  /// forced to run from the battery until it's down to the target
+  Discharging,
+  /// charging to 100% with the stop threshold lifted
+  Charging,
+}
+
+/// A one-off battery calibration cycle: discharge to `target`, charge to 100%, then put the
+/// thresholds back.
+///
+/// It takes hours, so the progress is kept on disk and a daemon that got restarted (or a reboot,
+/// which resets `charge_behaviour`) picks it up where it was.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Calibration {
+  /// percent
+  pub target: usize,
+  pub phase: Phase,
+  /// the thresholds to restore once done, None where there's no charge control
+  pub start_threshold: Option<usize>,
+  pub stop_threshold: Option<usize>,
+}
+
+impl fmt::Display for Calibration {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.phase {
+      Phase::Discharging => write!(f, "discharging to {}%", self.target),
+      Phase::Charging => write!(f, "charging to 100%"),
+    }
+  }
+}
+
+impl Calibration {
+  /// Skips straight to charging if the battery is already at or below `target`.
+  pub fn start(battery_states: &BatteryStates, target: usize) -> Result<Self, SystemStateError> {
+    if target >= 100 {
+      return Err(SystemStateError::UnsupportedErr(format!(
+        "calibration target has to be below 100%, got {target}%"
+      )));
+    }
+    if !battery_states.supports_charge_behaviour(ChargeBehaviour::ForceDischarge) {
+      return Err(SystemStateError::UnsupportedErr(
+        "battery can't be force discharged (no charge_behaviour)".to_string(),
+      ));
+    }
+
+    let phase = if battery_states.read_battery_capacity()? > target {
+      Phase::Discharging
+    } else {
+      Phase::Charging
+    };
+
+    Ok(Self {
+      target,
+      phase,
+      start_threshold: battery_states.read_charge_start_threshold().ok(),
+      stop_threshold: battery_states.read_charge_stop_threshold().ok(),
+    })
+  }
+
+  /// Writes what the current phase needs, again after a restart.
+  pub fn apply(&self, battery_states: &BatteryStates) -> Result<(), SystemStateError> {
+    match self.phase {
+      Phase::Discharging => battery_states.set_charge_behaviour(ChargeBehaviour::ForceDischarge)?,
+      Phase::Charging => {
+        battery_states.set_charge_behaviour(ChargeBehaviour::Auto)?;
+        if self.stop_threshold.is_some() {
+          battery_states.set_charge_stop_threshold(100)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Moves on to charging once the target is reached. Returns whether the battery is full and
+  /// the calibration done, `finish` puts the settings back.
+  pub fn update(&mut self, battery_states: &BatteryStates) -> Result<bool, SystemStateError> {
+    let capacity = battery_states.read_battery_capacity()?;
+    match self.phase {
+      Phase::Discharging if capacity <= self.target => {
+        self.phase = Phase::Charging;
+        self.apply(battery_states)?;
+        Ok(false)
+      }
+      Phase::Discharging => Ok(false),
+      Phase::Charging => Ok(battery_states.is_full()?),
+    }
+  }
+
+  /// Back to charging normally with the thresholds from before, for a finished or aborted
+  /// calibration.
+  pub fn finish(&self, battery_states: &BatteryStates) -> Result<(), SystemStateError> {
+    battery_states.set_charge_behaviour(ChargeBehaviour::Auto)?;
+    // the start threshold wasn't touched, so lowering the stop threshold first is fine
+    if let Some(stop) = self.stop_threshold {
+      battery_states.set_charge_stop_threshold(stop)?;
+    }
+    if let Some(start) = self.start_threshold {
+      battery_states.set_charge_start_threshold(start)?;
+    }
+    Ok(())
+  }
+
+  /// Stops force discharging while powereg isn't running, the thresholds and the saved state are
+  /// left for `load` and `apply` to pick up.
+  pub fn suspend(&self, battery_states: &BatteryStates) -> Result<(), SystemStateError> {
+    Ok(battery_states.set_charge_behaviour(ChargeBehaviour::Auto)?)
+  }
+
+  /// None if no calibration is in progress.
+  pub fn load(path: &str) -> io::Result<Option<Self>> {
+    match fs::read_to_string(path) {
+      Ok(contents) => toml::from_str(&contents)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Written to a temp file first like the snapshot.
+  pub fn save(&self, path: &str) -> io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+      fs::create_dir_all(parent)?;
+    }
+
+    let contents = toml::to_string(self).map_err(io::Error::other)?;
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, contents)?;
+    fs::rename(tmp_path, path)
+  }
+
+  pub fn remove(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+      _ => Ok(()),
+    }
+  }
+}
@@ -0,0 +1,78 @@
+use crate::battery::BatteryStates;
+use crate::system_state::{SystemState, SystemStateError};
+use crate::utils::Config;
+
+/// `charge-full`: the charge thresholds lifted until the battery is full or ac is unplugged,
+/// then the configured ones are put back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargeFull {
+  /// the thresholds from before, restored if the config doesn't set any
+  pub start_threshold: Option<usize>,
+  pub stop_threshold: Option<usize>,
+}
+
+impl ChargeFull {
+  pub fn start(system_state: &SystemState) -> Result<Self, SystemStateError> {
+    let battery_states = &system_state.battery_states;
+    if battery_states.charge_capabilities().is_none() {
+      return Err(SystemStateError::UnsupportedErr(
+        "no charge threshold control detected, the battery charges to full anyway".to_string(),
+      ));
+    }
+    if !system_state.on_ac()? {
+      return Err(SystemStateError::UnsupportedErr(
+        "not on ac power".to_string(),
+      ));
+    }
+
+    Ok(Self {
+      start_threshold: battery_states.read_charge_start_threshold().ok(),
+      stop_threshold: battery_states.read_charge_stop_threshold().ok(),
+    })
+  }
+
+  /// Writes the highest thresholds, again after a config reload set its own.
+  pub fn apply(&self, battery_states: &BatteryStates) -> Result<(), SystemStateError> {
+    let Some(capabilities) = battery_states.charge_capabilities() else {
+      return Ok(());
+    };
+    let (start, stop) = capabilities.full_charge();
+
+    // raising, so the stop threshold goes first
+    if let Some(stop) = stop {
+      battery_states.set_charge_stop_threshold(stop)?;
+    }
+    if let Some(start) = start {
+      battery_states.set_charge_start_threshold(start)?;
+    }
+    Ok(())
+  }
+
+  /// Full, or ac is gone and there is nothing left to charge from.
+  pub fn is_done(&self, system_state: &SystemState) -> Result<bool, SystemStateError> {
+    Ok(!system_state.on_ac()? || system_state.battery_states.is_full()?)
+  }
+
+  /// Puts back the thresholds of `config`, or the ones from before without any.
+  pub fn finish(
+    &self,
+    system_state: &SystemState,
+    config: Option<&Config>,
+  ) -> Result<(), SystemStateError> {
+    if let Some(config) = config
+      && (config.charge_start_threshold.is_some() || config.charge_stop_threshold.is_some())
+    {
+      return config.apply_thresholds(system_state);
+    }
+
+    // lowering, so the start threshold goes first
+    let battery_states = &system_state.battery_states;
+    if let Some(start) = self.start_threshold {
+      battery_states.set_charge_start_threshold(start)?;
+    }
+    if let Some(stop) = self.stop_threshold {
+      battery_states.set_charge_stop_threshold(stop)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,265 @@
+use crate::battery::TimeEstimate;
+use crate::system_state::State;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+pub const SOCKET_PATH: &str = "/run/powereg/powereg.sock";
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One request per connection: the client writes a toml encoded `Request`, shuts down its write
+/// half and reads back a toml encoded `Response`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+  pub version: u32,
+  pub command: Command,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Command {
+  Status,
+  /// Hold a state regardless of events, for `duration_s` seconds or until `Resume`
+  ForceProfile {
+    state: State,
+    duration_s: Option<u64>,
+  },
+  /// Stop automatic control, leaving the current settings in place
+  Pause,
+  /// Resume automatic control, also ending a forced profile
+  Resume,
+  Reload,
+  /// Write back the settings from before powereg started and pause automatic control
+  Restore,
+  /// Discharge the battery to `target` percent, charge it to 100% and restore the thresholds
+  Calibrate {
+    target: usize,
+  },
+  AbortCalibration,
+  /// Lift the charge thresholds until the battery is full or ac is unplugged
+  ChargeFull,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+  pub version: u32,
+  pub error: Option<String>,
+  pub status: Option<DaemonStatus>,
+}
+
+impl Response {
+  pub fn ok() -> Self {
+    Self {
+      version: PROTOCOL_VERSION,
+      error: None,
+      status: None,
+    }
+  }
+
+  pub fn error(error: &str) -> Self {
+    Self {
+      version: PROTOCOL_VERSION,
+      error: Some(error.to_string()),
+      status: None,
+    }
+  }
+
+  pub fn status(status: DaemonStatus) -> Self {
+    Self {
+      version: PROTOCOL_VERSION,
+      error: None,
+      status: Some(status),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonStatus {
+  pub state: State,
+  pub last_event: String,
+  pub paused: bool,
+  pub forced_state: Option<State>,
+  pub forced_remaining_s: Option<u64>,
+  pub time_estimate: Option<TimeEstimate>,
+  pub calibration: Option<String>,
+  #[serde(default)]
+  pub charging_full: bool,
+  pub settings: AppliedSettings,
+}
+
+impl fmt::Display for DaemonStatus {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Daemon:
+    state: {}
+    last event: {}
+    automatic control: {}",
+      self.state,
+      self.last_event,
+      if self.paused { "paused" } else { "running" },
+    )?;
+
+    if let Some(state) = self.forced_state {
+      match self.forced_remaining_s {
+        Some(s) => write!(f, "\n    forced state: {} ({}s left)", state, s)?,
+        None => write!(f, "\n    forced state: {}", state)?,
+      }
+    }
+
+    if let Some(estimate) = self.time_estimate {
+      write!(f, "\n    battery: {}", estimate)?;
+    }
+    if self.charging_full {
+      write!(
+        f,
+        "\n    charging to full, then back to the configured thresholds"
+      )?;
+    }
+    if let Some(calibration) = &self.calibration {
+      write!(f, "\n    battery calibration: {}", calibration)?;
+    }
+
+    write!(f, "\n{}", self.settings)
+  }
+}
+
+/// What the daemon last wrote, as read back from sysfs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppliedSettings {
+  pub governor: String,
+  pub epp: String,
+  pub boost: bool,
+  pub platform_profile: String,
+  pub charge_start_threshold: Option<usize>,
+  pub charge_stop_threshold: Option<usize>,
+}
+
+impl fmt::Display for AppliedSettings {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Applied settings:
+    scaling governer: {}
+    epp: {}
+    cpu boost: {}
+    platform profile: {}",
+      self.governor, self.epp, self.boost, self.platform_profile,
+    )?;
+
+    if let (Some(start), Some(stop)) = (self.charge_start_threshold, self.charge_stop_threshold) {
+      write!(f, "\n    charge thresholds: {}-{}%", start, stop)?;
+    } else if let Some(stop) = self.charge_stop_threshold {
+      write!(f, "\n    charge stop threshold: {}%", stop)?;
+    }
+
+    Ok(())
+  }
+}
+
+fn read_message(stream: &mut UnixStream) -> io::Result<String> {
+  stream.set_read_timeout(Some(IO_TIMEOUT))?;
+  let mut contents = String::new();
+  stream.read_to_string(&mut contents)?;
+  Ok(contents)
+}
+
+fn invalid_data<E: fmt::Display>(e: E) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+pub struct ControlServer {
+  listener: UnixListener,
+  path: String,
+}
+
+impl Drop for ControlServer {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+impl ControlServer {
+  pub fn bind(path: &str) -> io::Result<Self> {
+    if let Some(parent) = Path::new(path).parent() {
+      fs::create_dir_all(parent)?;
+    }
+    // left behind by a daemon that didn't shut down cleanly
+    if Path::new(path).exists() {
+      fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(Self {
+      listener,
+      path: path.to_string(),
+    })
+  }
+
+  pub fn as_raw_fd(&self) -> RawFd {
+    self.listener.as_raw_fd()
+  }
+
+  /// Next pending connection with its decoded request, None once no client is waiting.
+  pub fn accept(&self) -> Option<(UnixStream, Result<Request, String>)> {
+    let (mut stream, _) = self.listener.accept().ok()?;
+    if stream.set_nonblocking(false).is_err() {
+      return None;
+    }
+
+    let request = read_message(&mut stream)
+      .map_err(|e| e.to_string())
+      .and_then(|contents| toml::from_str::<Request>(&contents).map_err(|e| e.to_string()))
+      .and_then(|request| {
+        if request.version == PROTOCOL_VERSION {
+          Ok(request)
+        } else {
+          Err(format!(
+            "unsupported protocol version {} (daemon speaks {})",
+            request.version, PROTOCOL_VERSION
+          ))
+        }
+      });
+
+    Some((stream, request))
+  }
+
+  pub fn respond(mut stream: UnixStream, response: &Response) -> io::Result<()> {
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let contents = toml::to_string(response).map_err(invalid_data)?;
+    stream.write_all(contents.as_bytes())?;
+    stream.flush()
+  }
+}
+
+pub fn send_command(path: &str, command: Command) -> io::Result<Response> {
+  let mut stream = UnixStream::connect(path)?;
+  stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+  let request = Request {
+    version: PROTOCOL_VERSION,
+    command,
+  };
+  let contents = toml::to_string(&request).map_err(invalid_data)?;
+  stream.write_all(contents.as_bytes())?;
+  stream.shutdown(Shutdown::Write)?;
+
+  let response: Response = toml::from_str(&read_message(&mut stream)?).map_err(invalid_data)?;
+  if let Some(error) = &response.error {
+    return Err(io::Error::other(error.clone()));
+  }
+
+  Ok(response)
+}
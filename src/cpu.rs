@@ -1,10 +1,16 @@
-use crate::utils::{PersFd, PersFdError};
+use crate::cpu_backend::{probe_cpu_backend, CpuBackend, CpuCapabilities};
+use crate::load::{CpuLoad, LoadSampler};
+use crate::powercap::{Powercap, LONG_TERM, SHORT_TERM};
+use crate::sysfs::SysfsRoot;
+use crate::thermal::{Thermal, ThermalError};
+use crate::utils::{is_transient_io_error, PersFd, PersFdError};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::num;
-use std::thread;
-use std::time::Duration;
+use std::time::Instant;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum CpuType {
@@ -13,34 +19,73 @@ pub enum CpuType {
   Unknown,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum ScalingGoverner {
   Performance,
   Powersave,
-  Unknown,
+  Schedutil,
+  Ondemand,
+  Conservative,
+  Userspace,
+  /// any other governor the kernel advertises
+  Other(String),
 }
 
 impl ScalingGoverner {
   const PERFORMANCE: &str = "performance";
   const POWERSAVE: &str = "powersave";
+  const SCHEDUTIL: &str = "schedutil";
+  const ONDEMAND: &str = "ondemand";
+  const CONSERVATIVE: &str = "conservative";
+  const USERSPACE: &str = "userspace";
 
   pub fn from_string(s: &str) -> Self {
-    match s {
+    match s.trim() {
       ScalingGoverner::PERFORMANCE => Self::Performance,
       ScalingGoverner::POWERSAVE => Self::Powersave,
-      _ => Self::Unknown,
+      ScalingGoverner::SCHEDUTIL => Self::Schedutil,
+      ScalingGoverner::ONDEMAND => Self::Ondemand,
+      ScalingGoverner::CONSERVATIVE => Self::Conservative,
+      ScalingGoverner::USERSPACE => Self::Userspace,
+      other => Self::Other(other.to_string()),
+    }
+  }
+
+  /// A space separated list like `scaling_available_governors`.
+  pub fn parse_list(s: &str) -> Vec<Self> {
+    s.split_whitespace().map(Self::from_string).collect()
+  }
+
+  pub fn as_str(&self) -> &str {
+    match self {
+      Self::Performance => ScalingGoverner::PERFORMANCE,
+      Self::Powersave => ScalingGoverner::POWERSAVE,
+      Self::Schedutil => ScalingGoverner::SCHEDUTIL,
+      Self::Ondemand => ScalingGoverner::ONDEMAND,
+      Self::Conservative => ScalingGoverner::CONSERVATIVE,
+      Self::Userspace => ScalingGoverner::USERSPACE,
+      Self::Other(governor) => governor,
     }
   }
 }
 
-#[derive(PartialEq, Debug)]
+impl fmt::Display for ScalingGoverner {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum EPP {
   EDefault,
   Performance,
   BalancePerformance,
   BalancePower,
   Power,
-  Unknown,
+  /// 0 (performance) to 255 (power), only intel_pstate accepts these
+  Raw(u8),
+  /// any other preference the kernel advertises
+  Other(String),
 }
 
 impl EPP {
@@ -51,13 +96,88 @@ impl EPP {
   const POWER: &str = "power";
 
   pub fn from_string(s: &str) -> Self {
-    match s {
+    match s.trim() {
       EPP::DEFAULT => EPP::EDefault,
       EPP::PERFORMANCE => EPP::Performance,
       EPP::BALANCE_PERFORMANCE => EPP::BalancePerformance,
       EPP::BALANCE_POWER => EPP::BalancePower,
       EPP::POWER => EPP::Power,
-      _ => EPP::Unknown,
+      other => match other.parse() {
+        Ok(raw) => EPP::Raw(raw),
+        Err(_) => EPP::Other(other.to_string()),
+      },
+    }
+  }
+
+  /// A space separated list like `energy_performance_available_preferences`.
+  pub fn parse_list(s: &str) -> Vec<Self> {
+    s.split_whitespace().map(Self::from_string).collect()
+  }
+}
+
+impl fmt::Display for EPP {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EPP::EDefault => write!(f, "{}", EPP::DEFAULT),
+      EPP::Performance => write!(f, "{}", EPP::PERFORMANCE),
+      EPP::BalancePerformance => write!(f, "{}", EPP::BALANCE_PERFORMANCE),
+      EPP::BalancePower => write!(f, "{}", EPP::BALANCE_POWER),
+      EPP::Power => write!(f, "{}", EPP::POWER),
+      EPP::Raw(raw) => write!(f, "{}", raw),
+      EPP::Other(epp) => write!(f, "{}", epp),
+    }
+  }
+}
+
+/// A setting read from every core: the same everywhere, or which core has which value. Cores
+/// disagree when something else wrote to them, after a hotplug, or on hybrid cpus whose cores
+/// have different frequency ranges.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PerCore<T> {
+  Uniform(T),
+  Mixed(BTreeMap<usize, T>),
+}
+
+impl<T: PartialEq + Clone> PerCore<T> {
+  pub fn from_cores(values: Vec<(usize, T)>) -> Self {
+    match values.first() {
+      Some((_, first)) if values.iter().all(|(_, v)| v == first) => PerCore::Uniform(first.clone()),
+      _ => PerCore::Mixed(values.into_iter().collect()),
+    }
+  }
+
+  pub fn uniform(&self) -> Option<&T> {
+    match self {
+      PerCore::Uniform(value) => Some(value),
+      PerCore::Mixed(_) => None,
+    }
+  }
+
+  /// The cores whose value isn't `desired`, `cpus` are the cores a `Uniform` value stands for.
+  pub fn drifted(&self, desired: &T, cpus: &[usize]) -> Vec<usize> {
+    match self {
+      PerCore::Uniform(value) if value == desired => vec![],
+      PerCore::Uniform(_) => cpus.to_vec(),
+      PerCore::Mixed(values) => values
+        .iter()
+        .filter(|(_, value)| *value != desired)
+        .map(|(core, _)| *core)
+        .collect(),
+    }
+  }
+}
+
+impl<T: fmt::Display> fmt::Display for PerCore<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PerCore::Uniform(value) => write!(f, "{}", value),
+      PerCore::Mixed(values) => {
+        let values: Vec<String> = values
+          .iter()
+          .map(|(core, value)| format!("cpu{}: {}", core, value))
+          .collect();
+        write!(f, "mixed ({})", values.join(", "))
+      }
     }
   }
 }
@@ -66,7 +186,7 @@ impl EPP {
 pub enum CpuStatesError {
   InvalidScalingGovVal,
   InvalidEPPVal,
-  InvalidAMDPstate,
+  Unsupported,
   UnsupportedCpuType,
   EmptyProcStat,
   InvalidProcStat,
@@ -74,6 +194,7 @@ pub enum CpuStatesError {
   PersFdErr(PersFdError),
   ParseIntErr(num::ParseIntError),
   GeneralIoErr(io::Error),
+  ThermalErr(ThermalError),
 }
 
 impl fmt::Display for CpuStatesError {
@@ -81,9 +202,7 @@ impl fmt::Display for CpuStatesError {
     match self {
       CpuStatesError::InvalidScalingGovVal => write!(f, "Unsupported scaling governer value"),
       CpuStatesError::InvalidEPPVal => write!(f, "Unsupported epp value"),
-      CpuStatesError::InvalidAMDPstate => {
-        write!(f, "AMD pstate probably isn't set to active")
-      }
+      CpuStatesError::Unsupported => write!(f, "Not supported by this cpu"),
       CpuStatesError::UnsupportedCpuType => write!(f, "Detected and unsupported cpu type"),
       CpuStatesError::EmptyProcStat => write!(f, "Empty /proc/stat"),
       CpuStatesError::InvalidProcStat => write!(f, "Invalid /proc/stat"),
@@ -91,6 +210,22 @@ impl fmt::Display for CpuStatesError {
       CpuStatesError::PersFdErr(e) => write!(f, "{e}"),
       CpuStatesError::ParseIntErr(e) => write!(f, "Failed parsing integer: {e}"),
       CpuStatesError::GeneralIoErr(e) => write!(f, "General io error: {e}"),
+      CpuStatesError::ThermalErr(e) => write!(f, "{e}"),
+    }
+  }
+}
+
+impl CpuStatesError {
+  /// A half written or momentarily missing file, reading again later may succeed.
+  pub fn is_transient(&self) -> bool {
+    match self {
+      CpuStatesError::EmptyProcStat
+      | CpuStatesError::InvalidProcStat
+      | CpuStatesError::ParseIntErr(_) => true,
+      CpuStatesError::PersFdErr(e) => e.is_transient(),
+      CpuStatesError::GeneralIoErr(e) => is_transient_io_error(e),
+      CpuStatesError::ThermalErr(e) => e.is_transient(),
+      _ => false,
     }
   }
 }
@@ -113,20 +248,122 @@ impl From<io::Error> for CpuStatesError {
   }
 }
 
+impl From<ThermalError> for CpuStatesError {
+  fn from(error: ThermalError) -> Self {
+    CpuStatesError::ThermalErr(error)
+  }
+}
+
+pub const CPUFREQ_PATH: &str = "/sys/devices/system/cpu/cpufreq";
+
+/// A cpufreq policy (`cpufreq/policyN`), the knobs shared by the cores in `cpus`.
+pub struct CpuPolicy {
+  pub id: usize,
+  /// the policy's online cores (`affected_cpus`)
+  pub cpus: Vec<usize>,
+  /// kHz, `cpuinfo_min_freq` and `cpuinfo_max_freq`
+  pub hw_min_freq: usize,
+  pub hw_max_freq: usize,
+
+  scaling_governer: RefCell<PersFd>,
+  min_cpu_freq: RefCell<PersFd>,
+  max_cpu_freq: RefCell<PersFd>,
+  cpu_freq: RefCell<PersFd>, // TODO: possibly wrong (not same as btop)
+  epp: Option<RefCell<PersFd>>,
+}
+
+impl CpuPolicy {
+  /// None while all of the policy's cores are offline, its files can't be used then.
+  fn init(
+    root: &SysfsRoot,
+    id: usize,
+    epp_supported: bool,
+  ) -> Result<Option<Self>, CpuStatesError> {
+    let dir = format!("{}/policy{}", CPUFREQ_PATH, id);
+    let cpus = parse_cpu_list(&fs::read_to_string(
+      root.path(&format!("{dir}/affected_cpus")),
+    )?);
+    if cpus.is_empty() {
+      return Ok(None);
+    }
+
+    let open = |file: &str, write: bool| -> Result<RefCell<PersFd>, CpuStatesError> {
+      Ok(RefCell::new(PersFd::new(
+        &root.path(&format!("{dir}/{file}")),
+        write,
+      )?))
+    };
+
+    let read_freq = |file: &str| -> Result<usize, CpuStatesError> {
+      Ok(
+        fs::read_to_string(root.path(&format!("{dir}/{file}")))?
+          .trim()
+          .parse()?,
+      )
+    };
+
+    Ok(Some(Self {
+      id,
+      cpus,
+      hw_min_freq: read_freq("cpuinfo_min_freq")?,
+      hw_max_freq: read_freq("cpuinfo_max_freq")?,
+      scaling_governer: open("scaling_governor", true)?,
+      min_cpu_freq: open("scaling_min_freq", true)?,
+      max_cpu_freq: open("scaling_max_freq", true)?,
+      cpu_freq: open("scaling_cur_freq", false)?,
+      epp: match epp_supported {
+        true => Some(open("energy_performance_preference", true)?),
+        false => None,
+      },
+    }))
+  }
+
+  /// Every policy with an online core, ordered by id.
+  pub fn enumerate(root: &SysfsRoot, epp_supported: bool) -> Result<Vec<Self>, CpuStatesError> {
+    let mut ids: Vec<usize> = fs::read_dir(root.path(CPUFREQ_PATH))?
+      .filter_map(|entry| {
+        let name = entry.ok()?.file_name();
+        name.to_str()?.strip_prefix("policy")?.parse().ok()
+      })
+      .collect();
+    ids.sort();
+
+    let mut policies = vec![];
+    for id in ids {
+      if let Some(policy) = Self::init(root, id, epp_supported)? {
+        policies.push(policy);
+      }
+    }
+    Ok(policies)
+  }
+}
+
+/// `affected_cpus` style lists ("0 1 2 3"), ranges ("0-3,6") are accepted too.
+pub fn parse_cpu_list(s: &str) -> Vec<usize> {
+  s.split(|c: char| c.is_whitespace() || c == ',')
+    .filter(|part| !part.is_empty())
+    .flat_map(|part| match part.split_once('-') {
+      Some((start, end)) => match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) => (start..=end).collect(),
+        _ => vec![],
+      },
+      None => part.parse().ok().into_iter().collect(),
+    })
+    .collect()
+}
+
 pub struct CpuStates {
-  cpu_core_count: usize,
+  root: SysfsRoot,
   cpu_type: CpuType,
 
-  scaling_governer: Vec<RefCell<PersFd>>,
-  min_cpu_freq: Vec<RefCell<PersFd>>,
-  max_cpu_freq: Vec<RefCell<PersFd>>,
-  cpu_freq: Vec<RefCell<PersFd>>, // TODO: possibly wrong (not same as btop)
-  cpu_temp: RefCell<PersFd>,
-  cpu_load: RefCell<PersFd>, // TODO: possibly wrong
-  cpu_boost: RefCell<PersFd>,
-  epp: Vec<RefCell<PersFd>>,
-
-  cpu_power_draw: Option<RefCell<PersFd>>, // TODO: possibly wrong
+  /// re-enumerated when cores go on or offline, see `rescan`
+  policies: RefCell<Vec<CpuPolicy>>,
+  thermal: Thermal,
+  load: LoadSampler,
+  available_governors: Vec<ScalingGoverner>,
+  available_epp: Vec<EPP>,
+  backend: Box<dyn CpuBackend>,
+  powercap: Powercap,
 }
 
 impl fmt::Display for CpuStates {
@@ -135,304 +372,426 @@ impl fmt::Display for CpuStates {
       f,
       "CPU:
     cpu type: {:?}
-    scaling governer: {:?}
-    epp: {:?}
+    cpu driver: {} ({})
+    scaling governer: {}
+    epp: {}
     cpu boost: {}
-    min/max cpu freq: {:.2}-{:.2} GHz
+    min/max cpu freq: {}-{} GHz
     cpu freq: {:.2} GHz
-    cpu temp: {}°C
+    cpu temp: {}
     cpu load: {:.2}%
     cpu power draw: {:.2} W",
       self.cpu_type,
+      self.backend.name(),
+      self.backend.read_status().unwrap_or("unknown".to_string()),
       self
         .read_scaling_governer()
-        .unwrap_or(ScalingGoverner::Unknown),
-      self.read_epp().unwrap_or(EPP::Unknown),
+        .map(|governor| governor.to_string())
+        .unwrap_or("unknown".to_string()),
+      self
+        .read_epp()
+        .map(|epp| epp.to_string())
+        .unwrap_or("unknown".to_string()),
       self.read_cpu_boost().unwrap_or(false),
-      self.read_min_cpu_freq().unwrap_or(0.0),
-      self.read_max_cpu_freq().unwrap_or(0.0),
+      format_ghz(self.read_min_cpu_freq()),
+      format_ghz(self.read_max_cpu_freq()),
       self.read_avg_cpu_freq().unwrap_or(0.0),
-      self.read_cpu_temp().unwrap_or(0),
-      self.read_cpu_load().unwrap_or(0.0),
+      match (self.read_cpu_temp(), self.thermal.cpu_sensor()) {
+        (Ok(temp), Some(sensor)) => format!("{}°C ({})", temp, sensor.name),
+        _ => "unknown".to_string(),
+      },
+      self.cpu_load().map_or(0.0, |load| load.busy),
       self.read_cpu_power_draw().unwrap_or(0.0),
-    )
+    )?;
+
+    if let Some(load) = self.cpu_load() {
+      write!(
+        f,
+        "\n    cpu load breakdown: user {:.2}%, system {:.2}%, iowait {:.2}%, steal {:.2}%",
+        load.user, load.system, load.iowait, load.steal
+      )?;
+    }
+    let core_loads = self.core_loads();
+    if !core_loads.is_empty() {
+      let loads: Vec<String> = core_loads
+        .iter()
+        .map(|(core, load)| format!("{}: {:.0}%", core, load.busy))
+        .collect();
+      write!(f, "\n    core load: {}", loads.join(", "))?;
+    }
+    if let Ok((min, max)) = self.read_perf_pct() {
+      write!(f, "\n    min/max perf pct: {}-{}%", min, max)?;
+    }
+    for zone in self.powercap.zones() {
+      match zone.watts() {
+        Some(watts) => write!(f, "\n    {} power draw: {:.2} W", zone.name, watts)?,
+        None => write!(f, "\n    {} power draw: unknown", zone.name)?,
+      }
+    }
+    for zone in self.powercap.zones().iter().filter(|z| z.is_package()) {
+      for (label, constraint) in [("PL1", LONG_TERM), ("PL2", SHORT_TERM)] {
+        if let Some(watts) = zone.constraint(constraint).and_then(|c| c.read_limit()) {
+          write!(
+            f,
+            "\n    {} power limit {}: {:.2} W",
+            zone.name, label, watts
+          )?;
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// kHz to GHz, listing the cores if they differ.
+fn format_ghz(freq: Result<PerCore<usize>, CpuStatesError>) -> String {
+  let ghz = |khz: &usize| format!("{:.2}", *khz as f32 / 1_000_000.0);
+  match freq {
+    Ok(PerCore::Uniform(khz)) => ghz(&khz),
+    Ok(PerCore::Mixed(values)) => {
+      let values: Vec<String> = values.values().map(ghz).collect();
+      format!("[{}]", values.join(" "))
+    }
+    Err(_) => ghz(&0),
   }
 }
 
 impl CpuStates {
-  pub fn init(n: usize, cpu_type: &CpuType) -> Result<Self, CpuStatesError> {
+  pub fn init(root: &SysfsRoot, cpu_type: &CpuType) -> Result<Self, CpuStatesError> {
+    let backend = probe_cpu_backend(root)?;
+    let epp_supported = backend.capabilities().epp;
+    let policies = CpuPolicy::enumerate(root, epp_supported)?;
+    let Some(first) = policies.first() else {
+      eprintln!("No cpufreq policies found!");
+      return Err(CpuStatesError::Unsupported);
+    };
+    let first_dir = format!("{}/policy{}", CPUFREQ_PATH, first.id);
+
     let mut available_asgr = PersFd::new(
-      "/sys/devices/system/cpu/cpu0/cpufreq/scaling_available_governors",
+      &root.path(&format!("{first_dir}/scaling_available_governors")),
       false,
     )?;
-    let asgr = available_asgr.read_value()?;
-
-    if !asgr.contains("performance") || !asgr.contains("powersave") {
-      eprintln!("Incorrect available scaling governor options!");
+    let available_governors = ScalingGoverner::parse_list(&available_asgr.read_value()?);
+    if available_governors.is_empty() {
+      eprintln!("No scaling governors available!");
       return Err(CpuStatesError::InvalidScalingGovVal);
     }
 
-    let mut scaling_governer: Vec<RefCell<PersFd>> = vec![];
-    let mut cpu_freq: Vec<RefCell<PersFd>> = vec![];
-    let mut max_cpu_freq: Vec<RefCell<PersFd>> = vec![];
-    let mut min_cpu_freq: Vec<RefCell<PersFd>> = vec![];
-    let mut epp: Vec<RefCell<PersFd>> = vec![];
-    for i in 0..n {
-      let scaling_gov_path = format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor", i);
-      let cpu_freq_path = format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_cur_freq", i);
-      let min_cpu_freq_path = format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_min_freq", i);
-      let max_cpu_freq_path = format!("/sys/devices/system/cpu/cpu{}/cpufreq/scaling_max_freq", i);
-
-      let amd_epp_path = format!(
-        "/sys/devices/system/cpu/cpu{}/cpufreq/energy_performance_preference",
-        i
-      );
-
-      scaling_governer.push(RefCell::new(PersFd::new(&scaling_gov_path, true)?));
-      cpu_freq.push(RefCell::new(PersFd::new(&cpu_freq_path, false)?));
-      min_cpu_freq.push(RefCell::new(PersFd::new(&min_cpu_freq_path, true)?));
-      max_cpu_freq.push(RefCell::new(PersFd::new(&max_cpu_freq_path, true)?));
-      epp.push(RefCell::new(PersFd::new(&amd_epp_path, true)?));
-    }
-
-    let mut cpu_power_draw: Option<RefCell<PersFd>> = None;
-    if *cpu_type == CpuType::AMD {
-      let mut amd_pstate = PersFd::new("/sys/devices/system/cpu/amd_pstate/status", true)?;
-      let r_amd_pstate = amd_pstate.read_value()?;
-
-      if !r_amd_pstate.contains("active") {
-        println!("amd_pstate is not active!");
-        println!("Attempting to set amd_pstate to 'active'");
-
-        if let Err(e) = amd_pstate.set_value("active") {
-          eprintln!("Failed setting amd_pstate to 'active': {:?}", e);
-          return Err(CpuStatesError::InvalidAMDPstate);
-        }
-      }
-
-      cpu_power_draw = Some(RefCell::new(PersFd::new(
-        "/sys/class/powercap/intel-rapl:0/energy_uj",
-        false,
-      )?));
-    } else if *cpu_type == CpuType::Intel {
-    } else {
-      return Err(CpuStatesError::UnsupportedCpuType);
-    }
+    // missing on some drivers, any value is tried then
+    let available_epp = match epp_supported {
+      true => fs::read_to_string(root.path(&format!(
+        "{first_dir}/energy_performance_available_preferences"
+      )))
+      .map(|prefs| EPP::parse_list(&prefs))
+      .unwrap_or_default(),
+      false => vec![],
+    };
 
     Ok(Self {
-      cpu_core_count: n,
+      root: root.clone(),
       cpu_type: cpu_type.clone(),
 
-      scaling_governer,
-      min_cpu_freq,
-      max_cpu_freq,
-      cpu_freq,
-      cpu_temp: RefCell::new(PersFd::new("/sys/class/thermal/thermal_zone0/temp", false)?),
-      cpu_load: RefCell::new(PersFd::new("/proc/stat", false)?),
-      cpu_boost: RefCell::new(PersFd::new("/sys/devices/system/cpu/cpufreq/boost", true)?),
-      epp,
-
-      cpu_power_draw,
+      policies: RefCell::new(policies),
+      thermal: Thermal::discover(root),
+      load: LoadSampler::new(PersFd::new(&root.path("/proc/stat"), false)?),
+      available_governors,
+      available_epp,
+      backend,
+      powercap: Powercap::discover(root),
     })
   }
 
-  pub fn read_scaling_governer(&self) -> Result<ScalingGoverner, CpuStatesError> {
-    let gov = ScalingGoverner::from_string(&self.scaling_governer[0].borrow_mut().read_value()?);
-    assert_ne!(
-      gov,
-      ScalingGoverner::Unknown,
-      "Scaling governer is not unknown"
-    );
+  /// Re-enumerates the policies after cores went on or offline, true if the online cores
+  /// changed. Newly onlined cores come up with kernel defaults, the current profile has to be
+  /// applied again.
+  pub fn rescan(&self) -> Result<bool, CpuStatesError> {
+    let policies = CpuPolicy::enumerate(&self.root, self.capabilities().epp)?;
+    let cpus = |policies: &[CpuPolicy]| -> Vec<usize> {
+      policies.iter().flat_map(|p| p.cpus.clone()).collect()
+    };
+
+    let changed = cpus(&policies) != cpus(&self.policies.borrow());
+    *self.policies.borrow_mut() = policies;
+    Ok(changed)
+  }
+
+  pub fn capabilities(&self) -> CpuCapabilities {
+    self.backend.capabilities()
+  }
 
-    for fd in &self.scaling_governer[1..] {
-      let val = ScalingGoverner::from_string(&fd.borrow_mut().read_value()?);
-      assert_eq!(gov, val, "Scaling governer is the same for all cpu cores");
-    }
+  /// Sorted.
+  pub fn online_cpus(&self) -> Vec<usize> {
+    let mut cpus: Vec<usize> = self
+      .policies
+      .borrow()
+      .iter()
+      .flat_map(|p| p.cpus.clone())
+      .collect();
+    cpus.sort();
+    cpus
+  }
 
-    Ok(gov)
+  pub fn driver_name(&self) -> &'static str {
+    self.backend.name()
+  }
+
+  /// Files powereg writes to in every policy plus the driver's, for `Snapshot`.
+  pub fn knob_paths(&self) -> Vec<String> {
+    let policies = self.policies.borrow();
+    let per_policy = |fd: fn(&CpuPolicy) -> Option<&RefCell<PersFd>>| {
+      policies
+        .iter()
+        .filter_map(fd)
+        .map(|fd| fd.borrow().path().to_string())
+        .collect::<Vec<_>>()
+    };
+
+    [
+      per_policy(|p| Some(&p.scaling_governer)),
+      per_policy(|p| p.epp.as_ref()),
+      per_policy(|p| Some(&p.max_cpu_freq)),
+      per_policy(|p| Some(&p.min_cpu_freq)),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(self.backend.knob_paths())
+    .chain(self.powercap.knob_paths())
+    .collect()
+  }
+
+  pub fn read_scaling_governer(&self) -> Result<PerCore<ScalingGoverner>, CpuStatesError> {
+    self.read_per_core(
+      |p| Some(&p.scaling_governer),
+      |v| Ok::<_, CpuStatesError>(ScalingGoverner::from_string(v)),
+    )
   }
 
   pub fn set_scaling_governer(
     &self,
     scaling_governer: ScalingGoverner,
   ) -> Result<(), CpuStatesError> {
-    let write = match scaling_governer {
-      ScalingGoverner::Powersave => ScalingGoverner::POWERSAVE,
-      ScalingGoverner::Performance => ScalingGoverner::PERFORMANCE,
-      _ => return Err(CpuStatesError::InvalidScalingGovVal),
-    };
+    self.check_governor(&scaling_governer)?;
 
-    for fd in &self.scaling_governer {
-      fd.borrow_mut().set_value(write)?;
+    for policy in self.policies.borrow().iter() {
+      policy
+        .scaling_governer
+        .borrow_mut()
+        .set_value(scaling_governer.as_str())?;
     }
 
     Ok(())
   }
 
-  pub fn read_epp(&self) -> Result<EPP, CpuStatesError> {
-    let gov = EPP::from_string(&self.epp[0].borrow_mut().read_value()?);
-    assert_ne!(gov, EPP::Unknown, "EPP is not unknown");
-
-    for fd in &self.epp[1..] {
-      let val = EPP::from_string(&fd.borrow_mut().read_value()?);
-      assert_eq!(gov, val, "EPP is the same for all cpu cores");
-    }
-    Ok(gov)
+  /// From `scaling_available_governors`.
+  pub fn available_governors(&self) -> &[ScalingGoverner] {
+    &self.available_governors
   }
 
-  pub fn set_epp(&self, epp: EPP) -> Result<(), CpuStatesError> {
-    let write = match epp {
-      EPP::EDefault => EPP::DEFAULT,
-      EPP::Performance => EPP::PERFORMANCE,
-      EPP::BalancePerformance => EPP::BALANCE_PERFORMANCE,
-      EPP::BalancePower => EPP::BALANCE_POWER,
-      EPP::Power => EPP::POWER,
-      _ => return Err(CpuStatesError::InvalidEPPVal),
-    };
+  /// From `energy_performance_available_preferences`, empty if the driver doesn't list them.
+  pub fn available_epp(&self) -> &[EPP] {
+    &self.available_epp
+  }
 
-    for fd in &self.epp {
-      fd.borrow_mut().set_value(write)?;
+  pub fn check_governor(&self, scaling_governer: &ScalingGoverner) -> Result<(), CpuStatesError> {
+    if !self.available_governors.contains(scaling_governer) {
+      return Err(CpuStatesError::InvalidScalingGovVal);
     }
-
     Ok(())
   }
 
-  pub fn read_cpu_boost(&self) -> Result<bool, CpuStatesError> {
-    let val = self.cpu_boost.borrow_mut().read_value()?.parse::<u8>()?;
-    Ok(val == 1)
-  }
-
-  pub fn set_cpu_boost(&self, cpu_boost: bool) -> Result<(), CpuStatesError> {
-    let val_str = (cpu_boost as u8).to_string();
-    self.cpu_boost.borrow_mut().set_value(&val_str)?;
+  /// Raw values are left to the kernel, they aren't listed as available.
+  pub fn check_epp(&self, epp: &EPP) -> Result<(), CpuStatesError> {
+    if !self.capabilities().epp {
+      return Err(CpuStatesError::Unsupported);
+    }
+    let listed = self.available_epp.is_empty() || self.available_epp.contains(epp);
+    if !listed && !matches!(epp, EPP::Raw(_)) {
+      return Err(CpuStatesError::InvalidEPPVal);
+    }
     Ok(())
   }
 
-  /// GHz
-  pub fn read_avg_cpu_freq(&self) -> Result<f32, CpuStatesError> {
-    let mut total: usize = 0;
-
-    for fd in &self.cpu_freq {
-      let val: String = fd.borrow_mut().read_value()?;
-      total += val.parse::<usize>()?;
+  pub fn read_epp(&self) -> Result<PerCore<EPP>, CpuStatesError> {
+    if !self.capabilities().epp {
+      return Err(CpuStatesError::Unsupported);
     }
-
-    Ok(((total / self.cpu_core_count) as f32) / 1_000_000.0)
+    self.read_per_core(
+      |p| p.epp.as_ref(),
+      |v| Ok::<_, CpuStatesError>(EPP::from_string(v)),
+    )
   }
 
-  /// GHz
-  pub fn read_min_cpu_freq(&self) -> Result<f32, CpuStatesError> {
-    let prev: usize = self.min_cpu_freq[0].borrow_mut().read_value()?.parse()?;
+  pub fn set_epp(&self, epp: EPP) -> Result<(), CpuStatesError> {
+    if !self.capabilities().epp {
+      return Ok(());
+    }
+    self.check_epp(&epp)?;
 
-    for fd in &self.min_cpu_freq[1..] {
-      let val = fd.borrow_mut().read_value()?.clone().parse()?;
-      assert_eq!(prev, val, "min_cpu_freq is the same for all cpu cores");
+    let write = epp.to_string();
+    for fd in self.policies.borrow().iter().filter_map(|p| p.epp.as_ref()) {
+      fd.borrow_mut().set_value(&write)?;
     }
 
-    Ok((prev as f32) / 1_000_000.0)
+    Ok(())
   }
 
-  /// GHz
-  pub fn read_max_cpu_freq(&self) -> Result<f32, CpuStatesError> {
-    let prev: usize = self.max_cpu_freq[0].borrow_mut().read_value()?.parse()?;
+  pub fn read_cpu_boost(&self) -> Result<bool, CpuStatesError> {
+    self.backend.read_boost()
+  }
 
-    for fd in &self.max_cpu_freq[1..] {
-      let val: usize = fd.borrow_mut().read_value()?.clone().parse()?;
-      assert_eq!(prev, val, "max_cpu_freq is the same for all cpu cores");
+  pub fn set_cpu_boost(&self, cpu_boost: bool) -> Result<(), CpuStatesError> {
+    if !self.capabilities().boost {
+      return Ok(());
     }
+    self.backend.set_boost(cpu_boost)
+  }
 
-    Ok((prev as f32) / 1_000_000.0)
+  /// (min, max) in percent of the maximum supported performance
+  pub fn read_perf_pct(&self) -> Result<(usize, usize), CpuStatesError> {
+    self.backend.read_perf_pct()
   }
 
-  /// celcius
-  pub fn read_cpu_temp(&self) -> Result<usize, CpuStatesError> {
-    let temp: usize = self.cpu_temp.borrow_mut().read_value()?.parse()?;
-    Ok(temp / 1000)
+  pub fn set_min_perf_pct(&self, pct: usize) -> Result<(), CpuStatesError> {
+    self.backend.set_min_perf_pct(pct)
   }
 
-  // TODO: a better way to do this?
-  pub fn read_cpu_load(&self) -> Result<f64, CpuStatesError> {
-    let proc_stat = self.cpu_load.borrow_mut().read_value()?;
-    let line = proc_stat
-      .lines()
-      .next()
-      .ok_or(CpuStatesError::EmptyProcStat)?;
+  pub fn set_max_perf_pct(&self, pct: usize) -> Result<(), CpuStatesError> {
+    self.backend.set_max_perf_pct(pct)
+  }
 
-    let prev: Vec<u64> = line
-      .split_whitespace()
-      .skip(1)
-      .map(|s| s.parse::<u64>().map_err(|e| CpuStatesError::ParseIntErr(e)))
-      .collect::<Result<Vec<_>, _>>()?;
+  /// kHz, limits left as None aren't touched. The kernel rejects a min above the current max (and
+  /// the other way round), so the writes are ordered per core.
+  pub fn set_cpu_freq_limits(
+    &self,
+    min: Option<usize>,
+    max: Option<usize>,
+  ) -> Result<(), CpuStatesError> {
+    for policy in self.policies.borrow().iter() {
+      let (min_fd, max_fd) = (&policy.min_cpu_freq, &policy.max_cpu_freq);
+      let current_max: usize = max_fd.borrow_mut().read_value()?.parse()?;
+      let max_first = min.is_some_and(|min| min > current_max);
 
-    if prev.len() < 4 {
-      return Err(CpuStatesError::InvalidProcStat);
-    }
-    if prev.len() > 10 {
-      return Err(CpuStatesError::GenericError("to many fields".to_string()));
+      if max_first && let Some(max) = max {
+        max_fd.borrow_mut().set_value(&max.to_string())?;
+      }
+      if let Some(min) = min {
+        min_fd.borrow_mut().set_value(&min.to_string())?;
+      }
+      if !max_first && let Some(max) = max {
+        max_fd.borrow_mut().set_value(&max.to_string())?;
+      }
     }
 
-    let prev_total: u64 = prev.iter().sum();
-    let prev_idle = prev[3] + prev.get(4).unwrap_or(&0);
+    Ok(())
+  }
 
-    thread::sleep(Duration::from_millis(200));
+  /// Caps every policy's max frequency at `pct` percent of its hardware range, never above
+  /// `limit` (kHz). None lifts the cap back to `limit` or the hardware maximum.
+  pub fn cap_max_freq(&self, pct: Option<u8>, limit: Option<usize>) -> Result<(), CpuStatesError> {
+    for policy in self.policies.borrow().iter() {
+      let range = policy.hw_max_freq.saturating_sub(policy.hw_min_freq);
+      let cap = match pct {
+        Some(pct) => policy.hw_min_freq + range * usize::from(pct) / 100,
+        None => policy.hw_max_freq,
+      };
+      let current_min: usize = policy.min_cpu_freq.borrow_mut().read_value()?.parse()?;
+      let max = cap.min(limit.unwrap_or(cap)).max(current_min);
+      policy
+        .max_cpu_freq
+        .borrow_mut()
+        .set_value(&max.to_string())?;
+    }
 
-    let proc_stat2 = self.cpu_load.borrow_mut().read_value()?;
-    let line2 = proc_stat2
-      .lines()
-      .next()
-      .ok_or(CpuStatesError::EmptyProcStat)?;
+    Ok(())
+  }
 
-    let now: Vec<u64> = line2
-      .split_whitespace()
-      .skip(1)
-      .map(|s| s.parse::<u64>().map_err(|e| CpuStatesError::ParseIntErr(e)))
-      .collect::<Result<Vec<_>, _>>()?;
+  /// GHz
+  pub fn read_avg_cpu_freq(&self) -> Result<f32, CpuStatesError> {
+    let mut total: usize = 0;
+    let mut cores: usize = 0;
 
-    if now.len() < 4 {
-      return Err(CpuStatesError::InvalidProcStat);
-    }
-    if now.len() > 10 {
-      return Err(CpuStatesError::GenericError("to many fields".to_string()));
+    for policy in self.policies.borrow().iter() {
+      let val: String = policy.cpu_freq.borrow_mut().read_value()?;
+      total += val.parse::<usize>()? * policy.cpus.len();
+      cores += policy.cpus.len();
     }
 
-    let now_total: u64 = now.iter().sum();
-    let now_idle = now[3] + now.get(4).unwrap_or(&0);
+    Ok(((total / cores.max(1)) as f32) / 1_000_000.0)
+  }
 
-    let total_delta = (now_total as i64 - prev_total as i64).max(1);
-    let idle_delta = now_idle as i64 - prev_idle as i64;
+  /// kHz
+  pub fn read_min_cpu_freq(&self) -> Result<PerCore<usize>, CpuStatesError> {
+    self.read_per_core(|p| Some(&p.min_cpu_freq), |v| v.parse())
+  }
 
-    let busy_delta = (total_delta - idle_delta).max(0);
-    let load_percent = (busy_delta as f64 / total_delta as f64) * 100.0;
+  /// kHz
+  pub fn read_max_cpu_freq(&self) -> Result<PerCore<usize>, CpuStatesError> {
+    self.read_per_core(|p| Some(&p.max_cpu_freq), |v| v.parse())
+  }
 
-    Ok(load_percent)
+  /// Reads `fd` of every policy, the value applies to each of the policy's cores.
+  fn read_per_core<T: PartialEq + Clone, E: Into<CpuStatesError>>(
+    &self,
+    fd: impl Fn(&CpuPolicy) -> Option<&RefCell<PersFd>>,
+    parse: impl Fn(&str) -> Result<T, E>,
+  ) -> Result<PerCore<T>, CpuStatesError> {
+    let mut values = vec![];
+    for policy in self.policies.borrow().iter() {
+      let Some(fd) = fd(policy) else {
+        continue;
+      };
+      let value = parse(&fd.borrow_mut().read_value()?).map_err(Into::into)?;
+      values.extend(policy.cpus.iter().map(|cpu| (*cpu, value.clone())));
+    }
+    Ok(PerCore::from_cores(values))
   }
 
-  pub fn read_cpu_power_draw(&self) -> Result<f32, CpuStatesError> {
-    if let Some(power_draw) = &self.cpu_power_draw {
-      let start: u64 = power_draw.borrow_mut().read_value()?.trim().parse()?;
-      let start_time = std::time::Instant::now();
+  /// celcius
+  pub fn read_cpu_temp(&self) -> Result<usize, CpuStatesError> {
+    Ok(self.thermal.read_cpu_temp()?)
+  }
 
-      std::thread::sleep(std::time::Duration::from_millis(500));
+  pub fn thermal(&self) -> &Thermal {
+    &self.thermal
+  }
 
-      let end: u64 = power_draw.borrow_mut().read_value()?.trim().parse()?;
-      let end_time = std::time::Instant::now();
+  /// Between the last two `sample` calls, None until there were two.
+  pub fn cpu_load(&self) -> Option<CpuLoad> {
+    self.load.load()
+  }
 
-      if end < start {
-        return Ok(0.0);
-      }
+  pub fn core_loads(&self) -> BTreeMap<usize, CpuLoad> {
+    self.load.core_loads()
+  }
 
-      let energy_delta_uj = end - start;
-      let time_delta_ms = end_time.duration_since(start_time).as_secs_f32() * 1000.0;
+  /// Package power between the last two `sample` calls, 0 until there were two.
+  pub fn read_cpu_power_draw(&self) -> Result<f32, CpuStatesError> {
+    Ok(self.powercap.package_watts().unwrap_or(0.0) as f32)
+  }
 
-      if time_delta_ms <= 0.0 {
-        return Ok(0.0);
-      }
+  /// Watts, limits left as None aren't touched. Packages without RAPL power limits are skipped.
+  pub fn set_power_limits(
+    &self,
+    package: Option<u32>,
+    burst: Option<u32>,
+  ) -> Result<(), CpuStatesError> {
+    if let Some(watts) = package {
+      self.powercap.set_power_limit(LONG_TERM, watts)?;
+    }
+    if let Some(watts) = burst {
+      self.powercap.set_power_limit(SHORT_TERM, watts)?;
+    }
+    Ok(())
+  }
 
-      let watts = (energy_delta_uj as f32 / time_delta_ms) / 1000.0;
+  /// Takes the readings that are computed between two calls, the cpu load and the power draw.
+  pub fn sample(&self, now: Instant) -> Result<(), CpuStatesError> {
+    self.powercap.sample(now);
+    self.load.sample()
+  }
 
-      Ok(watts)
-    } else {
-      Ok(0.0)
-    }
+  pub fn powercap(&self) -> &Powercap {
+    &self.powercap
   }
 }
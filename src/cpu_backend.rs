@@ -0,0 +1,299 @@
+use crate::cpu::CpuStatesError;
+use crate::sysfs::SysfsRoot;
+use crate::utils::PersFd;
+use std::cell::RefCell;
+
+/// What a cpufreq driver lets powereg control, so callers don't have to check vendors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuCapabilities {
+  pub epp: bool,
+  pub boost: bool,
+  pub perf_pct: bool,
+}
+
+pub trait CpuBackend {
+  fn name(&self) -> &'static str;
+  fn capabilities(&self) -> CpuCapabilities;
+
+  /// Driver operation mode (e.g. active/passive/guided), if the driver has one.
+  fn read_status(&self) -> Result<String, CpuStatesError> {
+    Err(CpuStatesError::Unsupported)
+  }
+
+  fn read_boost(&self) -> Result<bool, CpuStatesError>;
+  fn set_boost(&self, boost: bool) -> Result<(), CpuStatesError>;
+
+  /// Files the backend writes to, for `Snapshot`.
+  fn knob_paths(&self) -> Vec<String>;
+
+  /// (min, max) in percent of the maximum supported performance
+  fn read_perf_pct(&self) -> Result<(usize, usize), CpuStatesError> {
+    Err(CpuStatesError::Unsupported)
+  }
+
+  fn set_min_perf_pct(&self, _pct: usize) -> Result<(), CpuStatesError> {
+    Err(CpuStatesError::Unsupported)
+  }
+
+  fn set_max_perf_pct(&self, _pct: usize) -> Result<(), CpuStatesError> {
+    Err(CpuStatesError::Unsupported)
+  }
+}
+
+const CPUFREQ_BOOST: &str = "/sys/devices/system/cpu/cpufreq/boost";
+
+/// Whether a pstate driver is in active mode, the only one with epp. Passive and guided mode are
+/// left as they are, the mode is the user's choice and powereg wouldn't put it back on exit.
+fn pstate_active(status: &mut PersFd, driver: &str) -> Result<bool, CpuStatesError> {
+  let mode = status.read_value()?;
+  if mode == "active" {
+    return Ok(true);
+  }
+
+  println!("{} is in '{}' mode, epp is not available", driver, mode);
+  Ok(false)
+}
+
+pub struct AmdPstate {
+  status: RefCell<PersFd>,
+  boost: RefCell<PersFd>,
+  active: bool,
+}
+
+impl AmdPstate {
+  const STATUS: &str = "/sys/devices/system/cpu/amd_pstate/status";
+
+  pub fn probe(root: &SysfsRoot) -> Option<Result<Self, CpuStatesError>> {
+    if !root.exists(Self::STATUS) {
+      return None;
+    }
+
+    Self::init(root).transpose()
+  }
+
+  /// None while the driver is disabled, acpi-cpufreq is in use then.
+  fn init(root: &SysfsRoot) -> Result<Option<Self>, CpuStatesError> {
+    let mut status = PersFd::new(&root.path(Self::STATUS), false)?;
+    if status.read_value()? == "disable" {
+      return Ok(None);
+    }
+    let active = pstate_active(&mut status, "amd_pstate")?;
+
+    Ok(Some(Self {
+      status: RefCell::new(status),
+      boost: RefCell::new(PersFd::new(&root.path(CPUFREQ_BOOST), true)?),
+      active,
+    }))
+  }
+}
+
+impl CpuBackend for AmdPstate {
+  fn name(&self) -> &'static str {
+    "amd_pstate"
+  }
+
+  fn capabilities(&self) -> CpuCapabilities {
+    CpuCapabilities {
+      epp: self.active,
+      boost: true,
+      perf_pct: false,
+    }
+  }
+
+  fn read_status(&self) -> Result<String, CpuStatesError> {
+    Ok(self.status.borrow_mut().read_value()?)
+  }
+
+  fn read_boost(&self) -> Result<bool, CpuStatesError> {
+    Ok(self.boost.borrow_mut().read_value()?.parse::<u8>()? == 1)
+  }
+
+  fn set_boost(&self, boost: bool) -> Result<(), CpuStatesError> {
+    Ok(
+      self
+        .boost
+        .borrow_mut()
+        .set_value(&(boost as u8).to_string())?,
+    )
+  }
+
+  fn knob_paths(&self) -> Vec<String> {
+    vec![self.boost.borrow().path().to_string()]
+  }
+}
+
+pub struct IntelPstate {
+  status: RefCell<PersFd>,
+  no_turbo: RefCell<PersFd>,
+  min_perf_pct: RefCell<PersFd>,
+  max_perf_pct: RefCell<PersFd>,
+  active: bool,
+}
+
+impl IntelPstate {
+  const STATUS: &str = "/sys/devices/system/cpu/intel_pstate/status";
+  const NO_TURBO: &str = "/sys/devices/system/cpu/intel_pstate/no_turbo";
+  const MIN_PERF_PCT: &str = "/sys/devices/system/cpu/intel_pstate/min_perf_pct";
+  const MAX_PERF_PCT: &str = "/sys/devices/system/cpu/intel_pstate/max_perf_pct";
+
+  pub fn probe(root: &SysfsRoot) -> Option<Result<Self, CpuStatesError>> {
+    if !root.exists(Self::STATUS) {
+      return None;
+    }
+
+    Self::init(root).transpose()
+  }
+
+  /// None while the driver is off, acpi-cpufreq is in use then.
+  fn init(root: &SysfsRoot) -> Result<Option<Self>, CpuStatesError> {
+    let mut status = PersFd::new(&root.path(Self::STATUS), false)?;
+    if status.read_value()? == "off" {
+      return Ok(None);
+    }
+    // passive mode (intel_cpufreq) still works, only without epp
+    let active = pstate_active(&mut status, "intel_pstate")?;
+
+    Ok(Some(Self {
+      status: RefCell::new(status),
+      no_turbo: RefCell::new(PersFd::new(&root.path(Self::NO_TURBO), true)?),
+      min_perf_pct: RefCell::new(PersFd::new(&root.path(Self::MIN_PERF_PCT), true)?),
+      max_perf_pct: RefCell::new(PersFd::new(&root.path(Self::MAX_PERF_PCT), true)?),
+      active,
+    }))
+  }
+}
+
+impl CpuBackend for IntelPstate {
+  fn name(&self) -> &'static str {
+    "intel_pstate"
+  }
+
+  fn capabilities(&self) -> CpuCapabilities {
+    CpuCapabilities {
+      epp: self.active,
+      boost: true,
+      perf_pct: true,
+    }
+  }
+
+  fn read_status(&self) -> Result<String, CpuStatesError> {
+    Ok(self.status.borrow_mut().read_value()?)
+  }
+
+  fn read_boost(&self) -> Result<bool, CpuStatesError> {
+    Ok(self.no_turbo.borrow_mut().read_value()?.parse::<u8>()? == 0)
+  }
+
+  fn set_boost(&self, boost: bool) -> Result<(), CpuStatesError> {
+    Ok(
+      self
+        .no_turbo
+        .borrow_mut()
+        .set_value(&(!boost as u8).to_string())?,
+    )
+  }
+
+  fn read_perf_pct(&self) -> Result<(usize, usize), CpuStatesError> {
+    Ok((
+      self.min_perf_pct.borrow_mut().read_value()?.parse()?,
+      self.max_perf_pct.borrow_mut().read_value()?.parse()?,
+    ))
+  }
+
+  fn set_min_perf_pct(&self, pct: usize) -> Result<(), CpuStatesError> {
+    Ok(
+      self
+        .min_perf_pct
+        .borrow_mut()
+        .set_value(&pct.min(100).to_string())?,
+    )
+  }
+
+  fn set_max_perf_pct(&self, pct: usize) -> Result<(), CpuStatesError> {
+    Ok(
+      self
+        .max_perf_pct
+        .borrow_mut()
+        .set_value(&pct.min(100).to_string())?,
+    )
+  }
+
+  fn knob_paths(&self) -> Vec<String> {
+    [&self.no_turbo, &self.min_perf_pct, &self.max_perf_pct]
+      .iter()
+      .map(|fd| fd.borrow().path().to_string())
+      .collect()
+  }
+}
+
+/// Plain cpufreq drivers (acpi-cpufreq and friends): governors only, boost if the platform has it.
+pub struct AcpiCpufreq {
+  boost: Option<RefCell<PersFd>>,
+}
+
+impl AcpiCpufreq {
+  const CPUFREQ: &str = "/sys/devices/system/cpu/cpu0/cpufreq";
+
+  pub fn probe(root: &SysfsRoot) -> Option<Result<Self, CpuStatesError>> {
+    if !root.exists(Self::CPUFREQ) {
+      return None;
+    }
+
+    Some(Ok(Self {
+      boost: PersFd::new(&root.path(CPUFREQ_BOOST), true)
+        .ok()
+        .map(RefCell::new),
+    }))
+  }
+}
+
+impl CpuBackend for AcpiCpufreq {
+  fn name(&self) -> &'static str {
+    "acpi-cpufreq"
+  }
+
+  fn capabilities(&self) -> CpuCapabilities {
+    CpuCapabilities {
+      epp: false,
+      boost: self.boost.is_some(),
+      perf_pct: false,
+    }
+  }
+
+  fn read_boost(&self) -> Result<bool, CpuStatesError> {
+    match &self.boost {
+      Some(fd) => Ok(fd.borrow_mut().read_value()?.parse::<u8>()? == 1),
+      None => Err(CpuStatesError::Unsupported),
+    }
+  }
+
+  fn set_boost(&self, boost: bool) -> Result<(), CpuStatesError> {
+    match &self.boost {
+      Some(fd) => Ok(fd.borrow_mut().set_value(&(boost as u8).to_string())?),
+      None => Err(CpuStatesError::Unsupported),
+    }
+  }
+
+  fn knob_paths(&self) -> Vec<String> {
+    self
+      .boost
+      .iter()
+      .map(|fd| fd.borrow().path().to_string())
+      .collect()
+  }
+}
+
+/// Picks the most capable backend the running kernel exposes.
+pub fn probe_cpu_backend(root: &SysfsRoot) -> Result<Box<dyn CpuBackend>, CpuStatesError> {
+  if let Some(backend) = AmdPstate::probe(root) {
+    return Ok(Box::new(backend?));
+  }
+  if let Some(backend) = IntelPstate::probe(root) {
+    return Ok(Box::new(backend?));
+  }
+  if let Some(backend) = AcpiCpufreq::probe(root) {
+    return Ok(Box::new(backend?));
+  }
+
+  Err(CpuStatesError::UnsupportedCpuType)
+}
@@ -0,0 +1,684 @@
+use crate::battery::ChargeBehaviour;
+use crate::calibration::Calibration;
+use crate::charge_full::ChargeFull;
+use crate::control::{Command, ControlServer, DaemonStatus, Response, SOCKET_PATH};
+use crate::dbus::Connection;
+use crate::events::{Event, EventPoller};
+use crate::power_profiles::{profile_name, PowerProfilesService, ProfileRequest, BALANCED};
+use crate::reload::ConfigWatcher;
+use crate::snapshot::restore_snapshot;
+use crate::system_state::{State, SystemState, SystemStateError};
+use crate::utils::{Config, StyledString};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// Retries transient errors with an exponential delay, `next_delay` gives up after
+/// `MAX_RETRIES` failures in a row.
+#[derive(Debug, Default)]
+pub struct Backoff {
+  failures: u32,
+}
+
+impl Backoff {
+  pub const MAX_RETRIES: u32 = 8;
+  const MAX_DELAY: Duration = Duration::from_secs(30);
+
+  pub fn next_delay(&mut self) -> Option<Duration> {
+    if self.failures >= Self::MAX_RETRIES {
+      return None;
+    }
+    let delay = Duration::from_secs(1 << self.failures).min(Self::MAX_DELAY);
+    self.failures += 1;
+    Some(delay)
+  }
+
+  pub fn reset(&mut self) {
+    self.failures = 0;
+  }
+}
+
+/// The automatic control loop of `--daemon` and `--live`, plus the control socket serving
+/// `--monitor` and the other client commands, and the power-profiles-daemon D-Bus API serving
+/// desktop power profile switchers.
+pub struct Daemon<'a> {
+  system_state: &'a SystemState,
+  poller: EventPoller,
+  control: Option<ControlServer>,
+  power_profiles: Option<PowerProfilesService>,
+  config_watcher: ConfigWatcher,
+  config_path: String,
+  backoff: Backoff,
+  live: bool,
+
+  /// the last config that was applied, reloads only apply what changed since
+  config: Option<Config>,
+
+  paused: bool,
+  forced: Option<(State, Option<Instant>)>,
+  calibration: Option<Calibration>,
+  charge_full: Option<ChargeFull>,
+  last_event: Event,
+  /// only logged when it changes, a drift that isn't reconverged would show up every check
+  last_drift: Vec<String>,
+}
+
+impl<'a> Daemon<'a> {
+  pub fn new(
+    system_state: &'a SystemState,
+    config_path: &str,
+    config: Option<Config>,
+    live: bool,
+  ) -> Result<Self, SystemStateError> {
+    system_state.post_init()?;
+    // the baseline for the first periodic check's load and power readings
+    system_state.cpu_states.sample(Instant::now())?;
+
+    let mut poller = EventPoller::new(system_state.policy.borrow().loop_duration_s)?;
+    let control = match ControlServer::bind(&system_state.root.path(SOCKET_PATH)) {
+      Ok(server) => {
+        poller.watch_fd(server.as_raw_fd(), Event::ControlRequest);
+        Some(server)
+      }
+      Err(e) => {
+        eprintln!("{} {}", "Failed to open control socket:".red(), e);
+        None
+      }
+    };
+
+    // a simulated sysfs tree mustn't answer for the real machine on the system bus
+    let power_profiles = if system_state.root.is_system() {
+      match Connection::connect_system().and_then(|connection| {
+        PowerProfilesService::start(
+          connection,
+          system_state.cpu_states.driver_name(),
+          // what power-profiles-daemon reports without a platform driver
+          match system_state.battery_states.has_platform_profile() {
+            true => "platform_profile",
+            false => "placeholder",
+          },
+        )
+      }) {
+        Ok(service) => {
+          poller.watch_fd(service.as_raw_fd(), Event::PowerProfilesRequest);
+          Some(service)
+        }
+        Err(e) => {
+          eprintln!(
+            "{} {}",
+            "Failed to export power profiles on D-Bus:".red(),
+            e
+          );
+          None
+        }
+      }
+    } else {
+      None
+    };
+
+    let config_watcher = ConfigWatcher::new(config_path);
+    if let Some(fd) = config_watcher.raw_fd() {
+      poller.watch_fd(fd, Event::ConfigChanged);
+    }
+
+    // without it SIGTERM would kill the daemon before the original settings are restored
+    if let Err(e) = poller.handle_signals() {
+      eprintln!("{} {}", "Failed to handle signals:".red(), e);
+    }
+
+    let calibration = Self::resume_calibration(system_state);
+
+    Ok(Self {
+      system_state,
+      poller,
+      control,
+      power_profiles,
+      config_watcher,
+      config_path: config_path.to_string(),
+      backoff: Backoff::default(),
+      live,
+
+      config,
+
+      paused: false,
+      forced: None,
+      calibration,
+      charge_full: None,
+      last_event: Event::PeriodicCheck,
+      last_drift: vec![],
+    })
+  }
+
+  /// Runs until SIGTERM/SIGINT (`Ok`) or an error that retrying didn't fix (`Err`), restoring
+  /// the original settings either way.
+  pub fn run(&mut self) -> Result<(), SystemStateError> {
+    loop {
+      if self.live {
+        print!("\x1B[2J\x1B[1;1H");
+        println!("{}\n{}", self.system_state, self.status());
+      }
+
+      let event = self.poller.poll_events();
+      if let Event::Shutdown(signal) = event {
+        println!("Received {}, shutting down", signal);
+        self.restore_original_settings();
+        return Ok(());
+      }
+
+      match self.handle(event) {
+        Ok(_) => self.backoff.reset(),
+        Err(e) if e.is_transient() => match self.backoff.next_delay() {
+          Some(delay) => {
+            eprintln!(
+              "{} {}, retrying in {}s",
+              "Transient error:".yellow(),
+              e,
+              delay.as_secs()
+            );
+            self.poller.retry_in(delay);
+          }
+          None => {
+            eprintln!(
+              "{} {}, giving up after {} retries",
+              "Shutting down:".red(),
+              e,
+              Backoff::MAX_RETRIES
+            );
+            self.restore_original_settings();
+            return Err(e);
+          }
+        },
+        Err(e) => {
+          eprintln!("{} {}", "Shutting down on fatal error:".red(), e);
+          self.restore_original_settings();
+          return Err(e);
+        }
+      }
+      self.notify_power_profiles();
+    }
+  }
+
+  /// A calibration left behind by the last run, an unreadable one or one that can't be applied
+  /// anymore is aborted.
+  fn resume_calibration(system_state: &SystemState) -> Option<Calibration> {
+    let path = system_state.calibration_path();
+    let battery_states = &system_state.battery_states;
+    let calibration = match Calibration::load(&path) {
+      Ok(calibration) => calibration?,
+      Err(e) => {
+        eprintln!(
+          "{} {}",
+          "Aborting battery calibration, unreadable state:".red(),
+          e
+        );
+        // nothing to restore the thresholds to, at least stop discharging
+        let _ = battery_states.set_charge_behaviour(ChargeBehaviour::Auto);
+        let _ = Calibration::remove(&path);
+        return None;
+      }
+    };
+
+    println!("Resuming battery calibration, {}", calibration);
+    if let Err(e) = calibration.apply(battery_states) {
+      eprintln!("{} {}", "Aborting battery calibration:".red(), e);
+      if let Err(e) = calibration.finish(battery_states) {
+        eprintln!("{} {}", "Failed to restore the thresholds:".red(), e);
+      }
+      let _ = Calibration::remove(&path);
+      return None;
+    }
+    Some(calibration)
+  }
+
+  /// Advances the calibration, errors are only logged so the next check tries again.
+  fn update_calibration(&mut self) {
+    let Some(calibration) = &mut self.calibration else {
+      return;
+    };
+    let battery_states = &self.system_state.battery_states;
+    let phase = calibration.phase;
+
+    match calibration.update(battery_states) {
+      Ok(true) => {
+        println!("Battery calibration done, restoring the charge thresholds");
+        self.stop_calibration();
+      }
+      Ok(false) if calibration.phase != phase => {
+        println!("Battery calibration: {}", calibration);
+        if let Err(e) = calibration.save(&self.system_state.calibration_path()) {
+          eprintln!("{} {}", "Failed to save the calibration state:".red(), e);
+        }
+      }
+      Ok(false) => {}
+      Err(e) => eprintln!("{} {}", "Battery calibration:".yellow(), e),
+    }
+  }
+
+  fn update_charge_full(&mut self) {
+    let Some(charge_full) = &self.charge_full else {
+      return;
+    };
+
+    match charge_full.is_done(self.system_state) {
+      Ok(true) => {
+        println!("Done charging to full, re-applying the charge thresholds");
+        if let Err(e) = charge_full.finish(self.system_state, self.config.as_ref()) {
+          eprintln!(
+            "{} {}",
+            "Failed to re-apply the charge thresholds:".red(),
+            e
+          );
+        }
+        self.charge_full = None;
+      }
+      Ok(false) => {}
+      Err(e) => eprintln!("{} {}", "Charging to full:".yellow(), e),
+    }
+  }
+
+  /// Finishes or aborts the calibration, restoring the thresholds from before it.
+  fn stop_calibration(&mut self) -> Option<SystemStateError> {
+    let calibration = self.calibration.take()?;
+    let result = calibration.finish(&self.system_state.battery_states);
+    if let Err(e) = Calibration::remove(&self.system_state.calibration_path()) {
+      eprintln!("{} {}", "Failed to remove the calibration state:".red(), e);
+    }
+    result.err()
+  }
+
+  fn restore_original_settings(&self) -> Option<io::Error> {
+    // the daemon is stopping, a calibration continues when it's back
+    if let Some(calibration) = &self.calibration
+      && let Err(e) = calibration.suspend(&self.system_state.battery_states)
+    {
+      eprintln!("{} {}", "Failed to stop force discharging:".red(), e);
+    }
+
+    match restore_snapshot(&self.system_state.snapshot_path()) {
+      Ok(_) => None,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        println!("No saved settings to restore");
+        None
+      }
+      Err(e) => {
+        eprintln!("{} {}", "Failed to restore the original settings:".red(), e);
+        Some(e)
+      }
+    }
+  }
+
+  fn handle(&mut self, event: Event) -> Result<(), SystemStateError> {
+    match event {
+      Event::ControlRequest => {
+        self.handle_control_requests();
+        return Ok(());
+      }
+      Event::PowerProfilesRequest => {
+        self.handle_power_profiles_requests();
+        return Ok(());
+      }
+      Event::ConfigChanged | Event::Hangup => {
+        let reload = self.config_watcher.drain() || matches!(event, Event::Hangup);
+        if reload && let Err(e) = self.reload_config() {
+          eprintln!("{} {}", "Failed to reload config:".red(), e);
+        }
+        return Ok(());
+      }
+      _ => {}
+    }
+    self.last_event = event.clone();
+
+    if let Some((state, Some(until))) = self.forced
+      && Instant::now() >= until
+    {
+      println!("Forced state {} expired", state);
+      self.forced = None;
+    }
+
+    // only read, so kept up while paused too
+    if let Event::PeriodicCheck = event {
+      self.system_state.cpu_states.sample(Instant::now())?;
+      self.system_state.battery_states.sample();
+      // asked for explicitly, so it goes on while paused
+      self.update_calibration();
+    }
+    if let Event::PeriodicCheck | Event::PowerUnPlug = event {
+      self.update_charge_full();
+    }
+    if self.paused {
+      return Ok(());
+    }
+    if let Event::PeriodicCheck | Event::CpuHotplug = event {
+      self.handle_hotplug()?;
+    }
+    if self.forced.is_none() {
+      event.handle_event(self.system_state)?;
+    }
+    if let Event::PeriodicCheck = event {
+      self.handle_drift()?;
+    }
+    Ok(())
+  }
+
+  /// Also run on periodic checks, a missed udev event would otherwise leave a core on kernel
+  /// defaults.
+  fn handle_hotplug(&self) -> Result<(), SystemStateError> {
+    if !self.system_state.cpu_states.rescan()? {
+      return Ok(());
+    }
+
+    println!(
+      "Online cpus changed to {:?}, re-applying the {} profile",
+      self.system_state.cpu_states.online_cpus(),
+      self.system_state.state.borrow()
+    );
+    self.system_state.reconverge()
+  }
+
+  fn handle_drift(&mut self) -> Result<(), SystemStateError> {
+    let drift = self.system_state.detect_drift()?;
+    if drift.is_empty() || drift == self.last_drift {
+      self.last_drift = drift;
+      return Ok(());
+    }
+
+    for line in &drift {
+      println!("{} {}", "Drifted from the profile:".yellow(), line);
+    }
+    if self.system_state.policy.borrow().reconverge {
+      println!(
+        "Re-applying the {} profile",
+        self.system_state.state.borrow()
+      );
+      self.system_state.reconverge()?;
+      self.last_drift = vec![];
+    } else {
+      self.last_drift = drift;
+    }
+    Ok(())
+  }
+
+  fn handle_control_requests(&mut self) {
+    let Some(control) = &self.control else {
+      return;
+    };
+
+    let mut requests = vec![];
+    while let Some(request) = control.accept() {
+      requests.push(request);
+    }
+
+    for (stream, request) in requests {
+      let response = match request {
+        Ok(request) => self.execute(request.command),
+        Err(e) => Response::error(&e),
+      };
+
+      if let Err(e) = ControlServer::respond(stream, &response) {
+        eprintln!("{} {}", "Failed to answer control request:".red(), e);
+      }
+    }
+  }
+
+  fn handle_power_profiles_requests(&mut self) {
+    let active_profile = self.active_profile();
+    let Some(power_profiles) = &mut self.power_profiles else {
+      return;
+    };
+
+    let command = match power_profiles.process(active_profile) {
+      Ok(Some(ProfileRequest::Force(state))) => Command::ForceProfile {
+        state,
+        duration_s: None,
+      },
+      // unlike `resume`, a pause or restore from the CLI stays in place
+      Ok(Some(ProfileRequest::Automatic)) => {
+        if let Some((state, _)) = self.forced.take() {
+          println!("Desktop released the forced state {}", state);
+        }
+        if !self.paused
+          && let Err(e) = Event::PeriodicCheck.handle_event(self.system_state)
+        {
+          eprintln!("{} {}", "Failed to apply desktop power profile:".red(), e);
+        }
+        return;
+      }
+      Ok(None) => return,
+      Err(e) => {
+        eprintln!("{} {}", "Lost D-Bus connection:".red(), e);
+        self.poller.unwatch_fd(power_profiles.as_raw_fd());
+        self.power_profiles = None;
+        return;
+      }
+    };
+
+    if let Some(error) = self.execute(command).error {
+      eprintln!(
+        "{} {}",
+        "Failed to apply desktop power profile:".red(),
+        error
+      );
+    }
+  }
+
+  fn notify_power_profiles(&mut self) {
+    let active_profile = self.active_profile();
+    if let Some(power_profiles) = &mut self.power_profiles
+      && let Err(e) = power_profiles.notify(active_profile)
+    {
+      eprintln!("{} {}", "Failed to notify power profile change:".red(), e);
+    }
+  }
+
+  /// The profile desktops see: a forced state, or "balanced" while powereg is in control.
+  fn active_profile(&self) -> &'static str {
+    match self.forced {
+      Some((state, _)) => profile_name(state),
+      None => BALANCED,
+    }
+  }
+
+  /// Runs a control request, what the control socket and the D-Bus service hand in.
+  pub fn execute(&mut self, command: Command) -> Response {
+    match command {
+      Command::Status => Response::status(self.status()),
+      Command::ForceProfile { state, duration_s } => {
+        if let Err(e) = self.system_state.apply_state(state) {
+          return Response::error(&e.to_string());
+        }
+
+        println!("Forcing state {}", state);
+        *self.system_state.state.borrow_mut() = state;
+        self.forced = Some((
+          state,
+          duration_s.map(|s| Instant::now() + Duration::from_secs(s)),
+        ));
+        Response::ok()
+      }
+      Command::Pause => {
+        println!("Pausing automatic control");
+        self.paused = true;
+        Response::ok()
+      }
+      Command::Resume => {
+        println!("Resuming automatic control");
+        self.paused = false;
+        self.forced = None;
+
+        // a `restore` removed the snapshot, the current settings are the original ones again
+        if let Err(e) = self.system_state.save_snapshot() {
+          eprintln!("{} {}", "Failed to save the original settings:".red(), e);
+        }
+
+        match Event::PeriodicCheck.handle_event(self.system_state) {
+          Ok(_) => Response::ok(),
+          Err(e) => Response::error(&e.to_string()),
+        }
+      }
+      Command::Calibrate { target } => {
+        if let Some(calibration) = &self.calibration {
+          return Response::error(&format!(
+            "battery calibration already running, {calibration}"
+          ));
+        }
+        if self.charge_full.is_some() {
+          return Response::error("already charging to full");
+        }
+
+        let battery_states = &self.system_state.battery_states;
+        let calibration = match Calibration::start(battery_states, target) {
+          Ok(calibration) => calibration,
+          Err(e) => return Response::error(&e.to_string()),
+        };
+        if let Err(e) = calibration.save(&self.system_state.calibration_path()) {
+          return Response::error(&format!("failed to save the calibration state: {e}"));
+        }
+        println!("Starting battery calibration, {}", calibration);
+        let result = calibration.apply(battery_states);
+        self.calibration = Some(calibration);
+        if let Err(e) = result {
+          self.stop_calibration();
+          return Response::error(&e.to_string());
+        }
+        Response::ok()
+      }
+      Command::AbortCalibration => {
+        if self.calibration.is_none() {
+          return Response::error("no battery calibration running");
+        }
+        println!("Aborting battery calibration");
+        match self.stop_calibration() {
+          Some(e) => Response::error(&e.to_string()),
+          None => Response::ok(),
+        }
+      }
+      Command::ChargeFull => {
+        if self.calibration.is_some() {
+          return Response::error("battery calibration running, it charges to full already");
+        }
+        if self.charge_full.is_some() {
+          return Response::ok();
+        }
+
+        let charge_full = match ChargeFull::start(self.system_state) {
+          Ok(charge_full) => charge_full,
+          Err(e) => return Response::error(&e.to_string()),
+        };
+        println!("Charging to full, lifting the charge thresholds");
+        if let Err(e) = charge_full.apply(&self.system_state.battery_states) {
+          let _ = charge_full.finish(self.system_state, self.config.as_ref());
+          return Response::error(&e.to_string());
+        }
+        self.charge_full = Some(charge_full);
+        Response::ok()
+      }
+      Command::Reload => match self.reload_config() {
+        Ok(_) => Response::ok(),
+        Err(e) => Response::error(&e),
+      },
+      Command::Restore => {
+        println!("Restoring the original settings, pausing automatic control");
+        self.paused = true;
+        self.forced = None;
+        if let Some(e) = self.stop_calibration() {
+          eprintln!("{} {}", "Failed to abort battery calibration:".red(), e);
+        }
+        // the snapshot has the thresholds to go back to
+        self.charge_full = None;
+
+        match self.restore_original_settings() {
+          Some(e) => Response::error(&e.to_string()),
+          None => Response::ok(),
+        }
+      }
+    }
+  }
+
+  /// Re-parses the config and applies what changed, an invalid config leaves the current one
+  /// in place.
+  fn reload_config(&mut self) -> Result<(), String> {
+    println!("Reloading config from {}", self.config_path);
+    let config =
+      Config::parse(&self.config_path).map_err(|e| format!("{}, keeping the current config", e))?;
+
+    let changes = match &self.config {
+      Some(old) => {
+        let diff = config.diff(old);
+        if diff.is_empty() {
+          println!("Config unchanged");
+          return Ok(());
+        }
+        for line in diff {
+          println!("    {}", line);
+        }
+        config.changes_from(old)
+      }
+      None => config.clone(),
+    };
+
+    let state = *self.system_state.state.borrow();
+    let old_profile = self.system_state.profiles.borrow().for_state(state);
+
+    changes
+      .apply(self.system_state)
+      .map_err(|e| e.to_string())?;
+    self.config = Some(config);
+
+    // the new thresholds apply once the battery is full
+    if let Some(charge_full) = &self.charge_full
+      && (changes.charge_start_threshold.is_some() || changes.charge_stop_threshold.is_some())
+    {
+      charge_full
+        .apply(&self.system_state.battery_states)
+        .map_err(|e| e.to_string())?;
+    }
+
+    // new thresholds are what the calibration restores, until then it keeps charging to 100%
+    if let Some(calibration) = &mut self.calibration
+      && (changes.charge_start_threshold.is_some() || changes.charge_stop_threshold.is_some())
+    {
+      if let Some(start) = changes.charge_start_threshold {
+        calibration.start_threshold = Some(start.into());
+      }
+      if let Some(stop) = changes.charge_stop_threshold {
+        calibration.stop_threshold = Some(stop.into());
+      }
+      calibration
+        .apply(&self.system_state.battery_states)
+        .map_err(|e| e.to_string())?;
+      if let Err(e) = calibration.save(&self.system_state.calibration_path()) {
+        eprintln!("{} {}", "Failed to save the calibration state:".red(), e);
+      }
+    }
+    self
+      .poller
+      .set_periodic_interval(self.system_state.policy.borrow().loop_duration_s);
+
+    let profile_changed = self.system_state.profiles.borrow().for_state(state) != old_profile;
+    if profile_changed && !self.paused {
+      println!("Re-applying the {} profile", state);
+      self
+        .system_state
+        .apply_state(state)
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  }
+
+  pub fn status(&self) -> DaemonStatus {
+    DaemonStatus {
+      state: *self.system_state.state.borrow(),
+      last_event: self.last_event.to_string(),
+      paused: self.paused,
+      forced_state: self.forced.map(|(state, _)| state),
+      forced_remaining_s: self
+        .forced
+        .and_then(|(_, until)| until)
+        .map(|until| until.saturating_duration_since(Instant::now()).as_secs()),
+      time_estimate: self.system_state.battery_states.estimate_time(),
+      calibration: self.calibration.as_ref().map(|c| c.to_string()),
+      charging_full: self.charge_full.is_some(),
+      settings: self.system_state.read_applied_settings(),
+    }
+  }
+}
@@ -0,0 +1,761 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+const SYSTEM_BUS_ADDRESS: &str = "unix:path=/run/dbus/system_bus_socket";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_MESSAGE_SIZE: usize = 1 << 27;
+
+pub const FLAG_NO_REPLY_EXPECTED: u8 = 0x1;
+
+#[derive(Debug)]
+pub enum DBusError {
+  AuthFailed(String),
+  InvalidMessage(String),
+  InvalidAddress(String),
+  /// An error reply: (error name, message)
+  Remote(String, String),
+  GeneralIoErr(io::Error),
+}
+
+impl fmt::Display for DBusError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DBusError::AuthFailed(e) => write!(f, "D-Bus authentication failed: {e}"),
+      DBusError::InvalidMessage(e) => write!(f, "Invalid D-Bus message: {e}"),
+      DBusError::InvalidAddress(e) => write!(f, "Unsupported D-Bus address: {e}"),
+      DBusError::Remote(name, e) => write!(f, "{name}: {e}"),
+      DBusError::GeneralIoErr(e) => write!(f, "General io error: {e}"),
+    }
+  }
+}
+
+impl From<io::Error> for DBusError {
+  fn from(error: io::Error) -> Self {
+    DBusError::GeneralIoErr(error)
+  }
+}
+
+fn invalid(e: &str) -> DBusError {
+  DBusError::InvalidMessage(e.to_string())
+}
+
+/// The subset of the D-Bus type system powereg needs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Byte(u8),
+  Bool(bool),
+  I32(i32),
+  U32(u32),
+  Str(String),
+  ObjectPath(String),
+  Signature(String),
+  Variant(Box<Value>),
+  /// (element signature, elements)
+  Array(String, Vec<Value>),
+  Struct(Vec<Value>),
+  DictEntry(Box<Value>, Box<Value>),
+}
+
+impl Value {
+  pub fn signature(&self) -> String {
+    match self {
+      Value::Byte(_) => "y".to_string(),
+      Value::Bool(_) => "b".to_string(),
+      Value::I32(_) => "i".to_string(),
+      Value::U32(_) => "u".to_string(),
+      Value::Str(_) => "s".to_string(),
+      Value::ObjectPath(_) => "o".to_string(),
+      Value::Signature(_) => "g".to_string(),
+      Value::Variant(_) => "v".to_string(),
+      Value::Array(elem, _) => format!("a{}", elem),
+      Value::Struct(fields) => format!(
+        "({})",
+        fields.iter().map(|f| f.signature()).collect::<String>()
+      ),
+      Value::DictEntry(k, v) => format!("{{{}{}}}", k.signature(), v.signature()),
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::Str(s) | Value::ObjectPath(s) | Value::Signature(s) => Some(s),
+      Value::Variant(v) => v.as_str(),
+      _ => None,
+    }
+  }
+
+  pub fn as_u32(&self) -> Option<u32> {
+    match self {
+      Value::U32(u) => Some(*u),
+      Value::Variant(v) => v.as_u32(),
+      _ => None,
+    }
+  }
+
+  /// a{sv} from (key, value) pairs
+  pub fn dict(entries: Vec<(&str, Value)>) -> Value {
+    Value::Array(
+      "{sv}".to_string(),
+      entries
+        .into_iter()
+        .map(|(k, v)| {
+          Value::DictEntry(
+            Box::new(Value::Str(k.to_string())),
+            Box::new(Value::Variant(Box::new(v))),
+          )
+        })
+        .collect(),
+    )
+  }
+
+  /// Looks up `key` in an a{sv}, returning the unwrapped variant.
+  pub fn dict_get(&self, key: &str) -> Option<&Value> {
+    let Value::Array(_, entries) = self else {
+      return None;
+    };
+    entries.iter().find_map(|entry| match entry {
+      Value::DictEntry(k, v) if k.as_str() == Some(key) => match v.as_ref() {
+        Value::Variant(inner) => Some(inner.as_ref()),
+        other => Some(other),
+      },
+      _ => None,
+    })
+  }
+}
+
+fn alignment(sig: u8) -> usize {
+  match sig {
+    b'y' | b'g' | b'v' => 1,
+    b'(' | b'{' | b'd' | b'x' | b't' => 8,
+    b'n' | b'q' => 2,
+    _ => 4,
+  }
+}
+
+/// Splits the first complete type off a signature.
+fn split_type(sig: &str) -> Result<(&str, &str), DBusError> {
+  let bytes = sig.as_bytes();
+  match bytes.first() {
+    None => Err(invalid("empty signature")),
+    Some(b'a') => {
+      let (elem, _) = split_type(&sig[1..])?;
+      Ok(sig.split_at(1 + elem.len()))
+    }
+    Some(open @ (b'(' | b'{')) => {
+      let close = if *open == b'(' { b')' } else { b'}' };
+      let mut depth = 0;
+      for (i, c) in bytes.iter().enumerate() {
+        if *c == *open {
+          depth += 1;
+        } else if *c == close {
+          depth -= 1;
+          if depth == 0 {
+            return Ok(sig.split_at(i + 1));
+          }
+        }
+      }
+      Err(invalid("unbalanced signature"))
+    }
+    Some(_) => Ok(sig.split_at(1)),
+  }
+}
+
+fn split_types(mut sig: &str) -> Result<Vec<&str>, DBusError> {
+  let mut types = vec![];
+  while !sig.is_empty() {
+    let (first, rest) = split_type(sig)?;
+    types.push(first);
+    sig = rest;
+  }
+  Ok(types)
+}
+
+struct Writer {
+  buf: Vec<u8>,
+}
+
+impl Writer {
+  fn align(&mut self, n: usize) {
+    while !self.buf.len().is_multiple_of(n) {
+      self.buf.push(0);
+    }
+  }
+
+  fn u32(&mut self, v: u32) {
+    self.align(4);
+    self.buf.extend_from_slice(&v.to_le_bytes());
+  }
+
+  fn string(&mut self, s: &str) {
+    self.u32(s.len() as u32);
+    self.buf.extend_from_slice(s.as_bytes());
+    self.buf.push(0);
+  }
+
+  fn signature(&mut self, s: &str) {
+    self.buf.push(s.len() as u8);
+    self.buf.extend_from_slice(s.as_bytes());
+    self.buf.push(0);
+  }
+
+  fn value(&mut self, value: &Value) {
+    match value {
+      Value::Byte(b) => self.buf.push(*b),
+      Value::Bool(b) => self.u32(*b as u32),
+      Value::I32(i) => self.u32(*i as u32),
+      Value::U32(u) => self.u32(*u),
+      Value::Str(s) | Value::ObjectPath(s) => self.string(s),
+      Value::Signature(s) => self.signature(s),
+      Value::Variant(v) => {
+        self.signature(&v.signature());
+        self.value(v);
+      }
+      Value::Array(elem, values) => {
+        self.u32(0);
+        let len_pos = self.buf.len() - 4;
+        self.align(alignment(elem.as_bytes()[0]));
+        let start = self.buf.len();
+        for v in values {
+          self.value(v);
+        }
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+      }
+      Value::Struct(fields) => {
+        self.align(8);
+        for f in fields {
+          self.value(f);
+        }
+      }
+      Value::DictEntry(k, v) => {
+        self.align(8);
+        self.value(k);
+        self.value(v);
+      }
+    }
+  }
+}
+
+struct Reader<'a> {
+  buf: &'a [u8],
+  pos: usize,
+}
+
+impl Reader<'_> {
+  fn align(&mut self, n: usize) {
+    self.pos = self.pos.div_ceil(n) * n;
+  }
+
+  fn take(&mut self, n: usize) -> Result<&[u8], DBusError> {
+    let end = self
+      .pos
+      .checked_add(n)
+      .filter(|end| *end <= self.buf.len())
+      .ok_or(invalid("truncated message"))?;
+    let bytes = &self.buf[self.pos..end];
+    self.pos = end;
+    Ok(bytes)
+  }
+
+  fn u32(&mut self) -> Result<u32, DBusError> {
+    self.align(4);
+    let bytes = self.take(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  fn string(&mut self) -> Result<String, DBusError> {
+    let len = self.u32()? as usize;
+    let s = String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("invalid utf-8"))?;
+    self.take(1)?;
+    Ok(s)
+  }
+
+  fn signature(&mut self) -> Result<String, DBusError> {
+    let len = self.take(1)?[0] as usize;
+    let s = String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("invalid utf-8"))?;
+    self.take(1)?;
+    Ok(s)
+  }
+
+  /// Reads one value of the single complete type `sig`.
+  fn value(&mut self, sig: &str) -> Result<Value, DBusError> {
+    match sig.as_bytes()[0] {
+      b'y' => Ok(Value::Byte(self.take(1)?[0])),
+      b'b' => Ok(Value::Bool(self.u32()? != 0)),
+      b'i' => Ok(Value::I32(self.u32()? as i32)),
+      b'u' => Ok(Value::U32(self.u32()?)),
+      b's' => Ok(Value::Str(self.string()?)),
+      b'o' => Ok(Value::ObjectPath(self.string()?)),
+      b'g' => Ok(Value::Signature(self.signature()?)),
+      b'v' => {
+        let inner = self.signature()?;
+        let (inner, _) = split_type(&inner)?;
+        Ok(Value::Variant(Box::new(self.value(inner)?)))
+      }
+      b'a' => {
+        let elem = &sig[1..];
+        let len = self.u32()? as usize;
+        self.align(alignment(elem.as_bytes()[0]));
+        let end = self.pos + len;
+        if end > self.buf.len() {
+          return Err(invalid("array exceeds message"));
+        }
+        let mut values = vec![];
+        while self.pos < end {
+          values.push(self.value(elem)?);
+        }
+        Ok(Value::Array(elem.to_string(), values))
+      }
+      b'(' => {
+        self.align(8);
+        let fields = split_types(&sig[1..sig.len() - 1])?
+          .into_iter()
+          .map(|f| self.value(f))
+          .collect::<Result<Vec<_>, _>>()?;
+        Ok(Value::Struct(fields))
+      }
+      b'{' => {
+        self.align(8);
+        let (key, rest) = split_type(&sig[1..sig.len() - 1])?;
+        let key = self.value(key)?;
+        let value = self.value(rest)?;
+        Ok(Value::DictEntry(Box::new(key), Box::new(value)))
+      }
+      _ => Err(DBusError::InvalidMessage(format!(
+        "unsupported type '{}'",
+        sig
+      ))),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+  MethodCall = 1,
+  MethodReturn = 2,
+  Error = 3,
+  Signal = 4,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+  pub msg_type: MessageType,
+  pub flags: u8,
+  pub serial: u32,
+
+  pub path: Option<String>,
+  pub interface: Option<String>,
+  pub member: Option<String>,
+  pub error_name: Option<String>,
+  pub reply_serial: Option<u32>,
+  pub destination: Option<String>,
+  pub sender: Option<String>,
+
+  pub body: Vec<Value>,
+}
+
+impl Message {
+  fn new(msg_type: MessageType) -> Self {
+    Self {
+      msg_type,
+      flags: 0,
+      serial: 0,
+      path: None,
+      interface: None,
+      member: None,
+      error_name: None,
+      reply_serial: None,
+      destination: None,
+      sender: None,
+      body: vec![],
+    }
+  }
+
+  pub fn method_call(
+    destination: &str,
+    path: &str,
+    interface: &str,
+    member: &str,
+    body: Vec<Value>,
+  ) -> Self {
+    Self {
+      path: Some(path.to_string()),
+      interface: Some(interface.to_string()),
+      member: Some(member.to_string()),
+      destination: Some(destination.to_string()),
+      body,
+      ..Self::new(MessageType::MethodCall)
+    }
+  }
+
+  pub fn signal(path: &str, interface: &str, member: &str, body: Vec<Value>) -> Self {
+    Self {
+      path: Some(path.to_string()),
+      interface: Some(interface.to_string()),
+      member: Some(member.to_string()),
+      body,
+      ..Self::new(MessageType::Signal)
+    }
+  }
+
+  pub fn method_return(call: &Message, body: Vec<Value>) -> Self {
+    Self {
+      reply_serial: Some(call.serial),
+      destination: call.sender.clone(),
+      body,
+      ..Self::new(MessageType::MethodReturn)
+    }
+  }
+
+  pub fn error(call: &Message, name: &str, text: &str) -> Self {
+    Self {
+      error_name: Some(name.to_string()),
+      reply_serial: Some(call.serial),
+      destination: call.sender.clone(),
+      body: vec![Value::Str(text.to_string())],
+      ..Self::new(MessageType::Error)
+    }
+  }
+
+  pub fn expects_reply(&self) -> bool {
+    self.msg_type == MessageType::MethodCall && self.flags & FLAG_NO_REPLY_EXPECTED == 0
+  }
+
+  fn encode(&self, serial: u32) -> Vec<u8> {
+    let mut body = Writer { buf: vec![] };
+    for v in &self.body {
+      body.value(v);
+    }
+    let signature: String = self.body.iter().map(|v| v.signature()).collect();
+
+    let mut fields = vec![];
+    let mut field = |code: u8, value: Value| {
+      fields.push(Value::Struct(vec![
+        Value::Byte(code),
+        Value::Variant(Box::new(value)),
+      ]));
+    };
+    if let Some(path) = &self.path {
+      field(1, Value::ObjectPath(path.clone()));
+    }
+    if let Some(interface) = &self.interface {
+      field(2, Value::Str(interface.clone()));
+    }
+    if let Some(member) = &self.member {
+      field(3, Value::Str(member.clone()));
+    }
+    if let Some(error_name) = &self.error_name {
+      field(4, Value::Str(error_name.clone()));
+    }
+    if let Some(reply_serial) = self.reply_serial {
+      field(5, Value::U32(reply_serial));
+    }
+    if let Some(destination) = &self.destination {
+      field(6, Value::Str(destination.clone()));
+    }
+    if !signature.is_empty() {
+      field(8, Value::Signature(signature));
+    }
+
+    let mut msg = Writer {
+      buf: vec![b'l', self.msg_type as u8, self.flags, 1],
+    };
+    msg.u32(body.buf.len() as u32);
+    msg.u32(serial);
+    msg.value(&Value::Array("(yv)".to_string(), fields));
+    msg.align(8);
+    msg.buf.extend_from_slice(&body.buf);
+    msg.buf
+  }
+
+  /// Total size of the message starting at `buf`, once its fixed header is available.
+  fn encoded_len(buf: &[u8]) -> Result<Option<usize>, DBusError> {
+    if buf.len() < 16 {
+      return Ok(None);
+    }
+    // big endian messages are measured so they can be skipped, `decode` rejects them
+    let read_u32 = |i: usize| {
+      let bytes = [buf[i], buf[i + 1], buf[i + 2], buf[i + 3]];
+      match buf[0] {
+        b'l' => Ok(u32::from_le_bytes(bytes) as usize),
+        b'B' => Ok(u32::from_be_bytes(bytes) as usize),
+        _ => Err(invalid("invalid byte order")),
+      }
+    };
+    let body_len = read_u32(4)?;
+    let fields_len = read_u32(12)?;
+    let len = (16 + fields_len).div_ceil(8) * 8 + body_len;
+    if len > MAX_MESSAGE_SIZE {
+      return Err(invalid("message too large"));
+    }
+    Ok(Some(len))
+  }
+
+  fn decode(buf: &[u8]) -> Result<Self, DBusError> {
+    let (mut msg, signature, body_start) = Self::decode_header(buf)?;
+    let mut reader = Reader {
+      buf,
+      pos: body_start,
+    };
+    for sig in split_types(&signature)? {
+      msg.body.push(reader.value(sig)?);
+    }
+    Ok(msg)
+  }
+
+  /// The message without its body, along with the body signature and offset.
+  fn decode_header(buf: &[u8]) -> Result<(Self, String, usize), DBusError> {
+    if buf[0] != b'l' {
+      return Err(invalid("only little endian messages are supported"));
+    }
+    let msg_type = match buf[1] {
+      1 => MessageType::MethodCall,
+      2 => MessageType::MethodReturn,
+      3 => MessageType::Error,
+      4 => MessageType::Signal,
+      _ => return Err(invalid("unknown message type")),
+    };
+    let mut msg = Self::new(msg_type);
+    msg.flags = buf[2];
+
+    let mut reader = Reader { buf, pos: 8 };
+    msg.serial = reader.u32()?;
+
+    let mut signature = String::new();
+    let Value::Array(_, fields) = reader.value("a(yv)")? else {
+      return Err(invalid("invalid header fields"));
+    };
+    for field in fields {
+      let Value::Struct(field) = field else {
+        continue;
+      };
+      let (Some(Value::Byte(code)), Some(value)) = (field.first(), field.get(1)) else {
+        continue;
+      };
+      let text = value.as_str().map(|s| s.to_string());
+      match code {
+        1 => msg.path = text,
+        2 => msg.interface = text,
+        3 => msg.member = text,
+        4 => msg.error_name = text,
+        5 => msg.reply_serial = value.as_u32(),
+        6 => msg.destination = text,
+        7 => msg.sender = text,
+        8 => signature = text.unwrap_or_default(),
+        _ => {}
+      }
+    }
+
+    reader.align(8);
+    Ok((msg, signature, reader.pos))
+  }
+}
+
+/// A bus connection over a unix socket, authenticated with EXTERNAL.
+pub struct Connection {
+  stream: UnixStream,
+  serial: u32,
+  buf: Vec<u8>,
+  queue: VecDeque<Message>,
+  pub unique_name: String,
+}
+
+impl AsRawFd for Connection {
+  fn as_raw_fd(&self) -> RawFd {
+    self.stream.as_raw_fd()
+  }
+}
+
+impl Connection {
+  pub fn connect_system() -> Result<Self, DBusError> {
+    let address =
+      env::var("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or_else(|_| SYSTEM_BUS_ADDRESS.to_string());
+    Self::connect(&address)
+  }
+
+  pub fn connect(address: &str) -> Result<Self, DBusError> {
+    let path = address
+      .split(';')
+      .filter_map(|a| a.strip_prefix("unix:"))
+      .flat_map(|a| a.split(','))
+      .find_map(|kv| kv.strip_prefix("path="))
+      .ok_or(DBusError::InvalidAddress(address.to_string()))?;
+
+    let mut connection = Self {
+      stream: UnixStream::connect(path)?,
+      serial: 0,
+      buf: vec![],
+      queue: VecDeque::new(),
+      unique_name: String::new(),
+    };
+    connection.authenticate()?;
+
+    let reply = connection.call(Message::method_call(
+      "org.freedesktop.DBus",
+      "/org/freedesktop/DBus",
+      "org.freedesktop.DBus",
+      "Hello",
+      vec![],
+    ))?;
+    connection.unique_name = reply
+      .body
+      .first()
+      .and_then(|v| v.as_str())
+      .unwrap_or_default()
+      .to_string();
+
+    Ok(connection)
+  }
+
+  fn authenticate(&mut self) -> Result<(), DBusError> {
+    let uid = unsafe { libc::geteuid() }.to_string();
+    let hex_uid: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+
+    self.stream.set_read_timeout(Some(CALL_TIMEOUT))?;
+    self.stream.write_all(b"\0")?;
+    self
+      .stream
+      .write_all(format!("AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+
+    let mut line = vec![];
+    let mut byte = [0u8];
+    while !line.ends_with(b"\r\n") {
+      if self.stream.read(&mut byte)? == 0 {
+        return Err(DBusError::AuthFailed("connection closed".to_string()));
+      }
+      line.push(byte[0]);
+    }
+    let line = String::from_utf8_lossy(&line);
+    if !line.starts_with("OK ") {
+      return Err(DBusError::AuthFailed(line.trim().to_string()));
+    }
+
+    self.stream.write_all(b"BEGIN\r\n")?;
+    Ok(())
+  }
+
+  pub fn send(&mut self, msg: &Message) -> Result<u32, DBusError> {
+    self.serial += 1;
+    self.stream.write_all(&msg.encode(self.serial))?;
+    Ok(self.serial)
+  }
+
+  /// Sends a method call and blocks until its reply, queueing anything else that arrives.
+  pub fn call(&mut self, msg: Message) -> Result<Message, DBusError> {
+    let serial = self.send(&msg)?;
+    self.stream.set_read_timeout(Some(CALL_TIMEOUT))?;
+
+    loop {
+      if let Some(i) = self.buffered_reply(serial)? {
+        let reply = self.queue.remove(i).unwrap();
+        if reply.msg_type == MessageType::Error {
+          let text = reply.body.first().and_then(|v| v.as_str()).unwrap_or("");
+          return Err(DBusError::Remote(
+            reply.error_name.clone().unwrap_or_default(),
+            text.to_string(),
+          ));
+        }
+        return Ok(reply);
+      }
+
+      let mut chunk = [0u8; 4096];
+      let n = self.stream.read(&mut chunk)?;
+      if n == 0 {
+        return Err(DBusError::GeneralIoErr(io::ErrorKind::UnexpectedEof.into()));
+      }
+      self.buf.extend_from_slice(&chunk[..n]);
+    }
+  }
+
+  fn buffered_reply(&mut self, serial: u32) -> Result<Option<usize>, DBusError> {
+    self.parse_buffered()?;
+    Ok(
+      self
+        .queue
+        .iter()
+        .position(|m| m.reply_serial == Some(serial)),
+    )
+  }
+
+  fn parse_buffered(&mut self) -> Result<(), DBusError> {
+    while let Some(len) = Message::encoded_len(&self.buf)? {
+      if self.buf.len() < len {
+        break;
+      }
+      let buf: Vec<u8> = self.buf.drain(..len).collect();
+      match Message::decode(&buf) {
+        Ok(msg) => self.queue.push_back(msg),
+        Err(e) => self.reject(&buf, &e)?,
+      }
+    }
+    Ok(())
+  }
+
+  /// Drops a message that can't be decoded, the stream stays in sync since its length is known.
+  /// Method calls get an InvalidArgs error so the caller isn't left waiting.
+  fn reject(&mut self, buf: &[u8], error: &DBusError) -> Result<(), DBusError> {
+    let Ok((call, _, _)) = Message::decode_header(buf) else {
+      return Ok(());
+    };
+    if call.expects_reply() {
+      self.send(&Message::error(
+        &call,
+        "org.freedesktop.DBus.Error.InvalidArgs",
+        &error.to_string(),
+      ))?;
+    }
+    Ok(())
+  }
+
+  /// Everything that can be read without blocking, for use once poll() reports the socket readable.
+  pub fn read_messages(&mut self) -> Result<Vec<Message>, DBusError> {
+    self.stream.set_nonblocking(true)?;
+    let mut chunk = [0u8; 4096];
+    let result = loop {
+      match self.stream.read(&mut chunk) {
+        Ok(0) => break Err(DBusError::GeneralIoErr(io::ErrorKind::UnexpectedEof.into())),
+        Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+        Err(e) => break Err(DBusError::GeneralIoErr(e)),
+      }
+    };
+    self.stream.set_nonblocking(false)?;
+    result?;
+
+    self.parse_buffered()?;
+    Ok(self.queue.drain(..).collect())
+  }
+
+  /// Returns the RequestName reply code (1 = primary owner).
+  pub fn request_name(&mut self, name: &str) -> Result<u32, DBusError> {
+    // DBUS_NAME_FLAG_DO_NOT_QUEUE
+    let reply = self.call(Message::method_call(
+      "org.freedesktop.DBus",
+      "/org/freedesktop/DBus",
+      "org.freedesktop.DBus",
+      "RequestName",
+      vec![Value::Str(name.to_string()), Value::U32(0x4)],
+    ))?;
+    reply
+      .body
+      .first()
+      .and_then(|v| v.as_u32())
+      .ok_or(invalid("invalid RequestName reply"))
+  }
+
+  pub fn add_match(&mut self, rule: &str) -> Result<(), DBusError> {
+    self.call(Message::method_call(
+      "org.freedesktop.DBus",
+      "/org/freedesktop/DBus",
+      "org.freedesktop.DBus",
+      "AddMatch",
+      vec![Value::Str(rule.to_string())],
+    ))?;
+    Ok(())
+  }
+}
@@ -1,10 +1,12 @@
 use crate::{
-  battery::ChargingStatus,
+  adapter::AdapterStates,
+  policy::{LoadLevel, Policy},
+  signals::{signal_name, SignalFd},
   system_state::{State, SystemState, SystemStateError},
 };
 use std::{
   fmt, io,
-  os::unix::io::AsRawFd,
+  os::unix::io::{AsRawFd, RawFd},
   time::{Duration, Instant},
 };
 use udev::MonitorBuilder;
@@ -18,15 +20,26 @@ pub enum Event {
   LowBattery,
   HighCpuLoad,
   LowCpuLoad,
+  /// past `high_cpu_temp` on ac, or not yet back to `normal_cpu_temp`
+  HighCpuTemp,
+  /// back to `normal_cpu_temp` after `HighCpuTemp`
+  NormalCpuTemp,
+  /// a core went on or offline
+  CpuHotplug,
+
+  ControlRequest,
+  PowerProfilesRequest,
+  ConfigChanged,
+  /// SIGHUP, reloads the config
+  Hangup,
+  /// SIGTERM or SIGINT
+  Shutdown(&'static str),
 
   Unknown,
   Error(String),
 }
 
 impl Event {
-  const HIGH_CPU_LOAD: f64 = 35.0;
-  const LOW_CPU_LOAD: f64 = 30.0;
-
   fn state_transition(self: &Event, system_state: &SystemState) {
     let old_state = *system_state.state.borrow();
 
@@ -35,17 +48,21 @@ impl Event {
         Event::PowerInPlug => State::Performance,
         Event::PowerUnPlug | Event::LowBattery => State::Powersave,
         Event::HighCpuLoad | Event::LowCpuLoad => State::Performance,
+        Event::HighCpuTemp => State::Balanced,
         _ => old_state,
       },
       State::Balanced => match self {
         Event::PowerInPlug => State::Performance,
         Event::PowerUnPlug | Event::LowBattery => State::Powersave,
         Event::HighCpuLoad | Event::LowCpuLoad => State::Performance,
+        Event::HighCpuTemp => State::Balanced,
+        Event::NormalCpuTemp => State::Performance,
         _ => old_state,
       },
       State::Powersave => match self {
         Event::PowerInPlug => State::Performance,
         Event::PowerUnPlug | Event::LowBattery => State::Powersave,
+        Event::HighCpuTemp => State::Balanced,
         _ => old_state,
       },
     };
@@ -53,22 +70,36 @@ impl Event {
     *system_state.state.borrow_mut() = new_state;
   }
 
-  fn periodic_check(system_state: &SystemState, cpu_load: f64) -> Result<Event, SystemStateError> {
-    let low_battery = system_state.battery_states.read_battery_capacity()? <= 20;
+  fn periodic_check(
+    system_state: &SystemState,
+    policy: &Policy,
+    load: LoadLevel,
+  ) -> Result<Event, SystemStateError> {
+    let low_battery = system_state.battery_states.has_battery()
+      && system_state.battery_states.read_battery_capacity()? <= policy.low_battery;
     if low_battery {
       return Ok(Event::LowBattery);
     }
 
-    let charging_status = system_state.battery_states.read_charging_status()?;
-    let discharging = charging_status == ChargingStatus::DisCharging;
+    let discharging = !system_state.on_ac()?;
 
-    let boost = system_state.cpu_states.read_cpu_boost()?;
-    let high_cpu_load = cpu_load >= Event::HIGH_CPU_LOAD;
-    let low_cpu_load = cpu_load < Event::LOW_CPU_LOAD;
+    // on battery powersave already runs the cpu as cool as it gets. Without a sensor the
+    // temperature is left out.
+    if !discharging && let Ok(cpu_temp) = system_state.cpu_states.read_cpu_temp() {
+      let balanced = *system_state.state.borrow() == State::Balanced;
+      if cpu_temp >= policy.high_cpu_temp || (balanced && cpu_temp > policy.normal_cpu_temp) {
+        return Ok(Event::HighCpuTemp);
+      } else if balanced {
+        return Ok(Event::NormalCpuTemp);
+      }
+    }
 
-    if high_cpu_load && !discharging && !boost {
+    let boost =
+      system_state.cpu_states.capabilities().boost && system_state.cpu_states.read_cpu_boost()?;
+
+    if load == LoadLevel::High && !discharging && !boost {
       return Ok(Event::HighCpuLoad);
-    } else if low_cpu_load && !discharging && boost {
+    } else if load == LoadLevel::Low && !discharging && boost {
       return Ok(Event::LowCpuLoad);
     }
 
@@ -80,17 +111,41 @@ impl Event {
   }
 
   pub fn handle_event(self: &Event, system_state: &SystemState) -> Result<(), SystemStateError> {
-    let cpu_load = system_state.cpu_states.read_cpu_load()?;
+    let policy = system_state.policy.borrow().clone();
+    // nothing to go on before the second sample
+    let load = match system_state.cpu_states.cpu_load() {
+      Some(cpu_load) => {
+        system_state
+          .load_debounce
+          .borrow_mut()
+          .update(cpu_load.busy, &policy, Instant::now())
+      }
+      None => LoadLevel::Normal,
+    };
 
-    let event = Self::periodic_check(system_state, cpu_load).unwrap_or(self.clone());
+    let event = Self::periodic_check(system_state, &policy, load).unwrap_or(self.clone());
 
     let old_state = *system_state.state.borrow();
     event.state_transition(system_state);
     let new_state = *system_state.state.borrow();
 
+    // the cap would outlive balanced otherwise, the new state's profile takes over boost
+    if new_state != State::Balanced && system_state.throttle.borrow_mut().reset() {
+      system_state.apply_throttle()?;
+    }
+
     // in its own branch because cpu boost may change depending on cpu load
     if new_state == State::Performance {
-      system_state.set_performance_mode(cpu_load >= Event::HIGH_CPU_LOAD)?;
+      let cpu_boost = match load {
+        LoadLevel::High => true,
+        LoadLevel::Low => false,
+        // between the thresholds boost stays as it is
+        LoadLevel::Normal => {
+          system_state.cpu_states.capabilities().boost
+            && system_state.cpu_states.read_cpu_boost()?
+        }
+      };
+      system_state.set_performance_mode(cpu_boost)?;
       return Ok(());
     }
 
@@ -102,6 +157,15 @@ impl Event {
       }
     }
 
+    if new_state == State::Balanced && policy.throttle {
+      let cpu_temp = system_state.cpu_states.read_cpu_temp()?;
+      if system_state.throttle.borrow_mut().update(cpu_temp, &policy) {
+        // a step back has to give boost back to the profile
+        system_state.set_balanced_mode()?;
+        system_state.apply_throttle()?;
+      }
+    }
+
     Ok(())
   }
 }
@@ -117,6 +181,15 @@ impl fmt::Display for Event {
       Event::LowBattery => write!(f, "low battery"),
       Event::LowCpuLoad => write!(f, "low cpu load"),
       Event::HighCpuLoad => write!(f, "high cpu load"),
+      Event::HighCpuTemp => write!(f, "high cpu temperature"),
+      Event::NormalCpuTemp => write!(f, "normal cpu temperature"),
+      Event::CpuHotplug => write!(f, "cpu hotplug"),
+
+      Event::ControlRequest => write!(f, "control request"),
+      Event::PowerProfilesRequest => write!(f, "power profiles request"),
+      Event::ConfigChanged => write!(f, "config changed"),
+      Event::Hangup => write!(f, "received SIGHUP"),
+      Event::Shutdown(signal) => write!(f, "received {}", signal),
 
       Event::Unknown => write!(f, "unknown event occured"),
       Event::Error(err) => write!(f, "an error occured: {}", err),
@@ -126,7 +199,9 @@ impl fmt::Display for Event {
 
 pub struct EventPoller {
   socket: udev::MonitorSocket,
-  last_periodic_check: Instant,
+  watched_fds: Vec<(RawFd, Event)>,
+  signals: Option<SignalFd>,
+  next_periodic_check: Instant,
   periodic_interval: Duration,
 }
 
@@ -134,57 +209,127 @@ impl EventPoller {
   pub fn new(interval_duration_s: u8) -> io::Result<Self> {
     let socket = MonitorBuilder::new()?
       .match_subsystem("power_supply")?
+      .match_subsystem("cpu")?
       .listen()?;
 
     Ok(Self {
       socket,
-      last_periodic_check: Instant::now(),
+      watched_fds: vec![],
+      signals: None,
+      next_periodic_check: Instant::now() + Duration::from_secs(interval_duration_s.into()),
       periodic_interval: Duration::from_secs(interval_duration_s.into()),
     })
   }
 
+  /// Delivers SIGTERM and SIGINT as `Event::Shutdown` and SIGHUP as `Event::Hangup` instead of
+  /// letting them kill the process.
+  pub fn handle_signals(&mut self) -> io::Result<()> {
+    self.signals = Some(SignalFd::new(&[libc::SIGTERM, libc::SIGINT, libc::SIGHUP])?);
+    Ok(())
+  }
+
+  /// Makes `poll_events` return `event` whenever `fd` becomes readable.
+  pub fn watch_fd(&mut self, fd: RawFd, event: Event) {
+    self.watched_fds.push((fd, event));
+  }
+
+  pub fn set_periodic_interval(&mut self, interval_duration_s: u8) {
+    let interval = Duration::from_secs(interval_duration_s.into());
+    self.next_periodic_check = Instant::now() + interval;
+    self.periodic_interval = interval;
+  }
+
+  /// Moves the next `Event::PeriodicCheck` to `delay` from now, to retry after an error.
+  pub fn retry_in(&mut self, delay: Duration) {
+    self.next_periodic_check = Instant::now() + delay;
+  }
+
+  pub fn unwatch_fd(&mut self, fd: RawFd) {
+    self.watched_fds.retain(|(watched, _)| *watched != fd);
+  }
+
   pub fn poll_events(&mut self) -> Event {
-    let elapsed = self.last_periodic_check.elapsed();
-    let timeout_ms = if elapsed >= self.periodic_interval {
-      0
-    } else {
-      (self.periodic_interval - elapsed).as_millis() as i32
-    };
+    let timeout_ms = self
+      .next_periodic_check
+      .saturating_duration_since(Instant::now())
+      .as_millis() as i32;
 
-    let mut fds = [libc::pollfd {
+    let mut fds = vec![libc::pollfd {
       fd: self.socket.as_raw_fd(),
       events: libc::POLLIN,
       revents: 0,
     }];
+    // -1 is ignored by poll
+    fds.push(libc::pollfd {
+      fd: self.signals.as_ref().map_or(-1, |s| s.as_raw_fd()),
+      events: libc::POLLIN,
+      revents: 0,
+    });
+    for (fd, _) in &self.watched_fds {
+      fds.push(libc::pollfd {
+        fd: *fd,
+        events: libc::POLLIN,
+        revents: 0,
+      });
+    }
 
-    let result = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout_ms) };
+    let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
 
     if result < 0 {
       return Event::Error(io::Error::last_os_error().to_string());
     }
 
-    if self.last_periodic_check.elapsed() >= self.periodic_interval {
-      self.last_periodic_check = Instant::now();
+    // checked first, a busy control socket mustn't delay a shutdown
+    if fds[1].revents & libc::POLLIN != 0
+      && let Some(signals) = &self.signals
+    {
+      let signals = signals.read();
+      if let Some(signal) = signals.iter().find(|s| **s != libc::SIGHUP) {
+        return Event::Shutdown(signal_name(*signal));
+      }
+      if !signals.is_empty() {
+        return Event::Hangup;
+      }
+    }
+
+    for (pollfd, (_, event)) in fds[2..].iter().zip(&self.watched_fds) {
+      // a hang up has to be reported too, otherwise the owner never notices and poll spins
+      if pollfd.revents & (libc::POLLIN | libc::POLLHUP) != 0 {
+        return event.clone();
+      }
+    }
+
+    if Instant::now() >= self.next_periodic_check {
+      self.next_periodic_check = Instant::now() + self.periodic_interval;
       return Event::PeriodicCheck;
     }
 
     for event in self.socket.iter() {
-      if event.event_type() == udev::EventType::Change {
-        if let Some(name) = event.property_value("POWER_SUPPLY_NAME") {
-          let name_str = name.to_str().unwrap_or("");
-
-          if name_str == "ACAD" || name_str == "AC" || name_str == "ADP1" || name_str == "AC0" {
-            if let Some(online) = event.property_value("POWER_SUPPLY_ONLINE") {
-              let online_str = online.to_str().unwrap_or("");
-
-              match online_str {
-                "1" => return Event::PowerInPlug,
-                "0" => return Event::PowerUnPlug,
-                _ => return Event::Unknown,
-              }
+      if event.subsystem().and_then(|s| s.to_str()) == Some("cpu") {
+        return Event::CpuHotplug;
+      }
+
+      let supply_type = event
+        .property_value("POWER_SUPPLY_TYPE")
+        .and_then(|t| t.to_str())
+        .unwrap_or("");
+      if !AdapterStates::is_adapter_type(supply_type) {
+        continue;
+      }
+
+      match event.event_type() {
+        udev::EventType::Add | udev::EventType::Change => {
+          if let Some(online) = event.property_value("POWER_SUPPLY_ONLINE") {
+            match online.to_str().unwrap_or("") {
+              "1" => return Event::PowerInPlug,
+              "0" => return Event::PowerUnPlug,
+              _ => return Event::Unknown,
             }
           }
         }
+        // usb-c sources may disappear entirely when a dock is unplugged
+        udev::EventType::Remove => return Event::PowerUnPlug,
+        _ => {}
       }
     }
 
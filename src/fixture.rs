@@ -0,0 +1,329 @@
+use crate::cpu::{CpuType, CPUFREQ_PATH};
+use crate::sysfs::SysfsRoot;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static FIXTURE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A fake /sys and /proc tree in a temp dir, removed again on drop.
+pub struct SysfsFixture {
+  dir: PathBuf,
+}
+
+impl Drop for SysfsFixture {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.dir);
+  }
+}
+
+impl SysfsFixture {
+  pub fn new() -> io::Result<Self> {
+    let dir = env::temp_dir().join(format!(
+      "powereg-fixture-{}-{}",
+      process::id(),
+      FIXTURE_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    if dir.exists() {
+      fs::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+
+    Ok(Self { dir })
+  }
+
+  /// ThinkPad with an AMD cpu (amd_pstate), two batteries and a Mains adapter.
+  pub fn thinkpad_amd() -> io::Result<Self> {
+    let fixture = Self::new()?;
+    fixture.add_cpus(&CpuType::AMD, 8)?;
+    fixture.add_amd_pstate()?;
+    fixture.add_dmi("LENOVO", "20L5CTO1WW", "ThinkPad T480")?;
+    fixture.add_battery("BAT0", 85, "Charging")?;
+    fixture.add_thinkpad_thresholds("BAT0")?;
+    fixture.add_battery("BAT1", 60, "Charging")?;
+    fixture.add_thinkpad_thresholds("BAT1")?;
+    fixture.add_adapter("AC", "Mains", true)?;
+    fixture.add_platform_profile()?;
+    fixture.add_hwmon("k10temp", &[("Tctl", 52000)])?;
+    fixture.add_hwmon("thinkpad", &[("CPU", 50000), ("GPU", 0)])?;
+    Ok(fixture)
+  }
+
+  /// Intel laptop (intel_pstate) using the generic charge_control attributes and a USB-C adapter.
+  pub fn intel_laptop() -> io::Result<Self> {
+    let fixture = Self::new()?;
+    fixture.add_cpus(&CpuType::Intel, 4)?;
+    fixture.add_intel_pstate()?;
+    fixture.add_dmi("Dell Inc.", "XPS 13 9310", "")?;
+    fixture.add_battery("BAT0", 50, "Discharging")?;
+    fixture.write(
+      "/sys/class/power_supply/BAT0/charge_control_end_threshold",
+      "100",
+    )?;
+    fixture.add_adapter("ucsi-source-psy-USBC000:001", "USB", false)?;
+    fixture.add_platform_profile()?;
+    fixture.add_rapl_constraint("intel-rapl:0", 0, "long_term", 15_000_000, 28_000_000)?;
+    fixture.add_rapl_constraint("intel-rapl:0", 1, "short_term", 25_000_000, 0)?;
+    fixture.add_hwmon("coretemp", &[("Package id 0", 61000), ("Core 0", 58000)])?;
+    Ok(fixture)
+  }
+
+  /// Desktop without battery or adapter.
+  pub fn desktop_amd() -> io::Result<Self> {
+    let fixture = Self::new()?;
+    fixture.add_cpus(&CpuType::AMD, 16)?;
+    fixture.add_amd_pstate()?;
+    fixture.add_platform_profile()?;
+    Ok(fixture)
+  }
+
+  pub fn root(&self) -> SysfsRoot {
+    SysfsRoot::new(&self.dir)
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// Writes `contents` to an absolute path (e.g. "/sys/...") inside the fixture.
+  pub fn write(&self, path: &str, contents: &str) -> io::Result<()> {
+    let path = self.root().path(path);
+    if let Some(parent) = Path::new(&path).parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", contents))
+  }
+
+  pub fn read(&self, path: &str) -> io::Result<String> {
+    Ok(
+      fs::read_to_string(self.root().path(path))?
+        .trim()
+        .to_string(),
+    )
+  }
+
+  pub fn remove(&self, path: &str) -> io::Result<()> {
+    let path = self.root().path(path);
+    if Path::new(&path).is_dir() {
+      fs::remove_dir_all(path)
+    } else {
+      fs::remove_file(path)
+    }
+  }
+
+  pub fn add_cpus(&self, cpu_type: &CpuType, n: usize) -> io::Result<()> {
+    let vendor = match cpu_type {
+      CpuType::AMD => "AuthenticAMD",
+      CpuType::Intel => "GenuineIntel",
+      CpuType::Unknown => "Unknown",
+    };
+    let mut cpuinfo = String::new();
+    let mut stat = String::from("cpu  4705 150 1120 16250 520 0 27 0 0 0");
+    for i in 0..n {
+      cpuinfo.push_str(&format!("processor\t: {}\nvendor_id\t: {}\n\n", i, vendor));
+      stat.push_str(&format!("\ncpu{} 588 18 140 2031 65 0 3 0 0 0", i));
+    }
+    self.write("/proc/cpuinfo", &cpuinfo)?;
+    self.write("/proc/stat", &stat)?;
+
+    // one policy per core like on most x86 machines, cpuN/cpufreq links to it
+    for i in 0..n {
+      let cpufreq = format!("{}/policy{}", CPUFREQ_PATH, i);
+      self.write(&format!("{}/related_cpus", cpufreq), &i.to_string())?;
+      self.write(&format!("{}/affected_cpus", cpufreq), &i.to_string())?;
+      self.write(
+        &format!("{}/scaling_available_governors", cpufreq),
+        "performance powersave",
+      )?;
+      self.write(&format!("{}/scaling_governor", cpufreq), "powersave")?;
+      self.write(&format!("{}/scaling_cur_freq", cpufreq), "1400000")?;
+      self.write(&format!("{}/scaling_min_freq", cpufreq), "400000")?;
+      self.write(&format!("{}/scaling_max_freq", cpufreq), "4000000")?;
+      self.write(&format!("{}/cpuinfo_min_freq", cpufreq), "400000")?;
+      self.write(&format!("{}/cpuinfo_max_freq", cpufreq), "4000000")?;
+      self.write(
+        &format!("{}/energy_performance_available_preferences", cpufreq),
+        "default performance balance_performance balance_power power",
+      )?;
+      self.write(
+        &format!("{}/energy_performance_preference", cpufreq),
+        "balance_performance",
+      )?;
+
+      let cpu = format!("/sys/devices/system/cpu/cpu{}", i);
+      self.write(&format!("{}/online", cpu), "1")?;
+      std::os::unix::fs::symlink(
+        format!("../cpufreq/policy{}", i),
+        self.root().path(&format!("{}/cpufreq", cpu)),
+      )?;
+    }
+
+    self.write("/sys/class/thermal/thermal_zone0/type", "acpitz")?;
+    self.write("/sys/class/thermal/thermal_zone0/temp", "45000")?;
+    self.add_rapl_zone("intel-rapl:0", "package-0", 1_000_000)
+  }
+
+  /// A powercap zone (`intel-rapl:0`) or subzone (`intel-rapl:0:1`), energy in µJ.
+  pub fn add_rapl_zone(&self, id: &str, name: &str, energy_uj: u64) -> io::Result<()> {
+    let dir = format!("/sys/class/powercap/{}", id);
+    self.write(&format!("{}/name", dir), name)?;
+    self.write(&format!("{}/energy_uj", dir), &energy_uj.to_string())?;
+    self.write(&format!("{}/max_energy_range_uj", dir), "262143328850")
+  }
+
+  /// What the kernel does on hotplug: the core's policy loses it from `affected_cpus`.
+  pub fn set_cpu_online(&self, cpu: usize, online: bool) -> io::Result<()> {
+    self.write(
+      &format!("/sys/devices/system/cpu/cpu{}/online", cpu),
+      if online { "1" } else { "0" },
+    )?;
+    let affected = if online {
+      cpu.to_string()
+    } else {
+      String::new()
+    };
+    self.write(
+      &format!("{}/policy{}/affected_cpus", CPUFREQ_PATH, cpu),
+      &affected,
+    )
+  }
+
+  /// The next `hwmonN` with one labeled `tempN_input` per sensor, temperatures in millidegrees.
+  pub fn add_hwmon(&self, name: &str, sensors: &[(&str, i64)]) -> io::Result<()> {
+    let n = fs::read_dir(self.root().path("/sys/class/hwmon"))
+      .map(|entries| entries.count())
+      .unwrap_or(0);
+    let dir = format!("/sys/class/hwmon/hwmon{}", n);
+    self.write(&format!("{}/name", dir), name)?;
+    for (i, (label, temp)) in sensors.iter().enumerate() {
+      self.write(&format!("{}/temp{}_label", dir, i + 1), label)?;
+      self.write(&format!("{}/temp{}_input", dir, i + 1), &temp.to_string())?;
+    }
+    Ok(())
+  }
+
+  /// `constraint_N_*` of a powercap zone, power in µW, a max of 0 is what firmware reports when
+  /// it doesn't say.
+  pub fn add_rapl_constraint(
+    &self,
+    id: &str,
+    n: usize,
+    name: &str,
+    limit_uw: u64,
+    max_uw: u64,
+  ) -> io::Result<()> {
+    let dir = format!("/sys/class/powercap/{}", id);
+    self.write(&format!("{}/constraint_{}_name", dir, n), name)?;
+    self.write(
+      &format!("{}/constraint_{}_power_limit_uw", dir, n),
+      &limit_uw.to_string(),
+    )?;
+    self.write(
+      &format!("{}/constraint_{}_max_power_uw", dir, n),
+      &max_uw.to_string(),
+    )
+  }
+
+  pub fn add_amd_pstate(&self) -> io::Result<()> {
+    self.write("/sys/devices/system/cpu/amd_pstate/status", "active")?;
+    self.write("/sys/devices/system/cpu/cpufreq/boost", "1")
+  }
+
+  pub fn add_intel_pstate(&self) -> io::Result<()> {
+    self.write("/sys/devices/system/cpu/intel_pstate/status", "active")?;
+    self.write("/sys/devices/system/cpu/intel_pstate/no_turbo", "0")?;
+    self.write("/sys/devices/system/cpu/intel_pstate/min_perf_pct", "20")?;
+    self.write("/sys/devices/system/cpu/intel_pstate/max_perf_pct", "100")
+  }
+
+  pub fn add_dmi(&self, vendor: &str, product_name: &str, product_version: &str) -> io::Result<()> {
+    self.write("/sys/class/dmi/id/sys_vendor", vendor)?;
+    self.write("/sys/class/dmi/id/product_name", product_name)?;
+    self.write("/sys/class/dmi/id/product_version", product_version)
+  }
+
+  pub fn add_battery(&self, name: &str, capacity: usize, status: &str) -> io::Result<()> {
+    let dir = format!("/sys/class/power_supply/{}", name);
+    let energy_full: usize = 50_000_000;
+    self.write(&format!("{}/type", dir), "Battery")?;
+    self.write(&format!("{}/scope", dir), "System")?;
+    self.write(&format!("{}/status", dir), status)?;
+    self.write(&format!("{}/capacity", dir), &capacity.to_string())?;
+    self.write(&format!("{}/power_now", dir), "7500000")?;
+    self.write(&format!("{}/energy_full", dir), &energy_full.to_string())?;
+    self.write(
+      &format!("{}/energy_now", dir),
+      &(energy_full * capacity / 100).to_string(),
+    )
+  }
+
+  /// A battery reporting charge (µAh, µA) instead of energy: 4 Ah of 4.5 Ah design at 11.4 V,
+  /// drawing 2 A at 12 V.
+  pub fn add_charge_battery(&self, name: &str, capacity: usize, status: &str) -> io::Result<()> {
+    let dir = format!("/sys/class/power_supply/{}", name);
+    let charge_full: usize = 4_000_000;
+    self.write(&format!("{}/type", dir), "Battery")?;
+    self.write(&format!("{}/scope", dir), "System")?;
+    self.write(&format!("{}/status", dir), status)?;
+    self.write(&format!("{}/capacity", dir), &capacity.to_string())?;
+    self.write(&format!("{}/current_now", dir), "2000000")?;
+    self.write(&format!("{}/voltage_now", dir), "12000000")?;
+    self.write(&format!("{}/voltage_min_design", dir), "11400000")?;
+    self.write(&format!("{}/charge_full", dir), &charge_full.to_string())?;
+    self.write(&format!("{}/charge_full_design", dir), "4500000")?;
+    self.write(
+      &format!("{}/charge_now", dir),
+      &(charge_full * capacity / 100).to_string(),
+    )
+  }
+
+  pub fn add_thinkpad_thresholds(&self, battery: &str) -> io::Result<()> {
+    let dir = format!("/sys/class/power_supply/{}", battery);
+    self.write(&format!("{}/charge_control_start_threshold", dir), "0")?;
+    self.write(&format!("{}/charge_control_end_threshold", dir), "100")
+  }
+
+  pub fn add_charge_behaviour(&self, battery: &str) -> io::Result<()> {
+    self.write(
+      &format!("/sys/class/power_supply/{}/charge_behaviour", battery),
+      "[auto] inhibit-charge force-discharge",
+    )
+  }
+
+  /// ideapad_acpi's machine wide switch, stops charging at 60% when on.
+  pub fn add_ideapad_conservation_mode(&self) -> io::Result<()> {
+    self.write(
+      "/sys/bus/platform/drivers/ideapad_acpi/VPC2004:00/conservation_mode",
+      "0",
+    )
+  }
+
+  /// samsung-laptop's switch, stops charging at 80% when on.
+  pub fn add_samsung_battery_life_extender(&self) -> io::Result<()> {
+    self.write("/sys/devices/platform/samsung/battery_life_extender", "0")
+  }
+
+  pub fn add_adapter(&self, name: &str, supply_type: &str, online: bool) -> io::Result<()> {
+    let dir = format!("/sys/class/power_supply/{}", name);
+    self.write(&format!("{}/type", dir), supply_type)?;
+    self.write(&format!("{}/online", dir), if online { "1" } else { "0" })
+  }
+
+  pub fn set_adapter_online(&self, name: &str, online: bool) -> io::Result<()> {
+    self.write(
+      &format!("/sys/class/power_supply/{}/online", name),
+      if online { "1" } else { "0" },
+    )
+  }
+
+  pub fn add_platform_profile(&self) -> io::Result<()> {
+    self.write(
+      "/sys/firmware/acpi/platform_profile_choices",
+      "low-power balanced performance",
+    )?;
+    self.write("/sys/firmware/acpi/platform_profile", "balanced")
+  }
+}
@@ -0,0 +1,112 @@
+use crate::battery::{BatteryStates, BatteryStatesError, POWER_SUPPLY_PATH};
+use crate::sysfs::SysfsRoot;
+use std::fmt;
+use std::fs;
+
+/// What a battery reports about itself and its wear, read once for `powereg battery`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatteryHealth {
+  pub name: String,
+  pub manufacturer: Option<String>,
+  pub model_name: Option<String>,
+  /// "Li-ion", "Li-poly"
+  pub technology: Option<String>,
+  pub cycle_count: Option<u64>,
+  /// µV
+  pub voltage_now: Option<u64>,
+  pub voltage_min_design: Option<u64>,
+  /// µWh, converted from `charge_full*` at the design voltage for batteries that report charge
+  pub energy_full: Option<u64>,
+  pub energy_full_design: Option<u64>,
+  /// µAh, only on batteries that report charge
+  pub charge_full: Option<u64>,
+  pub charge_full_design: Option<u64>,
+}
+
+impl BatteryHealth {
+  pub fn read(root: &SysfsRoot, name: &str) -> Self {
+    let dir = root.path(&format!("{}/{}", POWER_SUPPLY_PATH, name));
+    let read = |file: &str| {
+      fs::read_to_string(format!("{dir}/{file}"))
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    };
+    let read_int = |file: &str| read(file).and_then(|v| v.parse::<u64>().ok());
+
+    let voltage_min_design = read_int("voltage_min_design");
+    let voltage_now = read_int("voltage_now");
+    let charge_full = read_int("charge_full");
+    let charge_full_design = read_int("charge_full_design");
+    let to_energy =
+      |charge: Option<u64>| Some(charge? * voltage_min_design.or(voltage_now)? / 1_000_000);
+
+    Self {
+      name: name.to_string(),
+      manufacturer: read("manufacturer"),
+      model_name: read("model_name"),
+      technology: read("technology"),
+      // firmware without a counter reports 0
+      cycle_count: read_int("cycle_count").filter(|c| *c > 0),
+      voltage_now,
+      voltage_min_design,
+      energy_full: read_int("energy_full").or_else(|| to_energy(charge_full)),
+      energy_full_design: read_int("energy_full_design").or_else(|| to_energy(charge_full_design)),
+      charge_full,
+      charge_full_design,
+    }
+  }
+
+  /// Every system battery, see `BatteryStates::detect_batteries`.
+  pub fn read_all(root: &SysfsRoot) -> Result<Vec<Self>, BatteryStatesError> {
+    Ok(
+      BatteryStates::detect_batteries(root)?
+        .iter()
+        .map(|name| Self::read(root, name))
+        .collect(),
+    )
+  }
+
+  /// Percent of the design capacity that's lost, from charge when the battery reports it since
+  /// that isn't skewed by the conversion voltage.
+  pub fn wear(&self) -> Option<f64> {
+    let (full, design) = match (self.charge_full, self.charge_full_design) {
+      (Some(full), Some(design)) => (full, design),
+      _ => (self.energy_full?, self.energy_full_design?),
+    };
+    if design == 0 {
+      return None;
+    }
+    Some((100.0 - full as f64 / design as f64 * 100.0).max(0.0))
+  }
+}
+
+impl fmt::Display for BatteryHealth {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let unknown = || "unknown".to_string();
+    let wh = |uwh: Option<u64>| uwh.map_or_else(unknown, |v| format!("{:.2} Wh", v as f64 / 1e6));
+    let volts = |uv: Option<u64>| uv.map_or_else(unknown, |v| format!("{:.2} V", v as f64 / 1e6));
+
+    write!(
+      f,
+      "{}:
+    manufacturer: {}
+    model: {}
+    technology: {}
+    cycle count: {}
+    voltage: {} (design min {})
+    full capacity: {} of {} design
+    wear: {}",
+      self.name,
+      self.manufacturer.clone().unwrap_or_else(unknown),
+      self.model_name.clone().unwrap_or_else(unknown),
+      self.technology.clone().unwrap_or_else(unknown),
+      self.cycle_count.map_or_else(unknown, |c| c.to_string()),
+      volts(self.voltage_now),
+      volts(self.voltage_min_design),
+      wh(self.energy_full),
+      wh(self.energy_full_design),
+      self.wear().map_or_else(unknown, |w| format!("{:.1}%", w)),
+    )
+  }
+}
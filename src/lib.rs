@@ -1,6 +1,26 @@
+pub mod adapter;
 pub mod battery;
+pub mod battery_backend;
+pub mod calibration;
+pub mod charge_full;
+pub mod control;
 pub mod cpu;
+pub mod cpu_backend;
+pub mod daemon;
+pub mod dbus;
 pub mod events;
+pub mod fixture;
+pub mod health;
+pub mod load;
+pub mod policy;
+pub mod power_profiles;
+pub mod powercap;
+pub mod profile;
+pub mod reload;
 pub mod setup;
+pub mod signals;
+pub mod snapshot;
+pub mod sysfs;
 pub mod system_state;
+pub mod thermal;
 pub mod utils;
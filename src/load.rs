@@ -0,0 +1,180 @@
+use crate::cpu::CpuStatesError;
+use crate::utils::PersFd;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// Jiffies from a `/proc/stat` cpu line. guest and guest_nice are already counted in user and
+/// nice, so they're left out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CpuTimes {
+  pub user: u64,
+  pub nice: u64,
+  pub system: u64,
+  pub idle: u64,
+  pub iowait: u64,
+  pub irq: u64,
+  pub softirq: u64,
+  pub steal: u64,
+}
+
+impl CpuTimes {
+  fn parse(fields: &[&str]) -> Result<Self, CpuStatesError> {
+    if fields.len() < 4 {
+      return Err(CpuStatesError::InvalidProcStat);
+    }
+    let mut values = [0u64; 8];
+    for (value, field) in values.iter_mut().zip(fields) {
+      *value = field.parse()?;
+    }
+    let [user, nice, system, idle, iowait, irq, softirq, steal] = values;
+
+    Ok(Self {
+      user,
+      nice,
+      system,
+      idle,
+      iowait,
+      irq,
+      softirq,
+      steal,
+    })
+  }
+
+  fn total(&self) -> u64 {
+    self.user
+      + self.nice
+      + self.system
+      + self.idle
+      + self.iowait
+      + self.irq
+      + self.softirq
+      + self.steal
+  }
+}
+
+/// The `cpu` line and every `cpuN` line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcStat {
+  pub total: CpuTimes,
+  pub cores: BTreeMap<usize, CpuTimes>,
+}
+
+impl ProcStat {
+  pub fn parse(proc_stat: &str) -> Result<Self, CpuStatesError> {
+    let mut total = None;
+    let mut cores = BTreeMap::new();
+
+    for line in proc_stat.lines() {
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      let Some((name, fields)) = fields.split_first() else {
+        continue;
+      };
+      let Some(core) = name.strip_prefix("cpu") else {
+        continue;
+      };
+      let times = CpuTimes::parse(fields)?;
+      match core {
+        "" => total = Some(times),
+        core => {
+          cores.insert(
+            core.parse().map_err(|_| CpuStatesError::InvalidProcStat)?,
+            times,
+          );
+        }
+      }
+    }
+
+    Ok(Self {
+      total: total.ok_or(CpuStatesError::EmptyProcStat)?,
+      cores,
+    })
+  }
+}
+
+/// Percent of the time between two samples. iowait counts as idle, the cpu was free to run
+/// something else.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CpuLoad {
+  pub busy: f64,
+  /// user and nice
+  pub user: f64,
+  /// system, irq and softirq
+  pub system: f64,
+  pub iowait: f64,
+  pub steal: f64,
+}
+
+impl CpuLoad {
+  pub fn between(prev: &CpuTimes, now: &CpuTimes) -> Self {
+    let delta = |now: u64, prev: u64| now.saturating_sub(prev) as f64;
+    let total = delta(now.total(), prev.total());
+    if total == 0.0 {
+      return Self::default();
+    }
+    let pct = |jiffies: f64| jiffies / total * 100.0;
+
+    let idle = delta(now.idle, prev.idle) + delta(now.iowait, prev.iowait);
+    Self {
+      busy: pct((total - idle).max(0.0)),
+      user: pct(delta(now.user, prev.user) + delta(now.nice, prev.nice)),
+      system: pct(
+        delta(now.system, prev.system)
+          + delta(now.irq, prev.irq)
+          + delta(now.softirq, prev.softirq),
+      ),
+      iowait: pct(delta(now.iowait, prev.iowait)),
+      steal: pct(delta(now.steal, prev.steal)),
+    }
+  }
+}
+
+/// Keeps the previous `/proc/stat` between `sample` calls (the daemon's periodic checks), so the
+/// load covers the whole interval instead of a short sleep inside the reader.
+pub struct LoadSampler {
+  proc_stat: RefCell<PersFd>,
+  last: RefCell<Option<ProcStat>>,
+  load: RefCell<Option<(CpuLoad, BTreeMap<usize, CpuLoad>)>>,
+}
+
+impl LoadSampler {
+  pub fn new(proc_stat: PersFd) -> Self {
+    Self {
+      proc_stat: RefCell::new(proc_stat),
+      last: RefCell::new(None),
+      load: RefCell::new(None),
+    }
+  }
+
+  pub fn sample(&self) -> Result<(), CpuStatesError> {
+    let now = ProcStat::parse(&self.proc_stat.borrow_mut().read_value()?)?;
+
+    if let Some(prev) = self.last.borrow().as_ref() {
+      let cores = now
+        .cores
+        .iter()
+        .filter_map(|(core, times)| {
+          // a core that just came online has nothing to compare against
+          let prev = prev.cores.get(core)?;
+          Some((*core, CpuLoad::between(prev, times)))
+        })
+        .collect();
+      *self.load.borrow_mut() = Some((CpuLoad::between(&prev.total, &now.total), cores));
+    }
+    *self.last.borrow_mut() = Some(now);
+    Ok(())
+  }
+
+  /// Between the last two samples, None until there were two.
+  pub fn load(&self) -> Option<CpuLoad> {
+    self.load.borrow().as_ref().map(|(load, _)| *load)
+  }
+
+  pub fn core_loads(&self) -> BTreeMap<usize, CpuLoad> {
+    self
+      .load
+      .borrow()
+      .as_ref()
+      .map(|(_, cores)| cores.clone())
+      .unwrap_or_default()
+  }
+}
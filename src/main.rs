@@ -1,15 +1,22 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use powereg::control::{send_command, Command, SOCKET_PATH};
+use powereg::daemon::Daemon;
 use powereg::events::EventPoller;
+use powereg::health::BatteryHealth;
 use powereg::setup::{check_running_daemon_mode, install_daemon, uninstall_daemon};
-use powereg::system_state::SystemState;
+use powereg::snapshot::{restore_snapshot, SNAPSHOT_PATH};
+use powereg::sysfs::SysfsRoot;
+use powereg::system_state::{State, SystemState};
+use powereg::thermal::Thermal;
 use powereg::utils::{Config, StyledString};
+use std::time::Instant;
 
+/// refresh rate of `--monitor`, the daemon's is `policy.loop_duration_s`
 const LOOP_DURATION: u8 = 3;
 
-#[derive(Parser, Debug)]
-#[command(version, about)]
+#[derive(clap::Args, Debug)]
 #[group(id = "mode", required = true, multiple = false)]
-struct Args {
+struct Mode {
   #[arg(long, help = "Monitor running daemon and system stats")]
   pub monitor: bool,
   #[arg(long, help = "Run in live mode")]
@@ -23,14 +30,111 @@ struct Args {
   pub uninstall: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum ClientCommand {
+  #[command(about = "Print the running daemon's state and applied settings")]
+  Status,
+  #[command(about = "Hold a state (powersave, balanced, performance) regardless of events")]
+  Force {
+    #[arg(value_parser = parse_state)]
+    state: State,
+    #[arg(
+      long,
+      value_name = "SECONDS",
+      help = "Only hold the state for this long"
+    )]
+    duration: Option<u64>,
+  },
+  #[command(about = "Pause automatic control, keeping the current settings")]
+  Pause,
+  #[command(about = "Resume automatic control, ending a forced state")]
+  Resume,
+  #[command(about = "Reload the config file")]
+  Reload,
+  #[command(about = "Restore the settings from before powereg and pause automatic control")]
+  Restore,
+  #[command(about = "List the temperature sensors and which one is used for the cpu")]
+  Sensors,
+  #[command(about = "Report battery health: capacity, wear and cycle count")]
+  Battery,
+  #[command(
+    name = "charge-full",
+    about = "Charge to 100% once, then go back to the configured charge thresholds"
+  )]
+  ChargeFull,
+  #[command(
+    about = "Discharge the battery to PERCENT, charge it to 100% and restore the charge thresholds"
+  )]
+  Calibrate {
+    #[arg(
+      value_parser = clap::value_parser!(u8).range(0..100),
+      required_unless_present = "abort"
+    )]
+    percent: Option<u8>,
+    #[arg(long, conflicts_with = "percent", help = "Abort a running calibration")]
+    abort: bool,
+  },
+}
+
+fn parse_state(s: &str) -> Result<State, String> {
+  State::from_string(s).ok_or(format!("unknown state '{}'", s))
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, subcommand_negates_reqs = true)]
+struct Args {
+  #[command(subcommand)]
+  pub command: Option<ClientCommand>,
+
+  #[command(flatten)]
+  pub mode: Mode,
+
+  #[arg(
+    long,
+    value_name = "PATH",
+    help = "Run against a simulated /sys and /proc tree instead of the real system"
+  )]
+  pub sysfs_root: Option<String>,
+}
+
 fn main() {
-  if !unsafe { libc::geteuid() == 0 } {
+  let args = Args::parse();
+  let root = args
+    .sysfs_root
+    .as_ref()
+    .map(SysfsRoot::new)
+    .unwrap_or_default();
+
+  // only read sysfs, don't need root or the daemon
+  match args.command {
+    Some(ClientCommand::Sensors) => {
+      println!("{}", Thermal::discover(&root));
+      return;
+    }
+    Some(ClientCommand::Battery) => {
+      print_battery_health(&root);
+      return;
+    }
+    _ => {}
+  }
+
+  if root.is_system() && !unsafe { libc::geteuid() == 0 } {
     eprintln!("{}", "Need to run with root privileges!".red());
     return;
   }
 
-  let system_state = SystemState::init().unwrap();
-  system_state.post_init().unwrap();
+  if let Some(command) = args.command {
+    run_client_command(command);
+    return;
+  }
+
+  let system_state = match SystemState::init(&root) {
+    Ok(system_state) => system_state,
+    Err(e) => {
+      eprintln!("{} {}", "Failed to read the system state:".red(), e);
+      std::process::exit(1);
+    }
+  };
   if !system_state.linux {
     eprintln!("{}", "Need to be running on Linux!".red());
     return;
@@ -38,8 +142,7 @@ fn main() {
 
   // TODO: listen for 'q' to quit out
 
-  let args = Args::parse();
-  if args.monitor {
+  if args.mode.monitor {
     if !check_running_daemon_mode().unwrap() {
       println!("{}", "powereg not running in daemon mode!".red());
       println!("{}", "\tuse 'sudo powereg --install'".red());
@@ -47,12 +150,24 @@ fn main() {
 
     let mut poller = EventPoller::new(LOOP_DURATION).unwrap();
     loop {
+      if let Err(e) = system_state.cpu_states.sample(Instant::now()) {
+        eprintln!("{} {}", "Failed to sample the cpu:".red(), e);
+      }
       print!("\x1B[2J\x1B[1;1H");
       println!("{}", system_state);
+      match send_command(SOCKET_PATH, Command::Status) {
+        Ok(response) => {
+          if let Some(status) = response.status {
+            println!("{}", status);
+          }
+        }
+        Err(e) => println!("{} {}", "Could not reach daemon:".red(), e),
+      }
       let _ = poller.poll_events();
     }
-  } else if args.live {
-    Config::setup_config(&system_state);
+  } else if args.mode.live {
+    save_snapshot(&system_state);
+    let config = Config::setup_config(&system_state);
 
     if check_running_daemon_mode().unwrap() {
       println!("{}", "Powereg already running in daemon mode!".red());
@@ -60,22 +175,14 @@ fn main() {
       return;
     }
 
-    let mut poller = EventPoller::new(LOOP_DURATION).unwrap();
-    loop {
-      print!("\x1B[2J\x1B[1;1H");
-      println!("{}", system_state);
-      let event = poller.poll_events();
-      event.handle_event(&system_state).unwrap();
-    }
-  } else if args.daemon {
-    Config::setup_config(&system_state);
+    run_daemon(&system_state, config, true);
+  } else if args.mode.daemon {
+    save_snapshot(&system_state);
+    let config = Config::setup_config(&system_state);
 
-    let mut poller = EventPoller::new(LOOP_DURATION).unwrap();
-    loop {
-      let event = poller.poll_events();
-      event.handle_event(&system_state).unwrap();
-    }
-  } else if args.install {
+    run_daemon(&system_state, config, false);
+  } else if args.mode.install {
+    save_snapshot(&system_state);
     Config::setup_config(&system_state);
 
     if check_running_daemon_mode().unwrap() {
@@ -84,7 +191,7 @@ fn main() {
     }
 
     install_daemon().unwrap();
-  } else if args.uninstall {
+  } else if args.mode.uninstall {
     if !check_running_daemon_mode().unwrap() {
       println!("{}", "Powereg is not running in daemon mode!".red());
       return;
@@ -93,3 +200,82 @@ fn main() {
     uninstall_daemon().unwrap();
   }
 }
+
+/// Exits non-zero when the daemon stops on an error, so systemd restarts it.
+fn run_daemon(system_state: &SystemState, config: Option<Config>, live: bool) {
+  let config_path = Config::get_config_path().unwrap_or_default();
+  let result =
+    Daemon::new(system_state, &config_path, config, live).and_then(|mut daemon| daemon.run());
+  match result {
+    Ok(_) => println!("powereg stopped"),
+    Err(e) => {
+      eprintln!("{} {}", "powereg stopped:".red(), e);
+      std::process::exit(1);
+    }
+  }
+}
+
+/// Before the config is applied, so the snapshot holds the machine's own settings.
+fn save_snapshot(system_state: &SystemState) {
+  if let Err(e) = system_state.save_snapshot() {
+    eprintln!("{} {}", "Failed to save the original settings:".red(), e);
+  }
+}
+
+fn print_battery_health(root: &SysfsRoot) {
+  match BatteryHealth::read_all(root) {
+    Ok(batteries) if batteries.is_empty() => println!("{}", "No battery detected".yellow()),
+    Ok(batteries) => {
+      for battery in batteries {
+        println!("{}", battery);
+      }
+    }
+    Err(e) => eprintln!("{} {}", "Failed to read the batteries:".red(), e),
+  }
+}
+
+fn run_client_command(command: ClientCommand) {
+  let restore = matches!(command, ClientCommand::Restore);
+  let command = match command {
+    ClientCommand::Status => Command::Status,
+    ClientCommand::Force { state, duration } => Command::ForceProfile {
+      state,
+      duration_s: duration,
+    },
+    ClientCommand::Pause => Command::Pause,
+    ClientCommand::Resume => Command::Resume,
+    ClientCommand::Reload => Command::Reload,
+    ClientCommand::Restore => Command::Restore,
+    ClientCommand::ChargeFull => Command::ChargeFull,
+    ClientCommand::Calibrate { abort: true, .. } => Command::AbortCalibration,
+    ClientCommand::Calibrate { percent, .. } => Command::Calibrate {
+      target: percent.unwrap_or_default().into(),
+    },
+    ClientCommand::Sensors | ClientCommand::Battery => {
+      unreachable!("read from sysfs without the daemon")
+    }
+  };
+
+  match send_command(SOCKET_PATH, command) {
+    Ok(response) => match response.status {
+      Some(status) => println!("{}", status),
+      None => println!("{}", "Done".green()),
+    },
+    // nothing else is going to touch the settings, restore them from here
+    Err(e) if restore => {
+      println!(
+        "{} {}",
+        "Daemon not reachable, restoring directly:".yellow(),
+        e
+      );
+      match restore_snapshot(SNAPSHOT_PATH) {
+        Ok(_) => println!("{}", "Done".green()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+          println!("{}", "No saved settings to restore".yellow())
+        }
+        Err(e) => eprintln!("{} {}", "Failed to restore the original settings:".red(), e),
+      }
+    }
+    Err(e) => eprintln!("{} {}", "Request to powereg daemon failed:".red(), e),
+  }
+}
@@ -0,0 +1,243 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// When the daemon changes state, the `[policy]` section.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+  /// percent, boost is turned on above it while on ac
+  pub high_cpu_load: f64,
+  /// percent, boost is turned off again below it
+  pub low_cpu_load: f64,
+  /// percent of battery capacity that forces powersave
+  pub low_battery: usize,
+  /// seconds between periodic checks
+  pub loop_duration_s: u8,
+  /// seconds the cpu load has to stay past a threshold before it counts
+  pub load_debounce_s: u64,
+  /// rewrite cores whose governor, epp or frequency limits drifted from the profile, otherwise
+  /// the drift is only logged
+  pub reconverge: bool,
+  /// celcius, switches to balanced above it while on ac
+  pub high_cpu_temp: usize,
+  /// celcius, back out of balanced at or below it
+  pub normal_cpu_temp: usize,
+  /// turn off boost and lower the max frequency step by step while balanced, until the cpu holds
+  /// `target_cpu_temp`
+  pub throttle: bool,
+  /// celcius
+  pub target_cpu_temp: usize,
+}
+
+impl Default for Policy {
+  fn default() -> Self {
+    Self {
+      high_cpu_load: 35.0,
+      low_cpu_load: 30.0,
+      low_battery: 20,
+      loop_duration_s: 3,
+      load_debounce_s: 5,
+      reconverge: true,
+      high_cpu_temp: 85,
+      normal_cpu_temp: 75,
+      throttle: false,
+      target_cpu_temp: 80,
+    }
+  }
+}
+
+impl Policy {
+  pub fn validate(&self) -> Result<(), String> {
+    for (name, load) in [
+      ("high_cpu_load", self.high_cpu_load),
+      ("low_cpu_load", self.low_cpu_load),
+    ] {
+      if !(0.0..=100.0).contains(&load) {
+        return Err(format!("policy.{name} must be 0-100, got {load}"));
+      }
+    }
+    if self.low_cpu_load >= self.high_cpu_load {
+      return Err(format!(
+        "policy.low_cpu_load ({}) must be below policy.high_cpu_load ({})",
+        self.low_cpu_load, self.high_cpu_load
+      ));
+    }
+    if self.low_battery > 100 {
+      return Err(format!(
+        "policy.low_battery must be 0-100, got {}",
+        self.low_battery
+      ));
+    }
+    if self.loop_duration_s == 0 {
+      return Err("policy.loop_duration_s must be at least 1".to_string());
+    }
+    if self.normal_cpu_temp >= self.high_cpu_temp {
+      return Err(format!(
+        "policy.normal_cpu_temp ({}) must be below policy.high_cpu_temp ({})",
+        self.normal_cpu_temp, self.high_cpu_temp
+      ));
+    }
+    if self.target_cpu_temp > self.high_cpu_temp {
+      return Err(format!(
+        "policy.target_cpu_temp ({}) must not be above policy.high_cpu_temp ({})",
+        self.target_cpu_temp, self.high_cpu_temp
+      ));
+    }
+    Ok(())
+  }
+
+  /// Human readable list of the settings that differ from `old`.
+  pub fn diff(&self, old: &Policy) -> Vec<String> {
+    [
+      (
+        "high_cpu_load",
+        old.high_cpu_load.to_string(),
+        self.high_cpu_load.to_string(),
+      ),
+      (
+        "low_cpu_load",
+        old.low_cpu_load.to_string(),
+        self.low_cpu_load.to_string(),
+      ),
+      (
+        "low_battery",
+        old.low_battery.to_string(),
+        self.low_battery.to_string(),
+      ),
+      (
+        "loop_duration_s",
+        old.loop_duration_s.to_string(),
+        self.loop_duration_s.to_string(),
+      ),
+      (
+        "load_debounce_s",
+        old.load_debounce_s.to_string(),
+        self.load_debounce_s.to_string(),
+      ),
+      (
+        "reconverge",
+        old.reconverge.to_string(),
+        self.reconverge.to_string(),
+      ),
+      (
+        "high_cpu_temp",
+        old.high_cpu_temp.to_string(),
+        self.high_cpu_temp.to_string(),
+      ),
+      (
+        "normal_cpu_temp",
+        old.normal_cpu_temp.to_string(),
+        self.normal_cpu_temp.to_string(),
+      ),
+      (
+        "throttle",
+        old.throttle.to_string(),
+        self.throttle.to_string(),
+      ),
+      (
+        "target_cpu_temp",
+        old.target_cpu_temp.to_string(),
+        self.target_cpu_temp.to_string(),
+      ),
+    ]
+    .into_iter()
+    .filter(|(_, old, new)| old != new)
+    .map(|(name, old, new)| format!("policy.{name}: {old} -> {new}"))
+    .collect()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadLevel {
+  /// above `high_cpu_load` for at least `load_debounce_s`
+  High,
+  /// below `low_cpu_load` for at least `load_debounce_s`
+  Low,
+  /// in between, or not past a threshold for long enough yet
+  Normal,
+}
+
+/// Remembers since when the cpu load has been past each threshold, so a single high sample
+/// doesn't flip boost.
+#[derive(Debug, Default)]
+pub struct LoadDebounce {
+  high_since: Option<Instant>,
+  low_since: Option<Instant>,
+}
+
+impl LoadDebounce {
+  pub fn update(&mut self, cpu_load: f64, policy: &Policy, now: Instant) -> LoadLevel {
+    let debounce = Duration::from_secs(policy.load_debounce_s);
+
+    if cpu_load >= policy.high_cpu_load {
+      self.low_since = None;
+      let since = *self.high_since.get_or_insert(now);
+      if now.duration_since(since) >= debounce {
+        return LoadLevel::High;
+      }
+    } else if cpu_load < policy.low_cpu_load {
+      self.high_since = None;
+      let since = *self.low_since.get_or_insert(now);
+      if now.duration_since(since) >= debounce {
+        return LoadLevel::Low;
+      }
+    } else {
+      self.high_since = None;
+      self.low_since = None;
+    }
+
+    LoadLevel::Normal
+  }
+}
+
+/// How hard the cpu is held back to reach `target_cpu_temp`, one step per periodic check. Step 1
+/// turns boost off, every further step takes another 10% off the max frequency's range.
+#[derive(Debug, Default)]
+pub struct Throttle {
+  level: u8,
+}
+
+impl Throttle {
+  pub const MAX_LEVEL: u8 = 11;
+  const STEP_PCT: u8 = 10;
+  /// celcius below the target before a step is taken back, so it doesn't flip every check
+  const HYSTERESIS: usize = 3;
+
+  /// True if the level changed.
+  pub fn update(&mut self, cpu_temp: usize, policy: &Policy) -> bool {
+    let level = if cpu_temp > policy.target_cpu_temp {
+      (self.level + 1).min(Self::MAX_LEVEL)
+    } else if cpu_temp + Self::HYSTERESIS < policy.target_cpu_temp {
+      self.level.saturating_sub(1)
+    } else {
+      self.level
+    };
+
+    let changed = level != self.level;
+    self.level = level;
+    changed
+  }
+
+  /// True if it was throttling.
+  pub fn reset(&mut self) -> bool {
+    let throttled = self.level > 0;
+    self.level = 0;
+    throttled
+  }
+
+  pub fn level(&self) -> u8 {
+    self.level
+  }
+
+  pub fn boost_off(&self) -> bool {
+    self.level > 0
+  }
+
+  /// Percent of the hardware frequency range the max frequency is capped at, None if uncapped.
+  pub fn max_freq_pct(&self) -> Option<u8> {
+    match self.level {
+      0 | 1 => None,
+      level => Some(100 - (level - 1) * Self::STEP_PCT),
+    }
+  }
+}
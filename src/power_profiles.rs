@@ -0,0 +1,480 @@
+use crate::dbus::{Connection, DBusError, Message, MessageType, Value};
+use crate::system_state::State;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// (bus name and interface, object path) of both names power-profiles-daemon answers to.
+const INTERFACES: [(&str, &str); 2] = [
+  (
+    "org.freedesktop.UPower.PowerProfiles",
+    "/org/freedesktop/UPower/PowerProfiles",
+  ),
+  ("net.hadess.PowerProfiles", "/net/hadess/PowerProfiles"),
+];
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER_INTERFACE: &str = "org.freedesktop.DBus.Peer";
+
+const ERROR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+const ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const ERROR_UNKNOWN_PROPERTY: &str = "org.freedesktop.DBus.Error.UnknownProperty";
+
+/// The power-profiles-daemon version whose API this mirrors.
+const VERSION: &str = "0.21";
+
+pub const POWER_SAVER: &str = "power-saver";
+pub const BALANCED: &str = "balanced";
+pub const PERFORMANCE: &str = "performance";
+
+/// The profile a desktop slider shows for `state`.
+pub fn profile_name(state: State) -> &'static str {
+  match state {
+    State::Powersave => POWER_SAVER,
+    State::Balanced => BALANCED,
+    State::Performance => PERFORMANCE,
+  }
+}
+
+pub fn profile_state(profile: &str) -> Option<State> {
+  match profile {
+    POWER_SAVER => Some(State::Powersave),
+    BALANCED => Some(State::Balanced),
+    PERFORMANCE => Some(State::Performance),
+    _ => None,
+  }
+}
+
+/// What the desktop wants the daemon to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileRequest {
+  /// "balanced" was selected: powereg picks the state from events as usual
+  Automatic,
+  /// "performance" or "power-saver" was selected, or is held by an application
+  Force(State),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileHold {
+  pub cookie: u32,
+  pub state: State,
+  pub reason: String,
+  pub application_id: String,
+  /// unique bus name of the holder, the hold ends when it disconnects
+  pub owner: String,
+}
+
+/// Serves the power-profiles-daemon D-Bus API so GNOME/KDE power profile switchers keep working.
+///
+/// Selecting "performance" or "power-saver" forces that state until another profile is picked,
+/// selecting "balanced" hands control back to powereg's automatic mode. Holds (e.g. a game asking
+/// for performance) override the selection while they exist, "power-saver" holds winning over
+/// "performance" ones like in power-profiles-daemon.
+pub struct PowerProfilesService {
+  connection: Connection,
+  cpu_driver: String,
+  platform_driver: String,
+
+  selected: ProfileRequest,
+  holds: Vec<ProfileHold>,
+  next_cookie: u32,
+
+  requested: ProfileRequest,
+  last_active_profile: String,
+  holds_changed: bool,
+}
+
+impl AsRawFd for PowerProfilesService {
+  fn as_raw_fd(&self) -> RawFd {
+    self.connection.as_raw_fd()
+  }
+}
+
+impl PowerProfilesService {
+  /// Takes over both bus names, failing if they're already owned (e.g. by power-profiles-daemon).
+  pub fn start(
+    mut connection: Connection,
+    cpu_driver: &str,
+    platform_driver: &str,
+  ) -> Result<Self, DBusError> {
+    for (name, _) in INTERFACES {
+      // 1: DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER
+      if connection.request_name(name)? != 1 {
+        return Err(DBusError::Remote(
+          "org.freedesktop.DBus.Error.AddressInUse".to_string(),
+          format!("{name} is already owned (is power-profiles-daemon running?)"),
+        ));
+      }
+    }
+    connection.add_match(
+      "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+       member='NameOwnerChanged'",
+    )?;
+
+    Ok(Self {
+      connection,
+      cpu_driver: cpu_driver.to_string(),
+      platform_driver: platform_driver.to_string(),
+
+      selected: ProfileRequest::Automatic,
+      holds: vec![],
+      next_cookie: 1,
+
+      requested: ProfileRequest::Automatic,
+      last_active_profile: BALANCED.to_string(),
+      holds_changed: false,
+    })
+  }
+
+  pub fn holds(&self) -> &[ProfileHold] {
+    &self.holds
+  }
+
+  /// Answers everything pending on the bus. `active_profile` is what the daemon currently runs,
+  /// returns a new request when the selection or the holds changed what the desktop wants.
+  pub fn process(&mut self, active_profile: &str) -> Result<Option<ProfileRequest>, DBusError> {
+    for msg in self.connection.read_messages()? {
+      match msg.msg_type {
+        MessageType::MethodCall => {
+          let reply = self.handle_call(&msg, active_profile);
+          if msg.expects_reply() {
+            self.connection.send(&reply)?;
+          }
+        }
+        MessageType::Signal if msg.member.as_deref() == Some("NameOwnerChanged") => {
+          self.handle_name_owner_changed(&msg)?;
+        }
+        _ => {}
+      }
+    }
+
+    let requested = self.effective_request();
+    if requested == self.requested {
+      return Ok(None);
+    }
+    self.requested = requested;
+    Ok(Some(requested))
+  }
+
+  /// Emits PropertiesChanged when the active profile or the holds changed since the last call.
+  pub fn notify(&mut self, active_profile: &str) -> Result<(), DBusError> {
+    let mut changed = vec![];
+    if active_profile != self.last_active_profile {
+      self.last_active_profile = active_profile.to_string();
+      changed.push(("ActiveProfile", Value::Str(active_profile.to_string())));
+    }
+    if self.holds_changed {
+      self.holds_changed = false;
+      changed.push(("ActiveProfileHolds", self.holds_value()));
+    }
+    if changed.is_empty() {
+      return Ok(());
+    }
+
+    for (interface, path) in INTERFACES {
+      let body = vec![
+        Value::Str(interface.to_string()),
+        Value::dict(changed.clone()),
+        Value::Array("s".to_string(), vec![]),
+      ];
+      self.connection.send(&Message::signal(
+        path,
+        PROPERTIES_INTERFACE,
+        "PropertiesChanged",
+        body,
+      ))?;
+    }
+    Ok(())
+  }
+
+  fn effective_request(&self) -> ProfileRequest {
+    if self.holds.iter().any(|h| h.state == State::Powersave) {
+      ProfileRequest::Force(State::Powersave)
+    } else if self.holds.iter().any(|h| h.state == State::Performance) {
+      ProfileRequest::Force(State::Performance)
+    } else {
+      self.selected
+    }
+  }
+
+  fn handle_call(&mut self, msg: &Message, active_profile: &str) -> Message {
+    let Some(interface) = msg
+      .path
+      .as_deref()
+      .and_then(|path| INTERFACES.iter().find(|(_, p)| *p == path))
+      .map(|(interface, _)| *interface)
+    else {
+      return Message::error(msg, ERROR_UNKNOWN_METHOD, "No such object");
+    };
+
+    let args: Vec<&Value> = msg.body.iter().collect();
+    let member = msg.member.as_deref().unwrap_or("");
+    let result = match (msg.interface.as_deref().unwrap_or(interface), member) {
+      (PROPERTIES_INTERFACE, "Get") => match args.as_slice() {
+        [iface, Value::Str(name)] if iface.as_str() == Some(interface) => self
+          .property(name, active_profile)
+          .map(|v| vec![Value::Variant(Box::new(v))])
+          .ok_or((ERROR_UNKNOWN_PROPERTY, format!("No such property '{name}'"))),
+        _ => Err((ERROR_INVALID_ARGS, "Unknown interface".to_string())),
+      },
+      (PROPERTIES_INTERFACE, "GetAll") => match args.as_slice() {
+        [iface] if iface.as_str() == Some(interface) => Ok(vec![self.properties(active_profile)]),
+        [_] => Ok(vec![Value::dict(vec![])]),
+        _ => Err((ERROR_INVALID_ARGS, "Expected an interface name".to_string())),
+      },
+      (PROPERTIES_INTERFACE, "Set") => match args.as_slice() {
+        [iface, Value::Str(name), Value::Variant(value)] if iface.as_str() == Some(interface) => {
+          self.set_property(name, value).map(|_| vec![])
+        }
+        _ => Err((ERROR_INVALID_ARGS, "Unknown interface".to_string())),
+      },
+      (INTROSPECTABLE_INTERFACE, "Introspect") => {
+        Ok(vec![Value::Str(introspection_xml(interface))])
+      }
+      (PEER_INTERFACE, "Ping") => Ok(vec![]),
+      (iface, "HoldProfile") if iface == interface => match args.as_slice() {
+        [Value::Str(profile), Value::Str(reason), Value::Str(application_id)] => self
+          .hold_profile(msg, profile, reason, application_id)
+          .map(|cookie| vec![Value::U32(cookie)]),
+        _ => Err((ERROR_INVALID_ARGS, "Expected (sss)".to_string())),
+      },
+      (iface, "ReleaseProfile") if iface == interface => match args.as_slice() {
+        [Value::U32(cookie)] => self.release_profile(*cookie).map(|_| vec![]),
+        _ => Err((ERROR_INVALID_ARGS, "Expected (u)".to_string())),
+      },
+      (iface, member) => Err((
+        ERROR_UNKNOWN_METHOD,
+        format!("No method {member} on interface {iface}"),
+      )),
+    };
+
+    match result {
+      Ok(body) => Message::method_return(msg, body),
+      Err((name, text)) => Message::error(msg, name, &text),
+    }
+  }
+
+  fn property(&self, name: &str, active_profile: &str) -> Option<Value> {
+    match name {
+      "ActiveProfile" => Some(Value::Str(active_profile.to_string())),
+      "PerformanceInhibited" | "PerformanceDegraded" => Some(Value::Str(String::new())),
+      "Profiles" => Some(self.profiles_value()),
+      "Actions" => Some(Value::Array("s".to_string(), vec![])),
+      "ActiveProfileHolds" => Some(self.holds_value()),
+      "Version" => Some(Value::Str(VERSION.to_string())),
+      _ => None,
+    }
+  }
+
+  fn properties(&self, active_profile: &str) -> Value {
+    let names = [
+      "ActiveProfile",
+      "PerformanceInhibited",
+      "PerformanceDegraded",
+      "Profiles",
+      "Actions",
+      "ActiveProfileHolds",
+      "Version",
+    ];
+    Value::dict(
+      names
+        .into_iter()
+        .filter_map(|name| Some((name, self.property(name, active_profile)?)))
+        .collect(),
+    )
+  }
+
+  fn set_property(&mut self, name: &str, value: &Value) -> Result<(), (&'static str, String)> {
+    if name != "ActiveProfile" {
+      return Err((
+        ERROR_INVALID_ARGS,
+        format!("Property '{name}' is read-only"),
+      ));
+    }
+    let profile = value.as_str().unwrap_or("");
+    let state =
+      profile_state(profile).ok_or((ERROR_INVALID_ARGS, format!("Invalid profile '{profile}'")))?;
+
+    println!("Desktop selected profile {}", profile);
+    self.selected = match state {
+      State::Balanced => ProfileRequest::Automatic,
+      state => ProfileRequest::Force(state),
+    };
+
+    // picking a profile by hand overrides every hold, like in power-profiles-daemon
+    for hold in std::mem::take(&mut self.holds) {
+      self.emit_profile_released(hold.cookie);
+      self.holds_changed = true;
+    }
+
+    Ok(())
+  }
+
+  fn hold_profile(
+    &mut self,
+    msg: &Message,
+    profile: &str,
+    reason: &str,
+    application_id: &str,
+  ) -> Result<u32, (&'static str, String)> {
+    let state = match profile_state(profile) {
+      Some(State::Balanced) | None => {
+        return Err((
+          ERROR_INVALID_ARGS,
+          format!("Only '{PERFORMANCE}' and '{POWER_SAVER}' can be held, not '{profile}'"),
+        ));
+      }
+      Some(state) => state,
+    };
+
+    let cookie = self.next_cookie;
+    self.next_cookie += 1;
+    println!(
+      "{} holds profile {} ({}), cookie {}",
+      application_id, profile, reason, cookie
+    );
+    self.holds.push(ProfileHold {
+      cookie,
+      state,
+      reason: reason.to_string(),
+      application_id: application_id.to_string(),
+      owner: msg.sender.clone().unwrap_or_default(),
+    });
+    self.holds_changed = true;
+
+    Ok(cookie)
+  }
+
+  fn release_profile(&mut self, cookie: u32) -> Result<(), (&'static str, String)> {
+    let before = self.holds.len();
+    self.holds.retain(|h| h.cookie != cookie);
+    if self.holds.len() == before {
+      return Err((ERROR_INVALID_ARGS, format!("No hold with cookie {cookie}")));
+    }
+
+    println!("Released profile hold {}", cookie);
+    self.holds_changed = true;
+    Ok(())
+  }
+
+  fn handle_name_owner_changed(&mut self, msg: &Message) -> Result<(), DBusError> {
+    let [name, _, new_owner] = msg.body.as_slice() else {
+      return Ok(());
+    };
+    if new_owner.as_str() != Some("") {
+      return Ok(());
+    }
+
+    let name = name.as_str().unwrap_or("");
+    let (gone, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.holds)
+      .into_iter()
+      .partition(|h| h.owner == name);
+    self.holds = kept;
+    for hold in gone {
+      println!(
+        "Released profile hold {}, {} left the bus",
+        hold.cookie, hold.application_id
+      );
+      self.holds_changed = true;
+    }
+
+    Ok(())
+  }
+
+  fn emit_profile_released(&mut self, cookie: u32) {
+    for (interface, path) in INTERFACES {
+      let signal = Message::signal(path, interface, "ProfileReleased", vec![Value::U32(cookie)]);
+      if let Err(e) = self.connection.send(&signal) {
+        eprintln!("Failed to emit ProfileReleased: {}", e);
+      }
+    }
+  }
+
+  fn profiles_value(&self) -> Value {
+    let profiles = [POWER_SAVER, BALANCED, PERFORMANCE]
+      .into_iter()
+      .map(|profile| {
+        Value::dict(vec![
+          ("Profile", Value::Str(profile.to_string())),
+          ("Driver", Value::Str("multiple".to_string())),
+          ("PlatformDriver", Value::Str(self.platform_driver.clone())),
+          ("CpuDriver", Value::Str(self.cpu_driver.clone())),
+        ])
+      })
+      .collect();
+    Value::Array("a{sv}".to_string(), profiles)
+  }
+
+  fn holds_value(&self) -> Value {
+    let holds = self
+      .holds
+      .iter()
+      .map(|hold| {
+        Value::dict(vec![
+          ("Profile", Value::Str(profile_name(hold.state).to_string())),
+          ("Reason", Value::Str(hold.reason.clone())),
+          ("ApplicationId", Value::Str(hold.application_id.clone())),
+        ])
+      })
+      .collect();
+    Value::Array("a{sv}".to_string(), holds)
+  }
+}
+
+fn introspection_xml(interface: &str) -> String {
+  format!(
+    r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="{interface}">
+    <method name="HoldProfile">
+      <arg name="profile" type="s" direction="in"/>
+      <arg name="reason" type="s" direction="in"/>
+      <arg name="application_id" type="s" direction="in"/>
+      <arg name="cookie" type="u" direction="out"/>
+    </method>
+    <method name="ReleaseProfile">
+      <arg name="cookie" type="u" direction="in"/>
+    </method>
+    <signal name="ProfileReleased">
+      <arg name="cookie" type="u"/>
+    </signal>
+    <property name="ActiveProfile" type="s" access="readwrite"/>
+    <property name="PerformanceInhibited" type="s" access="read"/>
+    <property name="PerformanceDegraded" type="s" access="read"/>
+    <property name="Profiles" type="aa{{sv}}" access="read"/>
+    <property name="Actions" type="as" access="read"/>
+    <property name="ActiveProfileHolds" type="aa{{sv}}" access="read"/>
+    <property name="Version" type="s" access="read"/>
+  </interface>
+  <interface name="{PROPERTIES_INTERFACE}">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{{sv}}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed_properties" type="a{{sv}}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="{INTROSPECTABLE_INTERFACE}">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="{PEER_INTERFACE}">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#
+  )
+}
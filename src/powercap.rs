@@ -0,0 +1,234 @@
+use crate::sysfs::SysfsRoot;
+use crate::utils::{PersFd, PersFdError};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::time::Instant;
+
+pub const POWERCAP_PATH: &str = "/sys/class/powercap";
+
+/// RAPL zones of both vendors, amd cpus show up as `intel-rapl` on most kernels.
+const RAPL_PREFIXES: &[&str] = &["intel-rapl:", "amd-rapl:"];
+
+/// PL1 and PL2, the sustained and the burst package power limit.
+pub const LONG_TERM: &str = "long_term";
+pub const SHORT_TERM: &str = "short_term";
+
+/// A zone's `constraint_N_*` files.
+pub struct Constraint {
+  /// "long_term", "short_term", "peak_power"
+  pub name: String,
+  limit: RefCell<PersFd>,
+  /// µW, None if the firmware doesn't say (some report 0)
+  max_power: Option<u64>,
+}
+
+impl Constraint {
+  /// watts
+  pub fn read_limit(&self) -> Option<f64> {
+    let uw: u64 = self.limit.borrow_mut().read_value().ok()?.parse().ok()?;
+    Some(uw as f64 / 1_000_000.0)
+  }
+
+  /// Clamped to `constraint_N_max_power_uw`.
+  pub fn set_limit(&self, watts: u32) -> Result<(), PersFdError> {
+    let mut uw = u64::from(watts) * 1_000_000;
+    if let Some(max) = self.max_power {
+      uw = uw.min(max);
+    }
+    self.limit.borrow_mut().set_value(&uw.to_string())
+  }
+}
+
+/// A RAPL zone (`intel-rapl:0`) or subzone (`intel-rapl:0:1`) and its energy counter.
+pub struct Zone {
+  /// "package-0", subzones under their package: "package-0/core", "package-0/dram"
+  pub name: String,
+  /// the directory's name, "intel-rapl:0:1"
+  pub id: String,
+  pub constraints: Vec<Constraint>,
+  energy: RefCell<PersFd>,
+  /// µJ, where the counter wraps around
+  max_energy_range: u64,
+  /// µJ and when it was read
+  last: Cell<Option<(u64, Instant)>>,
+  watts: Cell<Option<f64>>,
+}
+
+impl Zone {
+  pub fn is_subzone(&self) -> bool {
+    self.id.matches(':').count() > 1
+  }
+
+  pub fn is_package(&self) -> bool {
+    !self.is_subzone() && self.name.starts_with("package")
+  }
+
+  pub fn constraint(&self, name: &str) -> Option<&Constraint> {
+    self.constraints.iter().find(|c| c.name == name)
+  }
+
+  /// Over the time between the last two samples, None until there were two.
+  pub fn watts(&self) -> Option<f64> {
+    self.watts.get()
+  }
+
+  fn sample(&self, now: Instant) {
+    let Some(energy) = self
+      .energy
+      .borrow_mut()
+      .read_value()
+      .ok()
+      .and_then(|v| v.parse::<u64>().ok())
+    else {
+      self.last.set(None);
+      self.watts.set(None);
+      return;
+    };
+
+    if let Some((last, then)) = self.last.get() {
+      let secs = now.duration_since(then).as_secs_f64();
+      let delta = if energy >= last {
+        energy - last
+      } else {
+        // wrapped around since the last sample
+        self.max_energy_range.saturating_sub(last) + energy
+      };
+      if secs > 0.0 {
+        self.watts.set(Some(delta as f64 / secs / 1_000_000.0));
+      }
+    }
+    self.last.set(Some((energy, now)));
+  }
+}
+
+/// Every RAPL zone. Power is computed from the energy counters between successive `sample`
+/// calls (the daemon's periodic checks) instead of sleeping between two reads.
+pub struct Powercap {
+  zones: Vec<Zone>,
+}
+
+impl Powercap {
+  /// Zones without a readable counter are left out, `energy_uj` is root only on newer kernels.
+  pub fn discover(root: &SysfsRoot) -> Self {
+    let mut ids: Vec<String> = fs::read_dir(root.path(POWERCAP_PATH))
+      .map(|entries| {
+        entries
+          .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+          .filter(|id| RAPL_PREFIXES.iter().any(|prefix| id.starts_with(prefix)))
+          .collect()
+      })
+      .unwrap_or_default();
+    // "intel-rapl:0" before its subzones "intel-rapl:0:0", "intel-rapl:0:1"
+    ids.sort();
+
+    let mut zones: Vec<Zone> = vec![];
+    for id in ids {
+      let dir = root.path(&format!("{POWERCAP_PATH}/{id}"));
+      let read = |file: &str| fs::read_to_string(format!("{dir}/{file}")).ok();
+
+      let Some(name) = read("name") else {
+        continue;
+      };
+      let Ok(energy) = PersFd::new(&format!("{dir}/energy_uj"), false) else {
+        continue;
+      };
+      let max_energy_range = read("max_energy_range_uj")
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(u64::MAX);
+
+      let name = match id.rsplit_once(':') {
+        Some((parent_id, _)) if parent_id.contains(':') => {
+          match zones.iter().find(|z| z.id == parent_id) {
+            Some(parent) => format!("{}/{}", parent.name, name.trim()),
+            None => name.trim().to_string(),
+          }
+        }
+        _ => name.trim().to_string(),
+      };
+
+      zones.push(Zone {
+        name,
+        id,
+        constraints: Self::constraints(&dir),
+        energy: RefCell::new(energy),
+        max_energy_range,
+        last: Cell::new(None),
+        watts: Cell::new(None),
+      });
+    }
+
+    Self { zones }
+  }
+
+  /// `constraint_0_*`, `constraint_1_*`, ... up to the first one missing.
+  fn constraints(dir: &str) -> Vec<Constraint> {
+    let mut constraints = vec![];
+    for n in 0.. {
+      let file = |suffix: &str| format!("{dir}/constraint_{n}_{suffix}");
+      let Ok(name) = fs::read_to_string(file("name")) else {
+        break;
+      };
+      let Ok(limit) = PersFd::new(&file("power_limit_uw"), true) else {
+        continue;
+      };
+      let max_power = fs::read_to_string(file("max_power_uw"))
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|max| *max > 0);
+
+      constraints.push(Constraint {
+        name: name.trim().to_string(),
+        limit: RefCell::new(limit),
+        max_power,
+      });
+    }
+    constraints
+  }
+
+  pub fn zones(&self) -> &[Zone] {
+    &self.zones
+  }
+
+  /// Reads every counter, the watts are over the time since the previous call.
+  pub fn sample(&self, now: Instant) {
+    for zone in &self.zones {
+      zone.sample(now);
+    }
+  }
+
+  /// What the cpu packages draw together, None until two samples were taken.
+  pub fn package_watts(&self) -> Option<f64> {
+    let packages: Vec<Option<f64>> = self
+      .zones
+      .iter()
+      .filter(|z| z.is_package())
+      .map(|z| z.watts())
+      .collect();
+    if packages.is_empty() {
+      return None;
+    }
+    packages.into_iter().sum()
+  }
+
+  /// Sets the `constraint` (`LONG_TERM`, `SHORT_TERM`) of every package, packages without it
+  /// are skipped.
+  pub fn set_power_limit(&self, constraint: &str, watts: u32) -> Result<(), PersFdError> {
+    for zone in self.zones.iter().filter(|z| z.is_package()) {
+      if let Some(constraint) = zone.constraint(constraint) {
+        constraint.set_limit(watts)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Every package power limit file, for the snapshot.
+  pub fn knob_paths(&self) -> Vec<String> {
+    self
+      .zones
+      .iter()
+      .filter(|z| z.is_package())
+      .flat_map(|z| &z.constraints)
+      .map(|c| c.limit.borrow().path().to_string())
+      .collect()
+  }
+}
@@ -0,0 +1,264 @@
+use crate::battery::PlatformProfile;
+use crate::cpu::EPP;
+use crate::system_state::State;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Boost {
+  On,
+  Off,
+  /// on while the cpu load is high, the built-in performance behaviour
+  Auto,
+}
+
+/// The knobs a state sets. Anything left out keeps whatever the previous profile wrote.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+  pub governor: Option<String>,
+  pub epp: Option<String>,
+  pub platform_profile: Option<String>,
+  pub boost: Option<Boost>,
+  pub min_freq_mhz: Option<u32>,
+  pub max_freq_mhz: Option<u32>,
+  /// intel_pstate only, skipped on other drivers
+  pub min_perf_pct: Option<u8>,
+  pub max_perf_pct: Option<u8>,
+  /// RAPL PL1 and PL2, clamped to what the firmware allows
+  pub package_power_limit_w: Option<u32>,
+  pub burst_power_limit_w: Option<u32>,
+}
+
+impl Profile {
+  pub fn powersave() -> Self {
+    Self {
+      governor: Some("powersave".to_string()),
+      epp: Some("power".to_string()),
+      platform_profile: Some("low-power".to_string()),
+      boost: Some(Boost::Off),
+      ..Default::default()
+    }
+  }
+
+  pub fn balanced() -> Self {
+    Self {
+      governor: Some("powersave".to_string()),
+      epp: Some("balance_power".to_string()),
+      platform_profile: Some("balanced".to_string()),
+      boost: Some(Boost::Off),
+      ..Default::default()
+    }
+  }
+
+  pub fn performance() -> Self {
+    Self {
+      governor: Some("performance".to_string()),
+      epp: Some("performance".to_string()),
+      platform_profile: Some("performance".to_string()),
+      boost: Some(Boost::Auto),
+      // a leftover cap would keep the driver from reaching full performance
+      max_perf_pct: Some(100),
+      ..Default::default()
+    }
+  }
+
+  pub fn builtin(name: &str) -> Option<Self> {
+    match State::from_string(name)? {
+      State::Powersave => Some(Self::powersave()),
+      State::Balanced => Some(Self::balanced()),
+      State::Performance => Some(Self::performance()),
+    }
+  }
+
+  /// `self` with every knob `other` sets replaced.
+  pub fn overlay(&self, other: &Profile) -> Profile {
+    Profile {
+      governor: other.governor.clone().or(self.governor.clone()),
+      epp: other.epp.clone().or(self.epp.clone()),
+      platform_profile: other
+        .platform_profile
+        .clone()
+        .or(self.platform_profile.clone()),
+      boost: other.boost.or(self.boost),
+      min_freq_mhz: other.min_freq_mhz.or(self.min_freq_mhz),
+      max_freq_mhz: other.max_freq_mhz.or(self.max_freq_mhz),
+      min_perf_pct: other.min_perf_pct.or(self.min_perf_pct),
+      max_perf_pct: other.max_perf_pct.or(self.max_perf_pct),
+      package_power_limit_w: other.package_power_limit_w.or(self.package_power_limit_w),
+      burst_power_limit_w: other.burst_power_limit_w.or(self.burst_power_limit_w),
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    // whether the kernel offers them is only known once applied, see
+    // `SystemState::check_profile`
+    for (knob, value) in [("governor", &self.governor), ("epp", &self.epp)] {
+      if let Some(value) = value
+        && (value.is_empty() || value.contains(char::is_whitespace))
+      {
+        return Err(format!("invalid {knob} '{value}'"));
+      }
+    }
+    if let Some(epp) = &self.epp
+      && epp.chars().all(|c| c.is_ascii_digit())
+      && !matches!(EPP::from_string(epp), EPP::Raw(_))
+    {
+      return Err(format!("raw epp must be 0-255, got {epp}"));
+    }
+    if let Some(platform_profile) = &self.platform_profile
+      && let PlatformProfile::Unknown = PlatformProfile::from_string(platform_profile)
+    {
+      return Err(format!("unknown platform_profile '{platform_profile}'"));
+    }
+
+    for pct in [self.min_perf_pct, self.max_perf_pct].into_iter().flatten() {
+      if pct > 100 {
+        return Err(format!("perf pct must be 0-100, got {pct}"));
+      }
+    }
+    if let (Some(min), Some(max)) = (self.min_perf_pct, self.max_perf_pct)
+      && min > max
+    {
+      return Err(format!(
+        "min_perf_pct ({min}) is above max_perf_pct ({max})"
+      ));
+    }
+    if let (Some(min), Some(max)) = (self.min_freq_mhz, self.max_freq_mhz)
+      && min > max
+    {
+      return Err(format!(
+        "min_freq_mhz ({min}) is above max_freq_mhz ({max})"
+      ));
+    }
+
+    for (knob, watts) in [
+      ("package_power_limit_w", self.package_power_limit_w),
+      ("burst_power_limit_w", self.burst_power_limit_w),
+    ] {
+      if watts == Some(0) {
+        return Err(format!("{knob} must be at least 1"));
+      }
+    }
+    if let (Some(package), Some(burst)) = (self.package_power_limit_w, self.burst_power_limit_w)
+      && package > burst
+    {
+      return Err(format!(
+        "package_power_limit_w ({package}) is above burst_power_limit_w ({burst})"
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+/// Which profile each `State` applies, the `[states]` section.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StateProfiles {
+  pub powersave: Option<String>,
+  pub balanced: Option<String>,
+  pub performance: Option<String>,
+}
+
+/// Every profile known to the daemon, the built-in ones included, and the state mapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profiles {
+  profiles: BTreeMap<String, Profile>,
+  states: StateProfiles,
+}
+
+impl Default for Profiles {
+  fn default() -> Self {
+    Self::new(BTreeMap::new(), StateProfiles::default())
+  }
+}
+
+impl Profiles {
+  /// Profiles named like a state are laid over the built-in one of that name, so a config only
+  /// has to list what it changes.
+  pub fn new(configured: BTreeMap<String, Profile>, states: StateProfiles) -> Self {
+    let mut profiles: BTreeMap<String, Profile> =
+      [State::Powersave, State::Balanced, State::Performance]
+        .into_iter()
+        .map(|state| {
+          (
+            state.to_string(),
+            Profile::builtin(&state.to_string()).unwrap(),
+          )
+        })
+        .collect();
+
+    for (name, profile) in configured {
+      let base = profiles.remove(&name).unwrap_or_default();
+      profiles.insert(name, base.overlay(&profile));
+    }
+
+    Self { profiles, states }
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    for (name, profile) in &self.profiles {
+      profile
+        .validate()
+        .map_err(|e| format!("profiles.{name}: {e}"))?;
+    }
+    for state in [State::Powersave, State::Balanced, State::Performance] {
+      let name = self.profile_name(state);
+      if !self.profiles.contains_key(name) {
+        return Err(format!("states.{state}: no profile named '{name}'"));
+      }
+    }
+    Ok(())
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&String, &Profile)> {
+    self.profiles.iter()
+  }
+
+  pub fn profile_name(&self, state: State) -> &str {
+    let configured = match state {
+      State::Powersave => &self.states.powersave,
+      State::Balanced => &self.states.balanced,
+      State::Performance => &self.states.performance,
+    };
+    configured.as_deref().unwrap_or(match state {
+      State::Powersave => "powersave",
+      State::Balanced => "balanced",
+      State::Performance => "performance",
+    })
+  }
+
+  pub fn for_state(&self, state: State) -> Profile {
+    self
+      .profiles
+      .get(self.profile_name(state))
+      .cloned()
+      .unwrap_or_else(|| Profile::builtin(&state.to_string()).unwrap())
+  }
+
+  /// Human readable list of what differs from `old`.
+  pub fn diff(&self, old: &Profiles) -> Vec<String> {
+    let mut diff = vec![];
+
+    let names: BTreeSet<&String> = old.profiles.keys().chain(self.profiles.keys()).collect();
+    for name in names {
+      match (old.profiles.get(name), self.profiles.get(name)) {
+        (None, Some(_)) => diff.push(format!("profiles.{name}: added")),
+        (Some(_), None) => diff.push(format!("profiles.{name}: removed")),
+        (Some(old), Some(new)) if old != new => diff.push(format!("profiles.{name}: changed")),
+        _ => {}
+      }
+    }
+
+    for state in [State::Powersave, State::Balanced, State::Performance] {
+      let (old, new) = (old.profile_name(state), self.profile_name(state));
+      if old != new {
+        diff.push(format!("states.{state}: {old} -> {new}"));
+      }
+    }
+
+    diff
+  }
+}
@@ -0,0 +1,111 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+
+/// Wakes the daemon up when the config file is written. SIGHUP is delivered by the
+/// `EventPoller`.
+///
+/// The config's directory is watched rather than the file itself, editors usually save by writing
+/// a new file and renaming it over the old one.
+pub struct ConfigWatcher {
+  inotify: Option<OwnedFd>,
+  file_name: String,
+}
+
+impl ConfigWatcher {
+  pub fn new(config_path: &str) -> Self {
+    let path = Path::new(config_path);
+    let file_name = path
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+      .unwrap_or_default();
+
+    let inotify = path.parent().and_then(|dir| match watch_dir(dir) {
+      Ok(fd) => Some(fd),
+      Err(e) => {
+        eprintln!("Not watching {} for changes: {}", dir.display(), e);
+        None
+      }
+    });
+
+    Self { inotify, file_name }
+  }
+
+  pub fn raw_fd(&self) -> Option<RawFd> {
+    self.inotify.as_ref().map(|fd| fd.as_raw_fd())
+  }
+
+  /// Consumes pending notifications, true if the config should be reloaded.
+  pub fn drain(&self) -> bool {
+    let mut reload = false;
+
+    if let Some(fd) = &self.inotify {
+      let mut buf = [0u8; 4096];
+      loop {
+        let n = unsafe {
+          libc::read(
+            fd.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+          )
+        };
+        if n <= 0 {
+          break;
+        }
+        reload |= self.names_config(&buf[..n as usize]);
+      }
+    }
+
+    reload
+  }
+
+  /// Whether any of the inotify events in `buf` is about the config file.
+  fn names_config(&self, buf: &[u8]) -> bool {
+    let header = mem::size_of::<libc::inotify_event>();
+    let mut offset = 0;
+    let mut found = false;
+
+    while offset + header <= buf.len() {
+      let event: libc::inotify_event =
+        unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event) };
+      let name_start = offset + header;
+      let name_end = (name_start + event.len as usize).min(buf.len());
+      let name = buf[name_start..name_end]
+        .split(|b| *b == 0)
+        .next()
+        .unwrap_or_default();
+
+      if name == self.file_name.as_bytes() {
+        found = true;
+      }
+      offset = name_end;
+    }
+
+    found
+  }
+}
+
+fn watch_dir(dir: &Path) -> io::Result<OwnedFd> {
+  let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+  if fd < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+  let dir = CString::new(dir.to_string_lossy().as_bytes())
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+  let wd = unsafe {
+    libc::inotify_add_watch(
+      fd.as_raw_fd(),
+      dir.as_ptr(),
+      libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+    )
+  };
+  if wd < 0 {
+    return Err(io::Error::last_os_error());
+  }
+
+  Ok(fd)
+}
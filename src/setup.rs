@@ -1,10 +1,54 @@
+use crate::snapshot::{restore_snapshot, SNAPSHOT_PATH};
 use crate::utils::StyledString;
-use std::{io, process::Command};
+use std::{io, path::Path, process::Command};
 
 const SERVICE_NAME: &str = "powereg";
 const SERVICE_PATH: &str = "/etc/systemd/system/powereg.service";
 const BINARY_PATH: &str = "/usr/local/bin/powereg";
 const RUN_FLAG: &str = "--daemon";
+/// Lets the daemon own the power-profiles-daemon bus names, see power_profiles.rs
+const DBUS_POLICY_PATH: &str = "/etc/dbus-1/system.d/powereg.conf";
+/// Reading the profiles is open to everyone, changing them is limited to root and the local
+/// console like the control socket.
+pub const DBUS_POLICY: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <policy context="default">
+    <allow send_destination="net.hadess.PowerProfiles"/>
+    <allow send_destination="org.freedesktop.UPower.PowerProfiles"/>
+    <deny send_destination="net.hadess.PowerProfiles" send_interface="org.freedesktop.DBus.Properties"
+          send_member="Set"/>
+    <deny send_destination="net.hadess.PowerProfiles" send_interface="net.hadess.PowerProfiles"
+          send_member="HoldProfile"/>
+    <deny send_destination="net.hadess.PowerProfiles" send_interface="net.hadess.PowerProfiles"
+          send_member="ReleaseProfile"/>
+    <deny send_destination="net.hadess.PowerProfiles" send_interface="org.freedesktop.UPower.PowerProfiles"
+          send_member="HoldProfile"/>
+    <deny send_destination="net.hadess.PowerProfiles" send_interface="org.freedesktop.UPower.PowerProfiles"
+          send_member="ReleaseProfile"/>
+    <deny send_destination="org.freedesktop.UPower.PowerProfiles" send_interface="org.freedesktop.DBus.Properties"
+          send_member="Set"/>
+    <deny send_destination="org.freedesktop.UPower.PowerProfiles" send_interface="net.hadess.PowerProfiles"
+          send_member="HoldProfile"/>
+    <deny send_destination="org.freedesktop.UPower.PowerProfiles" send_interface="net.hadess.PowerProfiles"
+          send_member="ReleaseProfile"/>
+    <deny send_destination="org.freedesktop.UPower.PowerProfiles" send_interface="org.freedesktop.UPower.PowerProfiles"
+          send_member="HoldProfile"/>
+    <deny send_destination="org.freedesktop.UPower.PowerProfiles" send_interface="org.freedesktop.UPower.PowerProfiles"
+          send_member="ReleaseProfile"/>
+  </policy>
+  <policy at_console="true">
+    <allow send_destination="net.hadess.PowerProfiles"/>
+    <allow send_destination="org.freedesktop.UPower.PowerProfiles"/>
+  </policy>
+  <policy user="root">
+    <allow own="net.hadess.PowerProfiles"/>
+    <allow own="org.freedesktop.UPower.PowerProfiles"/>
+    <allow send_destination="net.hadess.PowerProfiles"/>
+    <allow send_destination="org.freedesktop.UPower.PowerProfiles"/>
+  </policy>
+</busconfig>
+"#;
 
 pub fn check_running_daemon_mode() -> io::Result<bool> {
   println!("{}", "Running 'systemctl is-active powereg'".yellow());
@@ -34,15 +78,21 @@ pub fn install_daemon() -> io::Result<()> {
   let service_file = format!(
     r#"[Unit]
 Description=PowerEG - Power Management Daemon
-After=network.target
+After=network.target dbus.service
 Documentation=man:{}(8)
 
 [Service]
 Type=simple
 User=root
 ExecStart={} {}
+# re-reads the config, see reload.rs
+ExecReload=/bin/kill -HUP $MAINPID
 Restart=on-failure
 RestartSec=10
+# control socket, see control::SOCKET_PATH
+RuntimeDirectory=powereg
+# original settings, see snapshot::SNAPSHOT_PATH
+StateDirectory=powereg
 
 # Security and isolation options
 ProtectSystem=strict
@@ -73,6 +123,16 @@ WantedBy=multi-user.target
     )
   })?;
 
+  // desktops still find power profiles on D-Bus without it, only the daemon exports them
+  if let Err(e) = std::fs::write(DBUS_POLICY_PATH, DBUS_POLICY) {
+    eprintln!(
+      "{} {}: {}",
+      "Failed to write D-Bus policy to".red(),
+      DBUS_POLICY_PATH,
+      e
+    );
+  }
+
   println!("{}", "Running 'systemctl daemon-reload'".yellow());
   let output = Command::new("systemctl")
     .arg("daemon-reload")
@@ -181,6 +241,13 @@ pub fn uninstall_daemon() -> io::Result<()> {
     );
   }
 
+  // the daemon restores the original settings on SIGTERM, a snapshot left over means it couldn't
+  if Path::new(SNAPSHOT_PATH).exists()
+    && let Err(e) = restore_snapshot(SNAPSHOT_PATH)
+  {
+    eprintln!("{} {}", "Failed to restore the original settings:".red(), e);
+  }
+
   std::fs::remove_file(SERVICE_PATH).map_err(|e| {
     io::Error::new(
       e.kind(),
@@ -192,6 +259,7 @@ pub fn uninstall_daemon() -> io::Result<()> {
       ),
     )
   })?;
+  let _ = std::fs::remove_file(DBUS_POLICY_PATH);
 
   println!("{}", "Running 'systemctl daemon-reload'".yellow());
   let output = Command::new("systemctl")
@@ -220,6 +288,8 @@ pub fn uninstall_daemon() -> io::Result<()> {
 }
 
 fn check_installed_power_tools() -> bool {
+  // power-profiles-daemon holds the bus names powereg exports its replacement under, desktop
+  // profile switchers keep working through powereg
   let services = vec![
     "power-profiles-daemon.service",
     "tlp.service",
@@ -0,0 +1,65 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// Delivers signals through a file descriptor the poll loop can watch, instead of a handler.
+pub struct SignalFd {
+  fd: OwnedFd,
+}
+
+impl AsRawFd for SignalFd {
+  fn as_raw_fd(&self) -> RawFd {
+    self.fd.as_raw_fd()
+  }
+}
+
+impl SignalFd {
+  /// Blocks `signals` for this thread, so only the returned fd receives them.
+  pub fn new(signals: &[libc::c_int]) -> io::Result<Self> {
+    unsafe {
+      let mut mask: libc::sigset_t = mem::zeroed();
+      libc::sigemptyset(&mut mask);
+      for signal in signals {
+        libc::sigaddset(&mut mask, *signal);
+      }
+      if libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) != 0 {
+        return Err(io::Error::last_os_error());
+      }
+
+      let fd = libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(Self {
+        fd: OwnedFd::from_raw_fd(fd),
+      })
+    }
+  }
+
+  /// The pending signals, empty if none arrived.
+  pub fn read(&self) -> Vec<libc::c_int> {
+    let mut signals = vec![];
+    let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::signalfd_siginfo>();
+    while unsafe {
+      libc::read(
+        self.fd.as_raw_fd(),
+        &mut info as *mut _ as *mut libc::c_void,
+        size,
+      )
+    } == size as isize
+    {
+      signals.push(info.ssi_signo as libc::c_int);
+    }
+    signals
+  }
+}
+
+pub fn signal_name(signal: libc::c_int) -> &'static str {
+  match signal {
+    libc::SIGHUP => "SIGHUP",
+    libc::SIGINT => "SIGINT",
+    libc::SIGTERM => "SIGTERM",
+    _ => "signal",
+  }
+}
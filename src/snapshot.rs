@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SNAPSHOT_PATH: &str = "/var/lib/powereg/snapshot.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Knob {
+  pub path: String,
+  pub value: String,
+}
+
+/// The values of every knob powereg writes, as they were before it touched them.
+///
+/// Kept on disk until restored, so a daemon that crashed and got restarted still restores the
+/// machine's original settings instead of its own.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+  pub knobs: Vec<Knob>,
+}
+
+impl Snapshot {
+  /// Unreadable files are left out, there is nothing to restore for them.
+  pub fn capture(paths: &[String]) -> Self {
+    let knobs = paths
+      .iter()
+      .filter_map(|path| {
+        let value = fs::read_to_string(path).ok()?;
+        Some(Knob {
+          path: path.clone(),
+          value: value.trim().to_string(),
+        })
+      })
+      .collect();
+
+    Self { knobs }
+  }
+
+  pub fn load(path: &str) -> io::Result<Self> {
+    toml::from_str(&fs::read_to_string(path)?)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+  }
+
+  /// Written to a temp file first, a half written snapshot would be worse than none.
+  pub fn save(&self, path: &str) -> io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+      fs::create_dir_all(parent)?;
+    }
+
+    let contents = toml::to_string(self).map_err(io::Error::other)?;
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, contents)?;
+    fs::rename(tmp_path, path)
+  }
+
+  /// The saved snapshot if there is one (left behind by a daemon that didn't shut down
+  /// cleanly), otherwise a fresh capture of `paths` which gets saved.
+  pub fn load_or_capture(path: &str, paths: &[String]) -> io::Result<Self> {
+    if Path::new(path).exists() {
+      match Self::load(path) {
+        Ok(snapshot) => return Ok(snapshot),
+        Err(e) => eprintln!("Ignoring unreadable snapshot {}: {}", path, e),
+      }
+    }
+
+    let snapshot = Self::capture(paths);
+    snapshot.save(path)?;
+    Ok(snapshot)
+  }
+
+  /// Writes every value back. Knobs that depend on each other (min/max frequency, charge
+  /// start/stop) can fail depending on order, so failed writes get a second pass. Returns the
+  /// knobs that still couldn't be restored.
+  pub fn restore(&self) -> Vec<(String, io::Error)> {
+    let mut failed: Vec<&Knob> = vec![];
+    for knob in &self.knobs {
+      if fs::write(&knob.path, &knob.value).is_err() {
+        failed.push(knob);
+      }
+    }
+
+    failed
+      .into_iter()
+      .filter_map(|knob| {
+        fs::write(&knob.path, &knob.value)
+          .err()
+          .map(|e| (knob.path.clone(), e))
+      })
+      .collect()
+  }
+}
+
+/// Restores and removes the snapshot at `path`, reporting what couldn't be restored.
+pub fn restore_snapshot(path: &str) -> io::Result<()> {
+  let snapshot = Snapshot::load(path)?;
+  println!("Restoring {} settings from {}", snapshot.knobs.len(), path);
+
+  let failed = snapshot.restore();
+  for (knob, e) in &failed {
+    eprintln!("Failed to restore {}: {}", knob, e);
+  }
+  if !failed.is_empty() {
+    return Err(io::Error::other(format!(
+      "{} settings could not be restored, keeping {}",
+      failed.len(),
+      path
+    )));
+  }
+
+  fs::remove_file(path)
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Prefix for every /sys and /proc path powereg touches, so the daemon can run against a fake
+/// directory tree (see `fixture::SysfsFixture`) instead of real hardware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SysfsRoot {
+  root: PathBuf,
+}
+
+impl Default for SysfsRoot {
+  fn default() -> Self {
+    Self::system()
+  }
+}
+
+impl fmt::Display for SysfsRoot {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.root.display())
+  }
+}
+
+impl SysfsRoot {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self { root: root.into() }
+  }
+
+  pub fn system() -> Self {
+    Self::new("/")
+  }
+
+  pub fn is_system(&self) -> bool {
+    self.root == Path::new("/")
+  }
+
+  /// Maps an absolute path like "/sys/class/power_supply" under this root.
+  pub fn path(&self, path: &str) -> String {
+    self
+      .root
+      .join(path.trim_start_matches('/'))
+      .to_string_lossy()
+      .to_string()
+  }
+
+  pub fn exists(&self, path: &str) -> bool {
+    Path::new(&self.path(path)).exists()
+  }
+}
@@ -1,7 +1,16 @@
+use crate::adapter::{AdapterStates, AdapterStatesError};
 use crate::battery::{
   ACPIType, BatteryStates, BatteryStatesError, ChargingStatus, PlatformProfile,
 };
-use crate::cpu::{CpuStates, CpuStatesError, CpuType, ScalingGoverner, EPP};
+use crate::calibration::CALIBRATION_PATH;
+use crate::control::AppliedSettings;
+use crate::cpu::{CpuStates, CpuStatesError, CpuType, PerCore, ScalingGoverner, EPP};
+use crate::policy::{LoadDebounce, Policy, Throttle};
+use crate::profile::{Boost, Profile, Profiles};
+use crate::snapshot::{Snapshot, SNAPSHOT_PATH};
+use crate::sysfs::SysfsRoot;
+use crate::utils::is_transient_io_error;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::fmt;
 use std::fs;
@@ -10,24 +19,39 @@ use std::path::Path;
 
 #[derive(Debug)]
 pub enum SystemStateError {
-  ACPITypeErr(String),
+  UnsupportedErr(String),
 
   CpuStatesErr(CpuStatesError),
   BatteryStatesErr(BatteryStatesError),
+  AdapterStatesErr(AdapterStatesError),
   GeneralIoErr(io::Error),
 }
 
 impl fmt::Display for SystemStateError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
-      SystemStateError::ACPITypeErr(e) => write!(f, "{e}"),
+      SystemStateError::UnsupportedErr(e) => write!(f, "{e}"),
       SystemStateError::CpuStatesErr(e) => write!(f, "{e}"),
       SystemStateError::BatteryStatesErr(e) => write!(f, "{e}"),
+      SystemStateError::AdapterStatesErr(e) => write!(f, "{e}"),
       SystemStateError::GeneralIoErr(e) => write!(f, "General io error: {e}"),
     }
   }
 }
 
+impl SystemStateError {
+  /// Whether the daemon should retry instead of giving up, see `Daemon::run`.
+  pub fn is_transient(&self) -> bool {
+    match self {
+      SystemStateError::UnsupportedErr(_) => false,
+      SystemStateError::CpuStatesErr(e) => e.is_transient(),
+      SystemStateError::BatteryStatesErr(e) => e.is_transient(),
+      SystemStateError::AdapterStatesErr(e) => e.is_transient(),
+      SystemStateError::GeneralIoErr(e) => is_transient_io_error(e),
+    }
+  }
+}
+
 impl From<CpuStatesError> for SystemStateError {
   fn from(error: CpuStatesError) -> Self {
     SystemStateError::CpuStatesErr(error)
@@ -40,130 +64,382 @@ impl From<BatteryStatesError> for SystemStateError {
   }
 }
 
+impl From<AdapterStatesError> for SystemStateError {
+  fn from(error: AdapterStatesError) -> Self {
+    SystemStateError::AdapterStatesErr(error)
+  }
+}
+
 impl From<io::Error> for SystemStateError {
   fn from(error: io::Error) -> Self {
     SystemStateError::GeneralIoErr(error)
   }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum State {
   Powersave,
   Balanced,
   Performance,
 }
 
+impl State {
+  const POWERSAVE: &str = "powersave";
+  const BALANCED: &str = "balanced";
+  const PERFORMANCE: &str = "performance";
+
+  pub fn from_string(s: &str) -> Option<Self> {
+    match s {
+      State::POWERSAVE => Some(Self::Powersave),
+      State::BALANCED => Some(Self::Balanced),
+      State::PERFORMANCE => Some(Self::Performance),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for State {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Powersave => write!(f, "{}", State::POWERSAVE),
+      Self::Balanced => write!(f, "{}", State::BALANCED),
+      Self::Performance => write!(f, "{}", State::PERFORMANCE),
+    }
+  }
+}
+
 pub struct SystemState {
+  pub root: SysfsRoot,
   pub linux: bool,
   pub cpu_type: CpuType,
   pub acpi_type: ACPIType,
 
   pub cpu_states: CpuStates,
   pub battery_states: BatteryStates,
+  pub adapter_states: AdapterStates,
 
   pub state: RefCell<State>,
+  pub profiles: RefCell<Profiles>,
+  pub policy: RefCell<Policy>,
+  pub load_debounce: RefCell<LoadDebounce>,
+  pub throttle: RefCell<Throttle>,
 }
 
 impl fmt::Display for SystemState {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(
       f,
-      "{}\n{}\nSystem state: {:?}",
+      "{}\n{}\n{}\nSystem state: {:?}",
       self.cpu_states,
       self.battery_states,
+      self.adapter_states,
       *self.state.borrow(),
     )
   }
 }
 
 impl SystemState {
-  pub fn init() -> Result<Self, SystemStateError> {
-    let cpu_type = Self::detect_cpu_type();
-    let cpu_states = CpuStates::init(Self::num_cpu_cores()?, &cpu_type)?;
+  pub fn init(root: &SysfsRoot) -> Result<Self, SystemStateError> {
+    let cpu_type = Self::detect_cpu_type(root);
+    let acpi_type = Self::detect_acpi_type(root);
+    let cpu_states = CpuStates::init(root, &cpu_type)?;
+    let battery_states = BatteryStates::init(root, &acpi_type)?;
     Ok(Self {
+      root: root.clone(),
       linux: Self::detect_linux(),
       cpu_type,
-      acpi_type: Self::detect_acpi_type(),
+      acpi_type,
 
       cpu_states,
-      battery_states: BatteryStates::init()?,
+      battery_states,
+      adapter_states: AdapterStates::init(root)?,
 
       state: RefCell::new(State::Powersave),
+      profiles: RefCell::new(Profiles::default()),
+      policy: RefCell::new(Policy::default()),
+      load_debounce: RefCell::new(LoadDebounce::default()),
+      throttle: RefCell::new(Throttle::default()),
     })
   }
 
   pub fn post_init(&self) -> Result<(), SystemStateError> {
-    match self.battery_states.read_charging_status()? {
-      ChargingStatus::Charging | ChargingStatus::NotCharging => {
-        self.set_performance_mode(false)?;
-        *self.state.borrow_mut() = State::Performance;
-      }
-      ChargingStatus::DisCharging => {
-        self.set_powersave_mode()?;
-        *self.state.borrow_mut() = State::Powersave;
-      }
-      ChargingStatus::Unknown => {
-        self.set_balanced_mode()?;
-        *self.state.borrow_mut() = State::Powersave;
-      }
+    if self.on_ac()? {
+      self.set_performance_mode(false)?;
+      *self.state.borrow_mut() = State::Performance;
+    } else {
+      self.set_powersave_mode()?;
+      *self.state.borrow_mut() = State::Powersave;
     }
 
     Ok(())
   }
 
+  /// Every file powereg may write to.
+  pub fn knob_paths(&self) -> Vec<String> {
+    let mut paths = self.cpu_states.knob_paths();
+    paths.extend(self.battery_states.knob_paths());
+    paths
+  }
+
+  pub fn snapshot_path(&self) -> String {
+    self.root.path(SNAPSHOT_PATH)
+  }
+
+  pub fn calibration_path(&self) -> String {
+    self.root.path(CALIBRATION_PATH)
+  }
+
+  /// Saves the original value of every knob, has to run before anything gets applied. A
+  /// snapshot that's already saved is kept, it still holds the values from before powereg.
+  pub fn save_snapshot(&self) -> io::Result<Snapshot> {
+    Snapshot::load_or_capture(&self.snapshot_path(), &self.knob_paths())
+  }
+
+  /// Ac adapters are authoritative; the battery's charging status is only a fallback since it
+  /// reports "Not charging" once a charge threshold is reached.
+  pub fn on_ac(&self) -> Result<bool, SystemStateError> {
+    if self.adapter_states.has_adapter() {
+      return Ok(self.adapter_states.read_online()?);
+    }
+
+    if !self.battery_states.has_battery() {
+      return Ok(true);
+    }
+
+    Ok(self.battery_states.read_charging_status()? != ChargingStatus::DisCharging)
+  }
+
   pub fn set_powersave_mode(&self) -> Result<(), SystemStateError> {
-    self
-      .cpu_states
-      .set_scaling_governer(ScalingGoverner::Powersave)?;
+    self.apply_profile(&self.profiles.borrow().for_state(State::Powersave), false)
+  }
 
-    self.cpu_states.set_epp(EPP::Power)?;
+  /// Used while the cpu is too hot on ac, see `Event::periodic_check`.
+  pub fn set_balanced_mode(&self) -> Result<(), SystemStateError> {
+    self.apply_profile(&self.profiles.borrow().for_state(State::Balanced), false)
+  }
 
-    self
-      .battery_states
-      .set_platform_profile(&PlatformProfile::LowPower)?;
+  pub fn set_performance_mode(&self, cpu_boost: bool) -> Result<(), SystemStateError> {
+    if !self.on_ac()? {
+      return Ok(());
+    }
+
+    self.apply_performance_mode(cpu_boost)
+  }
+
+  /// What differs from the current state's profile on some cores, e.g. because something else
+  /// wrote to them or a core was hotplugged. Empty if every core matches.
+  pub fn detect_drift(&self) -> Result<Vec<String>, SystemStateError> {
+    let profile = self.profiles.borrow().for_state(*self.state.borrow());
+    let cpus = self.cpu_states.online_cpus();
+    let mut drift = vec![];
+
+    let mut check = |name: &str, cores: Vec<usize>, current: String| {
+      if !cores.is_empty() {
+        drift.push(format!("{name} on cpu {cores:?} (now {current})"));
+      }
+    };
 
-    self.cpu_states.set_cpu_boost(false)?;
+    if let Some(governor) = &profile.governor {
+      let current = self.cpu_states.read_scaling_governer()?;
+      let cores = current.drifted(&ScalingGoverner::from_string(governor), &cpus);
+      check("scaling governer", cores, current.to_string());
+    }
+    if let Some(epp) = &profile.epp {
+      let current = match self.cpu_states.read_epp() {
+        Err(CpuStatesError::Unsupported) => PerCore::Uniform(EPP::from_string(epp)),
+        current => current?,
+      };
+      let cores = current.drifted(&EPP::from_string(epp), &cpus);
+      check("epp", cores, current.to_string());
+    }
+    if let Some(mhz) = profile.min_freq_mhz {
+      let current = self.cpu_states.read_min_cpu_freq()?;
+      let cores = current.drifted(&(mhz as usize * 1000), &cpus);
+      check("min freq", cores, current.to_string());
+    }
+    // a throttled max frequency is supposed to differ
+    if let Some(mhz) = profile.max_freq_mhz
+      && self.throttle.borrow().level() == 0
+    {
+      let current = self.cpu_states.read_max_cpu_freq()?;
+      let cores = current.drifted(&(mhz as usize * 1000), &cpus);
+      check("max freq", cores, current.to_string());
+    }
+
+    Ok(drift)
+  }
 
+  /// Writes the current state's profile to every core again, keeping the boost `auto` resolved
+  /// to and any throttling.
+  pub fn reconverge(&self) -> Result<(), SystemStateError> {
+    match *self.state.borrow() {
+      State::Performance => {
+        let boost = self.cpu_states.capabilities().boost && self.cpu_states.read_cpu_boost()?;
+        self.apply_performance_mode(boost)?;
+      }
+      state => self.apply_state(state)?,
+    }
+    if self.throttle.borrow().level() > 0 {
+      self.apply_throttle()?;
+    }
     Ok(())
   }
 
-  // for now, only for high cpu temp situations when charging
-  pub fn set_balanced_mode(&self) -> Result<(), SystemStateError> {
-    self
-      .cpu_states
-      .set_scaling_governer(ScalingGoverner::Powersave)?;
+  /// Puts the throttle's limits on top of the current state's profile. Unthrottled, the max
+  /// frequency goes back to the profile's or the hardware's, boost is left to the profile.
+  pub fn apply_throttle(&self) -> Result<(), SystemStateError> {
+    let throttle = self.throttle.borrow();
+    let profile = self.profiles.borrow().for_state(*self.state.borrow());
+
+    if throttle.boost_off() && self.cpu_states.capabilities().boost {
+      self.cpu_states.set_cpu_boost(false)?;
+    }
+    self.cpu_states.cap_max_freq(
+      throttle.max_freq_pct(),
+      profile.max_freq_mhz.map(|mhz| mhz as usize * 1000),
+    )?;
+    Ok(())
+  }
 
-    self.cpu_states.set_epp(EPP::BalancePower)?;
+  /// Applies a state unconditionally, even performance on battery.
+  pub fn apply_state(&self, state: State) -> Result<(), SystemStateError> {
+    match state {
+      State::Powersave => self.set_powersave_mode(),
+      State::Balanced => self.set_balanced_mode(),
+      State::Performance => self.apply_performance_mode(true),
+    }
+  }
 
-    self
-      .battery_states
-      .set_platform_profile(&PlatformProfile::Balanced)?;
+  fn apply_performance_mode(&self, cpu_boost: bool) -> Result<(), SystemStateError> {
+    self.apply_profile(
+      &self.profiles.borrow().for_state(State::Performance),
+      cpu_boost,
+    )
+  }
+
+  /// Whether this machine offers the profile's governor and epp.
+  pub fn check_profile(&self, profile: &Profile) -> Result<(), String> {
+    if let Some(governor) = &profile.governor
+      && self
+        .cpu_states
+        .check_governor(&ScalingGoverner::from_string(governor))
+        .is_err()
+    {
+      let available: Vec<String> = self
+        .cpu_states
+        .available_governors()
+        .iter()
+        .map(|g| g.to_string())
+        .collect();
+      return Err(format!(
+        "governor '{governor}' not available (available: {})",
+        available.join(" ")
+      ));
+    }
 
-    self.cpu_states.set_cpu_boost(false)?;
+    if let Some(epp) = &profile.epp {
+      match self.cpu_states.check_epp(&EPP::from_string(epp)) {
+        Ok(_) => {}
+        // left out when applied, like the other knobs a cpu doesn't have
+        Err(CpuStatesError::Unsupported) => {}
+        Err(_) => {
+          let available: Vec<String> = self
+            .cpu_states
+            .available_epp()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+          return Err(format!(
+            "epp '{epp}' not available (available: {})",
+            available.join(" ")
+          ));
+        }
+      }
+    }
 
     Ok(())
   }
 
-  pub fn set_performance_mode(&self, cpu_boost: bool) -> Result<(), SystemStateError> {
-    if self.battery_states.read_charging_status()? == ChargingStatus::DisCharging {
-      return Ok(());
+  /// `auto_boost` is what `boost = "auto"` resolves to.
+  pub fn apply_profile(&self, profile: &Profile, auto_boost: bool) -> Result<(), SystemStateError> {
+    if let Some(governor) = &profile.governor {
+      self
+        .cpu_states
+        .set_scaling_governer(ScalingGoverner::from_string(governor))?;
     }
 
-    self
-      .cpu_states
-      .set_scaling_governer(ScalingGoverner::Performance)?;
+    if let Some(epp) = &profile.epp {
+      self.cpu_states.set_epp(EPP::from_string(epp))?;
+    }
 
-    self.cpu_states.set_epp(EPP::Performance)?;
+    if let Some(platform_profile) = &profile.platform_profile
+      && self.battery_states.has_platform_profile()
+    {
+      self
+        .battery_states
+        .set_platform_profile(&PlatformProfile::from_string(platform_profile))?;
+    }
 
-    self
-      .battery_states
-      .set_platform_profile(&PlatformProfile::Performance)?;
+    if let Some(boost) = profile.boost {
+      self.cpu_states.set_cpu_boost(match boost {
+        Boost::On => true,
+        Boost::Off => false,
+        Boost::Auto => auto_boost,
+      })?;
+    }
+
+    self.cpu_states.set_cpu_freq_limits(
+      profile.min_freq_mhz.map(|mhz| mhz as usize * 1000),
+      profile.max_freq_mhz.map(|mhz| mhz as usize * 1000),
+    )?;
+
+    if self.cpu_states.capabilities().perf_pct {
+      let (min, max) = (profile.min_perf_pct, profile.max_perf_pct);
+      let (_, current_max) = self.cpu_states.read_perf_pct()?;
+      let max_first = min.is_some_and(|min| usize::from(min) > current_max);
+
+      if max_first && let Some(max) = max {
+        self.cpu_states.set_max_perf_pct(max.into())?;
+      }
+      if let Some(min) = min {
+        self.cpu_states.set_min_perf_pct(min.into())?;
+      }
+      if !max_first && let Some(max) = max {
+        self.cpu_states.set_max_perf_pct(max.into())?;
+      }
+    }
 
-    self.cpu_states.set_cpu_boost(cpu_boost)?;
+    self
+      .cpu_states
+      .set_power_limits(profile.package_power_limit_w, profile.burst_power_limit_w)?;
 
     Ok(())
   }
 
+  pub fn read_applied_settings(&self) -> AppliedSettings {
+    AppliedSettings {
+      governor: self
+        .cpu_states
+        .read_scaling_governer()
+        .map(|governor| governor.to_string())
+        .unwrap_or("unknown".to_string()),
+      epp: self
+        .cpu_states
+        .read_epp()
+        .map(|epp| epp.to_string())
+        .unwrap_or("unknown".to_string()),
+      boost: self.cpu_states.read_cpu_boost().unwrap_or(false),
+      platform_profile: self
+        .battery_states
+        .read_platform_profile()
+        .unwrap_or(PlatformProfile::Unknown)
+        .to_string(),
+      charge_start_threshold: self.battery_states.read_charge_start_threshold().ok(),
+      charge_stop_threshold: self.battery_states.read_charge_stop_threshold().ok(),
+    }
+  }
+
   // TODO: look over all of these again bc it's vibe coded
   fn detect_linux() -> bool {
     #[cfg(target_os = "linux")]
@@ -191,8 +467,8 @@ impl SystemState {
       || (has_proc && has_sys && has_etc && has_os_release)
   }
 
-  fn detect_cpu_type() -> CpuType {
-    if let Ok(cpuinfo) = fs::read_to_string("/proc/cpuinfo") {
+  fn detect_cpu_type(root: &SysfsRoot) -> CpuType {
+    if let Ok(cpuinfo) = fs::read_to_string(root.path("/proc/cpuinfo")) {
       for line in cpuinfo.lines() {
         if line.starts_with("vendor_id") {
           if line.contains("GenuineIntel") {
@@ -204,6 +480,11 @@ impl SystemState {
       }
     }
 
+    // the host's cpu says nothing about a simulated tree
+    if !root.is_system() {
+      return CpuType::Unknown;
+    }
+
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
       if let Some(cpu_type) = Self::detect_cpu_via_cpuid() {
@@ -250,51 +531,40 @@ impl SystemState {
     }
   }
 
-  fn num_cpu_cores() -> Result<usize, SystemStateError> {
-    let cpu_dir = "/sys/devices/system/cpu/";
-    let mut count = 0;
-
-    for entry in fs::read_dir(cpu_dir)? {
-      let entry = entry?;
-      let path = entry.path();
-
-      if path.is_dir() {
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-          if name.starts_with("cpu") && name != "cpufreq" {
-            // Check if the remaining part of the name is a number
-            if name[3..].parse::<u32>().is_ok() {
-              count += 1;
-            }
-          }
-        }
-      }
+  /// Which laptop vendor's platform driver to expect, from the DMI strings.
+  fn detect_acpi_type(root: &SysfsRoot) -> ACPIType {
+    let dmi = |file: &str| {
+      fs::read_to_string(root.path(&format!("/sys/class/dmi/id/{file}")))
+        .map(|s| s.trim().to_lowercase())
+        .unwrap_or_default()
+    };
+    let (vendor, product_name, product_version) = (
+      dmi("sys_vendor"),
+      dmi("product_name"),
+      dmi("product_version"),
+    );
+
+    if product_version.contains("thinkpad")
+      || product_name.contains("thinkpad")
+      || root.exists("/proc/acpi/ibm")
+    {
+      return ACPIType::ThinkPad;
     }
-    Ok(count)
-  }
-
-  fn detect_acpi_type() -> ACPIType {
-    if let Ok(product_version) = fs::read_to_string("/sys/class/dmi/id/product_version") {
-      let product_version = product_version.trim().to_lowercase();
-      if product_version.contains("thinkpad") {
-        return ACPIType::ThinkPad;
-      }
-      //if product_version.contains("ideapad") {
-      //    return ACPIType::IdeaPad;
-      //}
+    if product_version.contains("ideapad") || product_name.contains("ideapad") {
+      return ACPIType::IdeaPad;
     }
 
-    if let Ok(product_name) = fs::read_to_string("/sys/class/dmi/id/product_name") {
-      let product_name = product_name.trim().to_lowercase();
-      if product_name.contains("thinkpad") {
-        return ACPIType::ThinkPad;
+    for (prefix, acpi_type) in [
+      ("asustek", ACPIType::Asus),
+      ("dell", ACPIType::Dell),
+      ("framework", ACPIType::Framework),
+      ("huawei", ACPIType::Huawei),
+      ("lg electronics", ACPIType::LG),
+      ("samsung", ACPIType::Samsung),
+    ] {
+      if vendor.starts_with(prefix) {
+        return acpi_type;
       }
-      //if product_name.contains("ideapad") {
-      //    return ACPIType::IdeaPad;
-      //}
-    }
-
-    if Path::new("/proc/acpi/ibm").exists() {
-      return ACPIType::ThinkPad;
     }
 
     ACPIType::Unknown
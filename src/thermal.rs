@@ -0,0 +1,230 @@
+use crate::sysfs::SysfsRoot;
+use crate::utils::{PersFd, PersFdError};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::num;
+
+pub const HWMON_PATH: &str = "/sys/class/hwmon";
+pub const THERMAL_PATH: &str = "/sys/class/thermal";
+
+/// Which sensor measures the cpu, best first: (hwmon name, labels) and thermal zone types.
+const CPU_HWMON: &[(&str, &[&str])] = &[
+  ("k10temp", &["Tdie", "Tctl"]),
+  ("zenpower", &["Tdie", "Tctl"]),
+  ("coretemp", &["Package id 0"]),
+  ("thinkpad", &["CPU", "temp1"]),
+];
+const CPU_ZONES: &[&str] = &["x86_pkg_temp", "acpitz"];
+
+#[derive(Debug)]
+pub enum ThermalError {
+  NoSensor,
+  UnknownSensor(String),
+  PersFdErr(PersFdError),
+  ParseIntErr(num::ParseIntError),
+}
+
+impl fmt::Display for ThermalError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ThermalError::NoSensor => write!(f, "No cpu temperature sensor found"),
+      ThermalError::UnknownSensor(name) => write!(f, "No temperature sensor named '{name}'"),
+      ThermalError::PersFdErr(e) => write!(f, "{e}"),
+      ThermalError::ParseIntErr(e) => write!(f, "Failed parsing integer: {e}"),
+    }
+  }
+}
+
+impl ThermalError {
+  pub fn is_transient(&self) -> bool {
+    match self {
+      ThermalError::NoSensor | ThermalError::UnknownSensor(_) => false,
+      ThermalError::ParseIntErr(_) => true,
+      ThermalError::PersFdErr(e) => e.is_transient(),
+    }
+  }
+}
+
+impl From<PersFdError> for ThermalError {
+  fn from(error: PersFdError) -> Self {
+    ThermalError::PersFdErr(error)
+  }
+}
+
+impl From<num::ParseIntError> for ThermalError {
+  fn from(error: num::ParseIntError) -> Self {
+    ThermalError::ParseIntErr(error)
+  }
+}
+
+pub struct Sensor {
+  /// "k10temp/Tctl" for hwmon inputs, "thermal/x86_pkg_temp" for thermal zones, what the config
+  /// uses to pick one
+  pub name: String,
+  input: RefCell<PersFd>,
+}
+
+impl Sensor {
+  /// celcius
+  pub fn read_temp(&self) -> Result<usize, ThermalError> {
+    let millidegrees: i64 = self.input.borrow_mut().read_value()?.parse()?;
+    Ok((millidegrees.max(0) / 1000) as usize)
+  }
+}
+
+/// Every temperature sensor of the machine, and which of them is the cpu's.
+pub struct Thermal {
+  sensors: Vec<Sensor>,
+  cpu_sensor: RefCell<Option<usize>>,
+}
+
+impl Thermal {
+  pub fn discover(root: &SysfsRoot) -> Self {
+    let mut sensors = Self::discover_hwmon(root);
+    sensors.extend(Self::discover_zones(root));
+    let cpu_sensor = Self::detect_cpu_sensor(&sensors);
+
+    Self {
+      sensors,
+      cpu_sensor: RefCell::new(cpu_sensor),
+    }
+  }
+
+  /// `tempN_input` of every hwmon device, named by `tempN_label` if there is one.
+  fn discover_hwmon(root: &SysfsRoot) -> Vec<Sensor> {
+    let mut sensors = vec![];
+
+    for dir in sorted_entries(&root.path(HWMON_PATH)) {
+      let Ok(chip) = fs::read_to_string(format!("{dir}/name")) else {
+        continue;
+      };
+      let chip = chip.trim();
+
+      let mut inputs: Vec<(usize, String)> = sorted_entries(&dir)
+        .into_iter()
+        .filter_map(|path| {
+          let file = path.rsplit('/').next()?;
+          let n = file
+            .strip_prefix("temp")?
+            .strip_suffix("_input")?
+            .parse()
+            .ok()?;
+          Some((n, path))
+        })
+        .collect();
+      inputs.sort();
+
+      for (n, input) in inputs {
+        let label = fs::read_to_string(format!("{dir}/temp{n}_label"))
+          .map(|label| label.trim().to_string())
+          .unwrap_or(format!("temp{n}"));
+        if let Ok(fd) = PersFd::new(&input, false) {
+          sensors.push(Sensor {
+            name: format!("{chip}/{label}"),
+            input: RefCell::new(fd),
+          });
+        }
+      }
+    }
+
+    sensors
+  }
+
+  fn discover_zones(root: &SysfsRoot) -> Vec<Sensor> {
+    sorted_entries(&root.path(THERMAL_PATH))
+      .into_iter()
+      .filter(|dir| {
+        dir
+          .rsplit('/')
+          .next()
+          .is_some_and(|d| d.starts_with("thermal_zone"))
+      })
+      .filter_map(|dir| {
+        let zone_type = fs::read_to_string(format!("{dir}/type")).ok()?;
+        let fd = PersFd::new(&format!("{dir}/temp"), false).ok()?;
+        Some(Sensor {
+          name: format!("thermal/{}", zone_type.trim()),
+          input: RefCell::new(fd),
+        })
+      })
+      .collect()
+  }
+
+  fn detect_cpu_sensor(sensors: &[Sensor]) -> Option<usize> {
+    let hwmon = CPU_HWMON
+      .iter()
+      .flat_map(|(chip, labels)| labels.iter().map(move |label| format!("{chip}/{label}")));
+    let zones = CPU_ZONES.iter().map(|zone| format!("thermal/{zone}"));
+
+    hwmon
+      .chain(zones)
+      .find_map(|name| sensors.iter().position(|s| s.name == name))
+  }
+
+  pub fn sensors(&self) -> &[Sensor] {
+    &self.sensors
+  }
+
+  pub fn cpu_sensor(&self) -> Option<&Sensor> {
+    self.cpu_sensor.borrow().map(|i| &self.sensors[i])
+  }
+
+  /// Index of the sensor `select` would pick, without picking it.
+  fn find(&self, name: Option<&str>) -> Result<Option<usize>, ThermalError> {
+    match name {
+      Some(name) => Ok(Some(
+        self
+          .sensors
+          .iter()
+          .position(|s| s.name == name)
+          .ok_or(ThermalError::UnknownSensor(name.to_string()))?,
+      )),
+      None => Ok(Self::detect_cpu_sensor(&self.sensors)),
+    }
+  }
+
+  /// Whether `select` would take `name`.
+  pub fn check(&self, name: Option<&str>) -> Result<(), ThermalError> {
+    self.find(name).map(|_| ())
+  }
+
+  /// Overrides the detected cpu sensor, None goes back to detection.
+  pub fn select(&self, name: Option<&str>) -> Result<(), ThermalError> {
+    *self.cpu_sensor.borrow_mut() = self.find(name)?;
+    Ok(())
+  }
+
+  /// celcius
+  pub fn read_cpu_temp(&self) -> Result<usize, ThermalError> {
+    self.cpu_sensor().ok_or(ThermalError::NoSensor)?.read_temp()
+  }
+}
+
+impl fmt::Display for Thermal {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Sensors:")?;
+    let cpu_sensor = *self.cpu_sensor.borrow();
+    for (i, sensor) in self.sensors.iter().enumerate() {
+      let temp = match sensor.read_temp() {
+        Ok(temp) => format!("{temp}°C"),
+        Err(_) => "unreadable".to_string(),
+      };
+      let cpu = if cpu_sensor == Some(i) { " (cpu)" } else { "" };
+      write!(f, "\n    {}: {}{}", sensor.name, temp, cpu)?;
+    }
+    Ok(())
+  }
+}
+
+fn sorted_entries(dir: &str) -> Vec<String> {
+  let mut entries: Vec<String> = fs::read_dir(dir)
+    .map(|entries| {
+      entries
+        .filter_map(|entry| Some(entry.ok()?.path().to_string_lossy().to_string()))
+        .collect()
+    })
+    .unwrap_or_default();
+  entries.sort();
+  entries
+}
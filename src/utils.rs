@@ -1,6 +1,10 @@
-use crate::battery::ACPIType;
+use crate::battery_backend::ChargeCapabilities;
+use crate::policy::Policy;
+use crate::profile::{Profile, Profiles, StateProfiles};
 use crate::system_state::{SystemState, SystemStateError};
+use crate::thermal::ThermalError;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt;
 use std::fs;
@@ -25,10 +29,31 @@ impl fmt::Display for PersFdError {
   }
 }
 
+impl PersFdError {
+  pub fn is_transient(&self) -> bool {
+    match self {
+      PersFdError::InvalidFilePerms => false,
+      PersFdError::ReadErr(e) | PersFdError::WriteErr(e) => is_transient_io_error(e),
+    }
+  }
+}
+
+/// Errors sysfs returns while a device is briefly gone or busy, e.g. right after resume, and
+/// that are worth retrying.
+pub fn is_transient_io_error(error: &io::Error) -> bool {
+  matches!(
+    error.kind(),
+    ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+  ) || matches!(
+    error.raw_os_error(),
+    Some(libc::EAGAIN | libc::EBUSY | libc::EIO | libc::ENODEV | libc::ENXIO | libc::ENOENT)
+  )
+}
+
 pub struct PersFd {
   file: File,
   write: bool,
-  //path: String,
+  path: String,
 }
 
 impl PersFd {
@@ -39,7 +64,15 @@ impl PersFd {
       .open(path)
       .map_err(PersFdError::ReadErr)?;
 
-    Ok(PersFd { file, write })
+    Ok(PersFd {
+      file,
+      write,
+      path: path.to_string(),
+    })
+  }
+
+  pub fn path(&self) -> &str {
+    &self.path
   }
 
   pub fn read_value(&mut self) -> Result<String, PersFdError> {
@@ -95,19 +128,41 @@ impl StyledString for str {
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct ConfigFile {
-  battery: BatteryConfig,
+  battery: Option<BatteryConfig>,
+  #[serde(default)]
+  profiles: BTreeMap<String, Profile>,
+  #[serde(default)]
+  states: StateProfiles,
+  #[serde(default)]
+  policy: Policy,
+  #[serde(default)]
+  thermal: ThermalConfig,
 }
 
 #[derive(Deserialize)]
 struct BatteryConfig {
-  start_threshold: u8,
+  /// left out where the hardware only has a stop threshold
+  #[serde(default)]
+  start_threshold: Option<u8>,
   stop_threshold: u8,
 }
 
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ThermalConfig {
+  sensor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Config {
   pub charge_start_threshold: Option<u8>,
   pub charge_stop_threshold: Option<u8>,
+  pub profiles: Profiles,
+  pub policy: Policy,
+  /// name of the sensor driving policy (see `powereg sensors`), None for the detected one
+  pub thermal_sensor: Option<String>,
 }
 
 impl fmt::Display for Config {
@@ -127,31 +182,195 @@ impl Config {
 
     let contents = fs::read_to_string(config_path)?;
     let config_file: ConfigFile = toml::from_str(&contents)?;
-    Ok(Self {
-      charge_start_threshold: Some(config_file.battery.start_threshold),
-      charge_stop_threshold: Some(config_file.battery.stop_threshold),
-    })
+    let config = Self {
+      charge_start_threshold: config_file.battery.as_ref().and_then(|b| b.start_threshold),
+      charge_stop_threshold: config_file.battery.as_ref().map(|b| b.stop_threshold),
+      profiles: Profiles::new(config_file.profiles, config_file.states),
+      policy: config_file.policy,
+      thermal_sensor: config_file.thermal.sensor,
+    };
+    config.validate()?;
+    Ok(config)
   }
 
-  pub fn apply(&self, system_state: &SystemState) -> Result<(), SystemStateError> {
-    if system_state.acpi_type != ACPIType::ThinkPad {
-      return Err(SystemStateError::ACPITypeErr(
-        "only thinkpad acpi supported for now".to_string(),
+  pub fn validate(&self) -> Result<(), String> {
+    for (name, threshold) in [
+      ("start_threshold", self.charge_start_threshold),
+      ("stop_threshold", self.charge_stop_threshold),
+    ] {
+      if let Some(threshold) = threshold
+        && threshold > 100
+      {
+        return Err(format!("battery.{name} must be 0-100, got {threshold}"));
+      }
+    }
+
+    if let (Some(start), Some(stop)) = (self.charge_start_threshold, self.charge_stop_threshold)
+      && start >= stop
+    {
+      return Err(format!(
+        "battery.start_threshold ({start}) must be below battery.stop_threshold ({stop})"
       ));
     }
 
-    if let Some(start_thresh) = self.charge_start_threshold {
-      println!("Setting charge start threshold to {}", start_thresh);
-      system_state
-        .battery_states
-        .set_charge_start_threshold(start_thresh.into())?;
+    self.profiles.validate()?;
+    self.policy.validate()
+  }
+
+  /// Human readable list of the settings that differ from `old`.
+  pub fn diff(&self, old: &Config) -> Vec<String> {
+    let mut diff = vec![];
+    for (name, old, new) in [
+      (
+        "battery.start_threshold",
+        old.charge_start_threshold,
+        self.charge_start_threshold,
+      ),
+      (
+        "battery.stop_threshold",
+        old.charge_stop_threshold,
+        self.charge_stop_threshold,
+      ),
+    ] {
+      if old != new {
+        let show = |v: Option<u8>| v.map_or("unset".to_string(), |v| v.to_string());
+        diff.push(format!("{}: {} -> {}", name, show(old), show(new)));
+      }
+    }
+    if old.thermal_sensor != self.thermal_sensor {
+      let show = |v: &Option<String>| v.clone().unwrap_or("detected".to_string());
+      diff.push(format!(
+        "thermal.sensor: {} -> {}",
+        show(&old.thermal_sensor),
+        show(&self.thermal_sensor)
+      ));
     }
+    diff.extend(self.profiles.diff(&old.profiles));
+    diff.extend(self.policy.diff(&old.policy));
+    diff
+  }
 
-    if let Some(stop_thresh) = self.charge_stop_threshold {
-      println!("Setting charge stop threshold to {}", stop_thresh);
+  /// Only the settings that differ from `old`, so applying it leaves everything else alone.
+  pub fn changes_from(&self, old: &Config) -> Config {
+    let changed = |old: Option<u8>, new: Option<u8>| if old != new { new } else { None };
+    Config {
+      charge_start_threshold: changed(old.charge_start_threshold, self.charge_start_threshold),
+      charge_stop_threshold: changed(old.charge_stop_threshold, self.charge_stop_threshold),
+      profiles: self.profiles.clone(),
+      policy: self.policy.clone(),
+      thermal_sensor: self.thermal_sensor.clone(),
+    }
+  }
+
+  /// Sets the charge thresholds and makes the profiles, policy and temperature sensor the ones
+  /// used from the next state change on. Everything is checked before anything is changed, a
+  /// rejected config leaves the current one in place.
+  pub fn apply(&self, system_state: &SystemState) -> Result<(), SystemStateError> {
+    for (name, profile) in self.profiles.iter() {
       system_state
+        .check_profile(profile)
+        .map_err(|e| SystemStateError::UnsupportedErr(format!("profiles.{name}: {e}")))?;
+    }
+    let thermal_err =
+      |e: ThermalError| SystemStateError::UnsupportedErr(format!("thermal.sensor: {e}"));
+    let thermal = system_state.cpu_states.thermal();
+    thermal
+      .check(self.thermal_sensor.as_deref())
+      .map_err(thermal_err)?;
+    let capabilities = self.check_thresholds(system_state)?;
+
+    *system_state.profiles.borrow_mut() = self.profiles.clone();
+    *system_state.policy.borrow_mut() = self.policy.clone();
+    thermal
+      .select(self.thermal_sensor.as_deref())
+      .map_err(thermal_err)?;
+    match capabilities {
+      Some(capabilities) => self.write_thresholds(system_state, &capabilities),
+      None => Ok(()),
+    }
+  }
+
+  /// Only the charge thresholds, nothing to do if there are none in the config.
+  pub fn apply_thresholds(&self, system_state: &SystemState) -> Result<(), SystemStateError> {
+    match self.check_thresholds(system_state)? {
+      Some(capabilities) => self.write_thresholds(system_state, &capabilities),
+      None => Ok(()),
+    }
+  }
+
+  /// What the charge control can do if the hardware takes the thresholds, None without
+  /// thresholds in the config.
+  fn check_thresholds(
+    &self,
+    system_state: &SystemState,
+  ) -> Result<Option<ChargeCapabilities>, SystemStateError> {
+    if self.charge_start_threshold.is_none() && self.charge_stop_threshold.is_none() {
+      return Ok(None);
+    }
+
+    let Some(capabilities) = system_state.battery_states.charge_capabilities() else {
+      return Err(SystemStateError::UnsupportedErr(
+        "no charge threshold control detected for this battery".to_string(),
+      ));
+    };
+
+    capabilities
+      .check(
+        self.charge_start_threshold.map(usize::from),
+        self.charge_stop_threshold.map(usize::from),
+      )
+      .map_err(|e| {
+        let backend = system_state
+          .battery_states
+          .charge_control_name()
+          .unwrap_or("charge control");
+        SystemStateError::UnsupportedErr(format!("battery.{e} ({backend})"))
+      })?;
+    Ok(Some(capabilities))
+  }
+
+  fn write_thresholds(
+    &self,
+    system_state: &SystemState,
+    capabilities: &ChargeCapabilities,
+  ) -> Result<(), SystemStateError> {
+    // `check_thresholds` already rejected a start threshold the hardware doesn't have
+    let set_start = |start_thresh: u8| -> Result<(), SystemStateError> {
+      println!("Setting charge start threshold to {}", start_thresh);
+      Ok(
+        system_state
+          .battery_states
+          .set_charge_start_threshold(start_thresh.into())?,
+      )
+    };
+    let set_stop = |stop_thresh: u8| -> Result<(), SystemStateError> {
+      if capabilities.stop.is_supported() {
+        println!("Setting charge stop threshold to {}", stop_thresh);
+        system_state
+          .battery_states
+          .set_charge_stop_threshold(stop_thresh.into())?;
+      } else {
+        println!("Charge stop threshold not supported, skipping");
+      }
+      Ok(())
+    };
+
+    // the firmware rejects a start threshold above the current stop threshold, so when both are
+    // raised past it the stop threshold has to go first
+    let raise_stop_first = match (self.charge_start_threshold, self.charge_stop_threshold) {
+      (Some(start), Some(_)) => system_state
         .battery_states
-        .set_charge_stop_threshold(stop_thresh.into())?;
+        .read_charge_stop_threshold()
+        .is_ok_and(|current_stop| usize::from(start) >= current_stop),
+      _ => false,
+    };
+
+    if raise_stop_first {
+      self.charge_stop_threshold.map(set_stop).transpose()?;
+      self.charge_start_threshold.map(set_start).transpose()?;
+    } else {
+      self.charge_start_threshold.map(set_start).transpose()?;
+      self.charge_stop_threshold.map(set_stop).transpose()?;
     }
 
     Ok(())
@@ -166,18 +385,32 @@ impl Config {
     }
   }
 
-  pub fn setup_config(system_state: &SystemState) {
+  /// Loads and applies the config, see `load`.
+  pub fn setup_config(system_state: &SystemState) -> Option<Config> {
     if let Ok(config_path) = Config::get_config_path() {
-      println!("Config path: {config_path}");
-      match Config::parse(&config_path) {
-        Ok(config) => match config.apply(&system_state) {
-          Ok(_) => {}
-          Err(e) => println!("{} {}", "Error while applying config:".red(), e),
-        },
-        Err(e) => eprintln!("{} {}", "Error loading config:".red(), e),
-      };
+      Config::load(&config_path, system_state)
     } else {
       println!("{}", "Error loading config".red());
+      None
+    }
+  }
+
+  /// Parses and applies the config, returning it when it's in effect so later reloads can be
+  /// diffed against it. None for a rejected config, the next reload then applies all of it.
+  pub fn load(config_path: &str, system_state: &SystemState) -> Option<Config> {
+    println!("Config path: {config_path}");
+    match Config::parse(config_path) {
+      Ok(config) => match config.apply(system_state) {
+        Ok(_) => Some(config),
+        Err(e) => {
+          println!("{} {}", "Error while applying config:".red(), e);
+          None
+        }
+      },
+      Err(e) => {
+        eprintln!("{} {}", "Error loading config:".red(), e);
+        None
+      }
     }
   }
 }
@@ -0,0 +1,165 @@
+use powereg::battery::ChargeBehaviour;
+use powereg::calibration::{Calibration, Phase};
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::SystemState;
+use std::fs;
+
+const BAT0: &str = "/sys/class/power_supply/BAT0";
+
+fn set_capacity(fixture: &SysfsFixture, battery: &str, capacity: usize) {
+  let dir = format!("/sys/class/power_supply/{battery}");
+  fixture
+    .write(&format!("{dir}/capacity"), &capacity.to_string())
+    .unwrap();
+  fixture
+    .write(
+      &format!("{dir}/energy_now"),
+      &(500_000 * capacity).to_string(),
+    )
+    .unwrap();
+}
+
+/// ThinkPad with thresholds 75-80 and both packs at `capacity`.
+fn thinkpad(capacity: usize) -> (SysfsFixture, SystemState) {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  for battery in ["BAT0", "BAT1"] {
+    fixture.add_charge_behaviour(battery).unwrap();
+    set_capacity(&fixture, battery, capacity);
+  }
+  let state = SystemState::init(&fixture.root()).unwrap();
+  state.battery_states.set_charge_stop_threshold(80).unwrap();
+  state.battery_states.set_charge_start_threshold(75).unwrap();
+  (fixture, state)
+}
+
+#[test]
+fn charge_behaviour_is_read_from_the_bracketed_value() {
+  let (fixture, state) = thinkpad(85);
+  let battery_states = &state.battery_states;
+
+  assert_eq!(
+    battery_states.read_charge_behaviour().unwrap(),
+    ChargeBehaviour::Auto
+  );
+  assert_eq!(
+    battery_states.batteries()[0].available_charge_behaviours(),
+    [
+      ChargeBehaviour::Auto,
+      ChargeBehaviour::InhibitCharge,
+      ChargeBehaviour::ForceDischarge
+    ]
+  );
+  assert!(battery_states.supports_charge_behaviour(ChargeBehaviour::ForceDischarge));
+
+  battery_states
+    .set_charge_behaviour(ChargeBehaviour::InhibitCharge)
+    .unwrap();
+  assert_eq!(
+    fixture.read(&format!("{BAT0}/charge_behaviour")).unwrap(),
+    "inhibit-charge"
+  );
+  assert_eq!(
+    battery_states.read_charge_behaviour().unwrap(),
+    ChargeBehaviour::InhibitCharge
+  );
+  assert!(state
+    .to_string()
+    .contains("charge behaviour: inhibit-charge"));
+
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  assert!(state.battery_states.read_charge_behaviour().is_err());
+  assert!(state
+    .battery_states
+    .set_charge_behaviour(ChargeBehaviour::Auto)
+    .is_err());
+  assert!(Calibration::start(&state.battery_states, 20).is_err());
+}
+
+#[test]
+fn calibration_discharges_charges_and_restores_the_thresholds() {
+  let (fixture, state) = thinkpad(85);
+  let battery_states = &state.battery_states;
+  let behaviour = || fixture.read(&format!("{BAT0}/charge_behaviour")).unwrap();
+  let stop = || {
+    fixture
+      .read(&format!("{BAT0}/charge_control_end_threshold"))
+      .unwrap()
+  };
+
+  assert!(Calibration::start(battery_states, 100).is_err());
+  let mut calibration = Calibration::start(battery_states, 20).unwrap();
+  assert_eq!(calibration.phase, Phase::Discharging);
+  assert_eq!(calibration.stop_threshold, Some(80));
+  calibration.apply(battery_states).unwrap();
+  assert_eq!(behaviour(), "force-discharge");
+
+  set_capacity(&fixture, "BAT0", 40);
+  set_capacity(&fixture, "BAT1", 40);
+  assert!(!calibration.update(battery_states).unwrap());
+  assert_eq!(calibration.phase, Phase::Discharging);
+
+  set_capacity(&fixture, "BAT0", 20);
+  set_capacity(&fixture, "BAT1", 20);
+  assert!(!calibration.update(battery_states).unwrap());
+  assert_eq!(calibration.phase, Phase::Charging);
+  assert_eq!(calibration.to_string(), "charging to 100%");
+  assert_eq!(behaviour(), "auto");
+  assert_eq!(stop(), "100");
+
+  set_capacity(&fixture, "BAT0", 99);
+  set_capacity(&fixture, "BAT1", 99);
+  assert!(!calibration.update(battery_states).unwrap());
+  // some packs stop short of 100% and report full
+  fixture.write(&format!("{BAT0}/status"), "Full").unwrap();
+  fixture
+    .write("/sys/class/power_supply/BAT1/status", "Full")
+    .unwrap();
+  assert!(calibration.update(battery_states).unwrap());
+
+  calibration.finish(battery_states).unwrap();
+  assert_eq!(stop(), "80");
+  assert_eq!(
+    fixture
+      .read(&format!("{BAT0}/charge_control_start_threshold"))
+      .unwrap(),
+    "75"
+  );
+}
+
+#[test]
+fn interrupted_calibration_is_picked_up_from_disk() {
+  let (_fixture, state) = thinkpad(60);
+  let battery_states = &state.battery_states;
+  let path = state.calibration_path();
+  assert_eq!(Calibration::load(&path).unwrap(), None);
+
+  // already below the target, straight to charging
+  let calibration = Calibration::start(battery_states, 70).unwrap();
+  assert_eq!(calibration.phase, Phase::Charging);
+  let calibration = Calibration::start(battery_states, 30).unwrap();
+  calibration.save(&path).unwrap();
+  calibration.apply(battery_states).unwrap();
+
+  // the daemon stopped: no force discharging without it, the state stays
+  calibration.suspend(battery_states).unwrap();
+  assert_eq!(
+    battery_states.read_charge_behaviour().unwrap(),
+    ChargeBehaviour::Auto
+  );
+
+  let resumed = Calibration::load(&path).unwrap().unwrap();
+  assert_eq!(resumed, calibration);
+  resumed.apply(battery_states).unwrap();
+  assert_eq!(
+    battery_states.read_charge_behaviour().unwrap(),
+    ChargeBehaviour::ForceDischarge
+  );
+
+  Calibration::remove(&path).unwrap();
+  assert_eq!(Calibration::load(&path).unwrap(), None);
+  Calibration::remove(&path).unwrap();
+
+  fs::write(&path, "phase = \"sideways\"\n").unwrap();
+  assert!(Calibration::load(&path).is_err());
+}
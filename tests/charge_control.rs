@@ -0,0 +1,271 @@
+use powereg::battery_backend::ThresholdRange;
+use powereg::charge_full::ChargeFull;
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::SystemState;
+use powereg::utils::Config;
+use std::fs;
+
+const BAT0: &str = "/sys/class/power_supply/BAT0";
+
+fn laptop(vendor: &str, product_name: &str) -> SysfsFixture {
+  let fixture = SysfsFixture::desktop_amd().unwrap();
+  fixture.add_dmi(vendor, product_name, "").unwrap();
+  fixture.add_battery("BAT0", 70, "Charging").unwrap();
+  fixture
+}
+
+fn apply(fixture: &SysfsFixture, state: &SystemState, battery: &str) -> Result<(), String> {
+  let path = fixture.dir().join("config.toml");
+  fs::write(&path, format!("[battery]\n{battery}\n")).unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  config.apply(state).map_err(|e| e.to_string())
+}
+
+#[test]
+fn ideapad_conservation_mode_only_stops_at_60() {
+  let fixture = laptop("LENOVO", "IdeaPad 5 14ARE05");
+  fixture.add_ideapad_conservation_mode().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let mode = "/sys/bus/platform/drivers/ideapad_acpi/VPC2004:00/conservation_mode";
+
+  let capabilities = state.battery_states.charge_capabilities().unwrap();
+  assert_eq!(capabilities.start, ThresholdRange::Unsupported);
+  assert_eq!(capabilities.stop, ThresholdRange::Fixed(&[60, 100]));
+  assert_eq!(
+    state.battery_states.charge_control_name(),
+    Some("ideapad_acpi")
+  );
+
+  let e = apply(
+    &fixture,
+    &state,
+    "start_threshold = 40\nstop_threshold = 60",
+  )
+  .unwrap_err();
+  assert!(e.contains("start_threshold is not supported"), "{e}");
+  assert_eq!(fixture.read(mode).unwrap(), "0");
+
+  apply(&fixture, &state, "stop_threshold = 60").unwrap();
+  assert_eq!(fixture.read(mode).unwrap(), "1");
+  assert_eq!(
+    state.battery_states.read_charge_stop_threshold().unwrap(),
+    60
+  );
+
+  let e = apply(&fixture, &state, "stop_threshold = 80").unwrap_err();
+  assert!(e.contains("allowed: 60 or 100"), "{e}");
+  assert!(e.contains("ideapad_acpi"), "{e}");
+  assert_eq!(fixture.read(mode).unwrap(), "1");
+
+  apply(&fixture, &state, "stop_threshold = 100").unwrap();
+  assert_eq!(fixture.read(mode).unwrap(), "0");
+}
+
+#[test]
+fn samsung_battery_life_extender_stops_at_80() {
+  let fixture = laptop("SAMSUNG ELECTRONICS CO., LTD.", "950XBE");
+  fixture.add_samsung_battery_life_extender().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+
+  apply(&fixture, &state, "stop_threshold = 80").unwrap();
+  assert_eq!(
+    fixture
+      .read("/sys/devices/platform/samsung/battery_life_extender")
+      .unwrap(),
+    "1"
+  );
+}
+
+#[test]
+fn vendor_drivers_declare_their_limits() {
+  // dell-laptop: start 50-95, stop 55-100, at least 5 apart
+  let fixture = laptop("Dell Inc.", "Latitude 7420");
+  fixture.add_thinkpad_thresholds("BAT0").unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  assert_eq!(
+    state.battery_states.charge_control_name(),
+    Some("dell_laptop")
+  );
+  assert!(apply(
+    &fixture,
+    &state,
+    "start_threshold = 40\nstop_threshold = 80"
+  )
+  .unwrap_err()
+  .contains("allowed: 50-95"));
+  assert!(apply(
+    &fixture,
+    &state,
+    "start_threshold = 78\nstop_threshold = 80"
+  )
+  .unwrap_err()
+  .contains("at least 5 above"));
+  apply(
+    &fixture,
+    &state,
+    "start_threshold = 60\nstop_threshold = 80",
+  )
+  .unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{BAT0}/charge_control_start_threshold"))
+      .unwrap(),
+    "60"
+  );
+
+  // lg-laptop only takes 80 or 100, and no start threshold
+  let fixture = laptop("LG Electronics", "17Z90P");
+  fixture.add_thinkpad_thresholds("BAT0").unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let capabilities = state.battery_states.charge_capabilities().unwrap();
+  assert_eq!(capabilities.start, ThresholdRange::Unsupported);
+  assert!(apply(&fixture, &state, "stop_threshold = 90")
+    .unwrap_err()
+    .contains("allowed: 80 or 100"));
+  assert!(apply(
+    &fixture,
+    &state,
+    "start_threshold = 50\nstop_threshold = 80"
+  )
+  .unwrap_err()
+  .contains("only a stop_threshold can be set"));
+  apply(&fixture, &state, "stop_threshold = 80").unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{BAT0}/charge_control_start_threshold"))
+      .unwrap(),
+    "0"
+  );
+
+  // asus-wmi: stop only
+  let fixture = laptop("ASUSTeK COMPUTER INC.", "ZenBook UX425EA");
+  fixture
+    .write(&format!("{BAT0}/charge_control_end_threshold"), "100")
+    .unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  assert_eq!(state.battery_states.charge_control_name(), Some("asus_wmi"));
+  apply(&fixture, &state, "stop_threshold = 60").unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{BAT0}/charge_control_end_threshold"))
+      .unwrap(),
+    "60"
+  );
+}
+
+#[test]
+fn unknown_vendors_use_the_generic_attributes() {
+  let fixture = laptop("Framework", "Laptop 13 (AMD Ryzen 7040Series)");
+  fixture.add_thinkpad_thresholds("BAT0").unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let capabilities = state.battery_states.charge_capabilities().unwrap();
+  assert_eq!(capabilities.start, ThresholdRange::Range(0, 99));
+  assert_eq!(capabilities.stop, ThresholdRange::Range(1, 100));
+
+  let fixture = laptop("Some Vendor", "Some Laptop");
+  assert!(SystemState::init(&fixture.root())
+    .unwrap()
+    .battery_states
+    .charge_capabilities()
+    .is_none());
+  fixture
+    .write(&format!("{BAT0}/charge_control_end_threshold"), "100")
+    .unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  assert_eq!(
+    state.battery_states.charge_control_name(),
+    Some("charge_control")
+  );
+}
+
+#[test]
+fn full_charge_is_the_highest_the_backend_takes() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let capabilities = state.battery_states.charge_capabilities().unwrap();
+  assert_eq!(capabilities.full_charge(), (Some(99), Some(100)));
+
+  let fixture = laptop("Dell Inc.", "Latitude 7420");
+  fixture.add_thinkpad_thresholds("BAT0").unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let capabilities = state.battery_states.charge_capabilities().unwrap();
+  assert_eq!(capabilities.full_charge(), (Some(95), Some(100)));
+
+  let fixture = laptop("LENOVO", "IdeaPad 5 14ARE05");
+  fixture.add_ideapad_conservation_mode().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let capabilities = state.battery_states.charge_capabilities().unwrap();
+  assert_eq!(capabilities.full_charge(), (None, Some(100)));
+}
+
+#[test]
+fn charge_full_lifts_the_thresholds_until_unplugged() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let path = fixture.dir().join("config.toml");
+  fs::write(
+    &path,
+    "[battery]\nstart_threshold = 75\nstop_threshold = 80\n",
+  )
+  .unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  config.apply(&state).unwrap();
+  let thresholds = |battery: &str| {
+    let dir = format!("/sys/class/power_supply/{battery}");
+    (
+      fixture
+        .read(&format!("{dir}/charge_control_start_threshold"))
+        .unwrap(),
+      fixture
+        .read(&format!("{dir}/charge_control_end_threshold"))
+        .unwrap(),
+    )
+  };
+
+  let charge_full = ChargeFull::start(&state).unwrap();
+  charge_full.apply(&state.battery_states).unwrap();
+  assert_eq!(thresholds("BAT0"), ("99".to_string(), "100".to_string()));
+  assert_eq!(thresholds("BAT1"), ("99".to_string(), "100".to_string()));
+  assert!(!charge_full.is_done(&state).unwrap());
+
+  fixture.set_adapter_online("AC", false).unwrap();
+  assert!(charge_full.is_done(&state).unwrap());
+  assert!(ChargeFull::start(&state).is_err());
+  charge_full.finish(&state, Some(&config)).unwrap();
+  assert_eq!(thresholds("BAT0"), ("75".to_string(), "80".to_string()));
+}
+
+#[test]
+fn charge_full_without_configured_thresholds_restores_the_old_ones() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  state.battery_states.set_charge_stop_threshold(90).unwrap();
+  state.battery_states.set_charge_start_threshold(40).unwrap();
+  let stop = || {
+    fixture
+      .read(&format!("{BAT0}/charge_control_end_threshold"))
+      .unwrap()
+  };
+
+  let charge_full = ChargeFull::start(&state).unwrap();
+  charge_full.apply(&state.battery_states).unwrap();
+  assert_eq!(stop(), "100");
+
+  // BAT0 is at 85%, BAT1 at 60%
+  assert!(!charge_full.is_done(&state).unwrap());
+  for battery in ["BAT0", "BAT1"] {
+    fixture
+      .write(&format!("/sys/class/power_supply/{battery}/status"), "Full")
+      .unwrap();
+  }
+  assert!(charge_full.is_done(&state).unwrap());
+
+  charge_full.finish(&state, None).unwrap();
+  assert_eq!(stop(), "90");
+  assert_eq!(
+    fixture
+      .read(&format!("{BAT0}/charge_control_start_threshold"))
+      .unwrap(),
+    "40"
+  );
+}
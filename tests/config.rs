@@ -0,0 +1,358 @@
+use powereg::fixture::SysfsFixture;
+use powereg::policy::{LoadDebounce, LoadLevel, Policy, Throttle};
+use powereg::reload::ConfigWatcher;
+use powereg::system_state::{State, SystemState};
+use powereg::utils::Config;
+use std::fs;
+use std::time::{Duration, Instant};
+
+fn write_config(fixture: &SysfsFixture, start: u8, stop: u8) -> String {
+  let path = fixture.dir().join("config.toml");
+  fs::write(
+    &path,
+    format!("[battery]\nstart_threshold = {start}\nstop_threshold = {stop}\n"),
+  )
+  .unwrap();
+  path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn invalid_thresholds_are_rejected() {
+  let fixture = SysfsFixture::new().unwrap();
+
+  let path = write_config(&fixture, 90, 80);
+  assert!(Config::parse(&path).is_err());
+  let path = write_config(&fixture, 40, 120);
+  assert!(Config::parse(&path).is_err());
+  let path = write_config(&fixture, 75, 80);
+  assert!(Config::parse(&path).is_ok());
+}
+
+#[test]
+fn only_changed_settings_are_reapplied() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let bat0 = "/sys/class/power_supply/BAT0";
+
+  let old = Config::parse(&write_config(&fixture, 75, 80)).unwrap();
+  old.apply(&state).unwrap();
+
+  // raising both past the current stop threshold
+  let new = Config::parse(&write_config(&fixture, 85, 95)).unwrap();
+  assert_eq!(
+    new.diff(&old),
+    [
+      "battery.start_threshold: 75 -> 85",
+      "battery.stop_threshold: 80 -> 95"
+    ]
+  );
+  new.changes_from(&old).apply(&state).unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{bat0}/charge_control_start_threshold"))
+      .unwrap(),
+    "85"
+  );
+  assert_eq!(
+    fixture
+      .read(&format!("{bat0}/charge_control_end_threshold"))
+      .unwrap(),
+    "95"
+  );
+
+  // an untouched setting isn't written again
+  fixture
+    .write(&format!("{bat0}/charge_control_start_threshold"), "50")
+    .unwrap();
+  let newer = Config::parse(&write_config(&fixture, 85, 90)).unwrap();
+  assert_eq!(newer.diff(&new), ["battery.stop_threshold: 95 -> 90"]);
+  newer.changes_from(&new).apply(&state).unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{bat0}/charge_control_start_threshold"))
+      .unwrap(),
+    "50"
+  );
+  assert_eq!(
+    fixture
+      .read(&format!("{bat0}/charge_control_end_threshold"))
+      .unwrap(),
+    "90"
+  );
+}
+
+#[test]
+fn watcher_sees_config_writes() {
+  let fixture = SysfsFixture::new().unwrap();
+  let path = write_config(&fixture, 75, 80);
+  let watcher = ConfigWatcher::new(&path);
+  assert!(watcher.raw_fd().is_some());
+  assert!(!watcher.drain());
+
+  fs::write(fixture.dir().join("unrelated"), "").unwrap();
+  assert!(!watcher.drain());
+
+  // editors that save through a rename
+  fs::write(fixture.dir().join("config.toml.swp"), "").unwrap();
+  fs::rename(
+    fixture.dir().join("config.toml.swp"),
+    fixture.dir().join("config.toml"),
+  )
+  .unwrap();
+  assert!(watcher.drain());
+
+  write_config(&fixture, 70, 80);
+  assert!(watcher.drain());
+  assert!(!watcher.drain());
+}
+
+#[test]
+fn profiles_override_the_builtin_states() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let path = fixture.dir().join("config.toml");
+  fs::write(
+    &path,
+    r#"
+[profiles.powersave]
+epp = "balance_power"
+
+[profiles.quiet]
+governor = "powersave"
+epp = "balance_performance"
+boost = "off"
+max_freq_mhz = 2000
+max_perf_pct = 60
+
+[states]
+performance = "quiet"
+"#,
+  )
+  .unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  assert_eq!(config.charge_stop_threshold, None);
+  config.apply(&state).unwrap();
+
+  let cpu0 = "/sys/devices/system/cpu/cpu0/cpufreq";
+  state.set_powersave_mode().unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{cpu0}/energy_performance_preference"))
+      .unwrap(),
+    "balance_power"
+  );
+  // untouched knobs of a built-in profile stay as they were
+  assert_eq!(
+    fixture.read("/sys/firmware/acpi/platform_profile").unwrap(),
+    "low-power"
+  );
+
+  state.apply_state(State::Performance).unwrap();
+  assert_eq!(
+    fixture.read(&format!("{cpu0}/scaling_governor")).unwrap(),
+    "powersave"
+  );
+  assert_eq!(
+    fixture
+      .read(&format!("{cpu0}/energy_performance_preference"))
+      .unwrap(),
+    "balance_performance"
+  );
+  assert_eq!(
+    fixture.read(&format!("{cpu0}/scaling_max_freq")).unwrap(),
+    "2000000"
+  );
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/intel_pstate/max_perf_pct")
+      .unwrap(),
+    "60"
+  );
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/intel_pstate/no_turbo")
+      .unwrap(),
+    "1"
+  );
+}
+
+#[test]
+fn invalid_profiles_are_rejected() {
+  let fixture = SysfsFixture::new().unwrap();
+  let path = fixture.dir().join("config.toml");
+
+  for config in [
+    "[profiles.quiet]\nepp = \"300\"\n",
+    "[profiles.quiet]\ngovernor = \"\"\n",
+    "[profiles.quiet]\nmin_perf_pct = 80\nmax_perf_pct = 40\n",
+    "[profiles.quiet]\nturbo = true\n",
+    "[profiles.quiet]\npackage_power_limit_w = 30\nburst_power_limit_w = 20\n",
+    "[states]\npowersave = \"missing\"\n",
+  ] {
+    fs::write(&path, config).unwrap();
+    assert!(
+      Config::parse(path.to_str().unwrap()).is_err(),
+      "accepted {config}"
+    );
+  }
+}
+
+#[test]
+fn profiles_use_any_governor_and_epp_the_kernel_offers() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let cpufreq = "/sys/devices/system/cpu/cpu0/cpufreq";
+  fixture
+    .write(
+      &format!("{cpufreq}/scaling_available_governors"),
+      "conservative ondemand userspace powersave performance schedutil",
+    )
+    .unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let path = fixture.dir().join("config.toml");
+
+  fs::write(
+    &path,
+    "[profiles.powersave]\ngovernor = \"schedutil\"\nepp = \"200\"\n",
+  )
+  .unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  config.apply(&state).unwrap();
+  state.set_powersave_mode().unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{cpufreq}/scaling_governor"))
+      .unwrap(),
+    "schedutil"
+  );
+  assert_eq!(
+    fixture
+      .read(&format!("{cpufreq}/energy_performance_preference"))
+      .unwrap(),
+    "200"
+  );
+
+  // valid names, but not offered by this machine
+  for profile in [
+    "[profiles.quiet]\ngovernor = \"interactive\"\n",
+    "[profiles.quiet]\nepp = \"whisper\"\n",
+  ] {
+    fs::write(&path, profile).unwrap();
+    let config = Config::parse(path.to_str().unwrap()).unwrap();
+    assert!(config.apply(&state).is_err(), "applied {profile}");
+  }
+}
+
+#[test]
+fn policy_is_read_and_validated() {
+  let fixture = SysfsFixture::new().unwrap();
+  let path = fixture.dir().join("config.toml");
+
+  fs::write(&path, "[policy]\nhigh_cpu_load = 60.0\nlow_battery = 10\n").unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  assert_eq!(config.policy.high_cpu_load, 60.0);
+  assert_eq!(config.policy.low_cpu_load, Policy::default().low_cpu_load);
+  assert_eq!(config.policy.low_battery, 10);
+
+  for policy in [
+    "[policy]\nhigh_cpu_load = 30.0\nlow_cpu_load = 40.0\n",
+    "[policy]\nloop_duration_s = 0\n",
+    "[policy]\nlow_battery = 120\n",
+    "[policy]\nhigh_cpu_temp = 70\n",
+    "[policy]\ntarget_cpu_temp = 90\n",
+  ] {
+    fs::write(&path, policy).unwrap();
+    assert!(
+      Config::parse(path.to_str().unwrap()).is_err(),
+      "accepted {policy}"
+    );
+  }
+}
+
+#[test]
+fn rejected_configs_change_nothing() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let path = fixture.dir().join("config.toml");
+  let policy = "[policy]\nhigh_cpu_load = 60.0\n";
+
+  // unknown sensor
+  fs::write(
+    &path,
+    format!("{policy}[thermal]\nsensor = \"k10temp/Tctl\"\n"),
+  )
+  .unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  assert!(config.apply(&state).is_err());
+  assert_eq!(*state.policy.borrow(), Policy::default());
+
+  // the generic backend has no start threshold, dell-laptop's stop has to be 55 or more
+  fs::write(
+    &path,
+    format!("{policy}[battery]\nstart_threshold = 40\nstop_threshold = 50\n"),
+  )
+  .unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  assert!(config.apply(&state).is_err());
+  assert_eq!(*state.policy.borrow(), Policy::default());
+  assert_eq!(
+    state.cpu_states.thermal().cpu_sensor().unwrap().name,
+    "coretemp/Package id 0"
+  );
+}
+
+#[test]
+fn load_must_stay_past_a_threshold_for_the_debounce() {
+  let policy = Policy {
+    load_debounce_s: 6,
+    ..Policy::default()
+  };
+  let mut debounce = LoadDebounce::default();
+  let start = Instant::now();
+  let at = |s: u64| start + Duration::from_secs(s);
+
+  assert_eq!(debounce.update(90.0, &policy, at(0)), LoadLevel::Normal);
+  assert_eq!(debounce.update(90.0, &policy, at(3)), LoadLevel::Normal);
+  assert_eq!(debounce.update(90.0, &policy, at(6)), LoadLevel::High);
+
+  // a dip between the thresholds restarts the clock
+  assert_eq!(debounce.update(32.0, &policy, at(9)), LoadLevel::Normal);
+  assert_eq!(debounce.update(90.0, &policy, at(12)), LoadLevel::Normal);
+
+  assert_eq!(debounce.update(5.0, &policy, at(15)), LoadLevel::Normal);
+  assert_eq!(debounce.update(5.0, &policy, at(21)), LoadLevel::Low);
+
+  let immediate = Policy {
+    load_debounce_s: 0,
+    ..Policy::default()
+  };
+  assert_eq!(
+    LoadDebounce::default().update(90.0, &immediate, at(0)),
+    LoadLevel::High
+  );
+}
+
+#[test]
+fn throttle_steps_toward_the_target_temperature() {
+  let policy = Policy::default();
+  let mut throttle = Throttle::default();
+  assert_eq!(throttle.max_freq_pct(), None);
+
+  assert!(throttle.update(85, &policy));
+  assert!(throttle.boost_off());
+  assert_eq!(throttle.max_freq_pct(), None);
+  assert!(throttle.update(85, &policy));
+  assert_eq!(throttle.max_freq_pct(), Some(90));
+
+  assert!(!throttle.update(80, &policy));
+  assert!(!throttle.update(77, &policy));
+  assert!(throttle.update(76, &policy));
+  assert_eq!(throttle.level(), 1);
+
+  for _ in 0..20 {
+    throttle.update(95, &policy);
+  }
+  assert_eq!(throttle.level(), Throttle::MAX_LEVEL);
+  assert_eq!(throttle.max_freq_pct(), Some(0));
+  assert!(throttle.reset());
+  assert!(!throttle.reset());
+}
@@ -0,0 +1,86 @@
+use powereg::battery::TimeEstimate;
+use powereg::control::{
+  send_command, AppliedSettings, Command, ControlServer, DaemonStatus, Response,
+};
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::State;
+use std::thread;
+
+fn serve_one(server: &ControlServer, response: impl Fn(Command) -> Response) {
+  loop {
+    if let Some((stream, request)) = server.accept() {
+      let response = match request {
+        Ok(request) => response(request.command),
+        Err(e) => Response::error(&e),
+      };
+      ControlServer::respond(stream, &response).unwrap();
+      return;
+    }
+    thread::yield_now();
+  }
+}
+
+#[test]
+fn status_round_trip() {
+  let fixture = SysfsFixture::new().unwrap();
+  let path = fixture.dir().join("powereg.sock");
+  let path = path.to_str().unwrap().to_string();
+  let server = ControlServer::bind(&path).unwrap();
+
+  let status = DaemonStatus {
+    state: State::Balanced,
+    last_event: "periodic check".to_string(),
+    paused: false,
+    forced_state: Some(State::Balanced),
+    forced_remaining_s: Some(42),
+    time_estimate: Some(TimeEstimate {
+      secs: 5400,
+      charging: true,
+      target: 80,
+    }),
+    calibration: Some("discharging to 20%".to_string()),
+    charging_full: false,
+    settings: AppliedSettings {
+      governor: "Powersave".to_string(),
+      epp: "BalancePower".to_string(),
+      boost: false,
+      platform_profile: "balanced".to_string(),
+      charge_start_threshold: Some(75),
+      charge_stop_threshold: Some(80),
+    },
+  };
+
+  let client_path = path.clone();
+  let client = thread::spawn(move || send_command(&client_path, Command::Status));
+  let expected = status.clone();
+  serve_one(&server, move |command| {
+    assert_eq!(command, Command::Status);
+    Response::status(expected.clone())
+  });
+
+  let response = client.join().unwrap().unwrap();
+  assert_eq!(response.status, Some(status));
+}
+
+#[test]
+fn errors_are_returned_to_the_client() {
+  let fixture = SysfsFixture::new().unwrap();
+  let path = fixture.dir().join("powereg.sock");
+  let path = path.to_str().unwrap().to_string();
+  let server = ControlServer::bind(&path).unwrap();
+
+  let client_path = path.clone();
+  let client = thread::spawn(move || {
+    send_command(
+      &client_path,
+      Command::ForceProfile {
+        state: State::Performance,
+        duration_s: Some(60),
+      },
+    )
+  });
+  serve_one(&server, |_| Response::error("not on ac power"));
+
+  let error = client.join().unwrap().unwrap_err();
+  assert_eq!(error.to_string(), "not on ac power");
+}
@@ -0,0 +1,49 @@
+use powereg::control::Command;
+use powereg::daemon::Daemon;
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::SystemState;
+use powereg::utils::Config;
+use std::fs;
+
+const BAT0: &str = "/sys/class/power_supply/BAT0";
+
+#[test]
+fn config_rejected_at_startup_is_applied_in_full_on_reload() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let path = fixture.dir().join("config.toml");
+  let path = path.to_str().unwrap();
+  let thresholds = "[battery]\nstart_threshold = 60\nstop_threshold = 70\n";
+  let stop = || {
+    fixture
+      .read(&format!("{BAT0}/charge_control_end_threshold"))
+      .unwrap()
+  };
+
+  fs::write(
+    path,
+    format!("{thresholds}\n[thermal]\nsensor = \"nouveau/GPU\"\n"),
+  )
+  .unwrap();
+  let config = Config::load(path, &state);
+  assert!(config.is_none());
+  assert_ne!(stop(), "70");
+
+  // needs a udev monitor for its event loop
+  let mut daemon = match Daemon::new(&state, path, config, false) {
+    Ok(daemon) => daemon,
+    Err(e) => {
+      eprintln!("skipping, can't start the daemon: {}", e);
+      return;
+    }
+  };
+  fs::write(path, thresholds).unwrap();
+  assert_eq!(daemon.execute(Command::Reload).error, None);
+  assert_eq!(stop(), "70");
+  assert_eq!(
+    fixture
+      .read(&format!("{BAT0}/charge_control_start_threshold"))
+      .unwrap(),
+    "60"
+  );
+}
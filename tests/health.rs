@@ -0,0 +1,79 @@
+use powereg::fixture::SysfsFixture;
+use powereg::health::BatteryHealth;
+use powereg::system_state::SystemState;
+
+#[test]
+fn wear_comes_from_the_design_capacity() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let dir = "/sys/class/power_supply/BAT0";
+  for (file, value) in [
+    ("energy_full_design", "57000000"),
+    ("cycle_count", "312"),
+    ("manufacturer", "SMP"),
+    ("model_name", "01AV430"),
+    ("technology", "Li-poly"),
+    ("voltage_now", "12450000"),
+    ("voltage_min_design", "11580000"),
+  ] {
+    fixture.write(&format!("{dir}/{file}"), value).unwrap();
+  }
+
+  let health = BatteryHealth::read_all(&fixture.root()).unwrap();
+  assert_eq!(health.len(), 2);
+  let bat0 = &health[0];
+  assert_eq!(bat0.name, "BAT0");
+  assert_eq!(bat0.energy_full, Some(50_000_000));
+  assert_eq!(bat0.charge_full, None);
+  assert_eq!(format!("{:.1}", bat0.wear().unwrap()), "12.3");
+
+  let report = bat0.to_string();
+  for line in [
+    "manufacturer: SMP",
+    "model: 01AV430",
+    "cycle count: 312",
+    "voltage: 12.45 V (design min 11.58 V)",
+    "full capacity: 50.00 Wh of 57.00 Wh design",
+    "wear: 12.3%",
+  ] {
+    assert!(report.contains(line), "{report}");
+  }
+
+  // BAT1 reports no design capacity
+  assert_eq!(health[1].wear(), None);
+  assert!(health[1].to_string().contains("wear: unknown"));
+
+  let fixture = SysfsFixture::desktop_amd().unwrap();
+  assert!(BatteryHealth::read_all(&fixture.root()).unwrap().is_empty());
+}
+
+#[test]
+fn charge_reporting_batteries_are_converted_to_energy() {
+  let fixture = SysfsFixture::desktop_amd().unwrap();
+  fixture
+    .add_charge_battery("BAT0", 80, "Discharging")
+    .unwrap();
+  fixture
+    .write("/sys/class/power_supply/BAT0/cycle_count", "0")
+    .unwrap();
+
+  let health = BatteryHealth::read(&fixture.root(), "BAT0");
+  assert_eq!(health.charge_full, Some(4_000_000));
+  // at the 11.4 V design voltage
+  assert_eq!(health.energy_full, Some(45_600_000));
+  assert_eq!(health.energy_full_design, Some(51_300_000));
+  assert_eq!(format!("{:.1}", health.wear().unwrap()), "11.1");
+  assert!(health.to_string().contains("cycle count: unknown"));
+
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let battery = &state.battery_states.batteries()[0];
+  assert_eq!(battery.read_energy_now().unwrap(), 36_480_000);
+  assert_eq!(battery.read_energy_full().unwrap(), 45_600_000);
+  assert_eq!(state.battery_states.read_battery_capacity().unwrap(), 80);
+  assert_eq!(state.battery_states.read_total_power_draw().unwrap(), 24.0);
+
+  // negative while discharging on some drivers
+  fixture
+    .write("/sys/class/power_supply/BAT0/current_now", "-1500000")
+    .unwrap();
+  assert_eq!(state.battery_states.read_total_power_draw().unwrap(), 18.0);
+}
@@ -0,0 +1,93 @@
+use powereg::events::Event;
+use powereg::fixture::SysfsFixture;
+use powereg::load::{CpuLoad, CpuTimes, ProcStat};
+use powereg::system_state::{State, SystemState};
+use std::time::Instant;
+
+/// `/proc/stat` with two cores, both running the same times.
+fn proc_stat(user: u64, system: u64, idle: u64, iowait: u64, steal: u64) -> String {
+  let line = format!("{user} 0 {system} {idle} {iowait} 0 0 {steal} 0 0");
+  format!("cpu  {line}\ncpu0 {line}\ncpu1 {line}\nintr 12345 0 0\nctxt 67890\n")
+}
+
+#[test]
+fn proc_stat_is_parsed_per_core() {
+  let stat = ProcStat::parse(&proc_stat(100, 50, 800, 30, 20)).unwrap();
+  assert_eq!(stat.cores.keys().copied().collect::<Vec<_>>(), [0, 1]);
+  assert_eq!(
+    stat.total,
+    CpuTimes {
+      user: 100,
+      system: 50,
+      idle: 800,
+      iowait: 30,
+      steal: 20,
+      ..Default::default()
+    }
+  );
+
+  // old kernels have fewer fields
+  assert!(ProcStat::parse("cpu  1 2 3 4\n").is_ok());
+  assert!(ProcStat::parse("cpu  1 2 3\n").is_err());
+  assert!(ProcStat::parse("intr 1\n").is_err());
+}
+
+#[test]
+fn load_is_split_by_category() {
+  let prev = ProcStat::parse(&proc_stat(0, 0, 0, 0, 0)).unwrap();
+  let now = ProcStat::parse(&proc_stat(400, 200, 300, 50, 50)).unwrap();
+  let load = CpuLoad::between(&prev.total, &now.total);
+
+  assert_eq!(
+    load,
+    CpuLoad {
+      busy: 65.0,
+      user: 40.0,
+      system: 20.0,
+      iowait: 5.0,
+      steal: 5.0,
+    }
+  );
+  assert_eq!(CpuLoad::between(&now.total, &now.total), CpuLoad::default());
+}
+
+#[test]
+fn load_covers_the_time_between_samples() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  state.post_init().unwrap();
+  let boost = || {
+    fixture
+      .read("/sys/devices/system/cpu/cpufreq/boost")
+      .unwrap()
+  };
+  state.policy.borrow_mut().load_debounce_s = 0;
+
+  fixture
+    .write("/proc/stat", &proc_stat(0, 0, 0, 0, 0))
+    .unwrap();
+  state.cpu_states.sample(Instant::now()).unwrap();
+  assert_eq!(state.cpu_states.cpu_load(), None);
+  Event::PeriodicCheck.handle_event(&state).unwrap();
+  assert_eq!(boost(), "0");
+
+  fixture
+    .write("/proc/stat", &proc_stat(800, 100, 100, 0, 0))
+    .unwrap();
+  state.cpu_states.sample(Instant::now()).unwrap();
+  assert_eq!(state.cpu_states.cpu_load().unwrap().busy, 90.0);
+  assert_eq!(state.cpu_states.core_loads()[&1].user, 80.0);
+  Event::PeriodicCheck.handle_event(&state).unwrap();
+  assert_eq!(*state.state.borrow(), State::Performance);
+  assert_eq!(boost(), "1");
+
+  // reading doesn't take a new sample
+  fixture
+    .write("/proc/stat", &proc_stat(800, 100, 1100, 0, 0))
+    .unwrap();
+  assert_eq!(state.cpu_states.cpu_load().unwrap().busy, 90.0);
+  state.cpu_states.sample(Instant::now()).unwrap();
+  assert_eq!(state.cpu_states.cpu_load().unwrap().busy, 0.0);
+  Event::PeriodicCheck.handle_event(&state).unwrap();
+  assert_eq!(boost(), "0");
+}
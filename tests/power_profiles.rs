@@ -0,0 +1,363 @@
+use powereg::dbus::{Connection, DBusError, Message, Value};
+use powereg::fixture::SysfsFixture;
+use powereg::power_profiles::{PowerProfilesService, ProfileRequest};
+use powereg::setup::DBUS_POLICY;
+use powereg::system_state::State;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const NAME: &str = "net.hadess.PowerProfiles";
+const PATH: &str = "/net/hadess/PowerProfiles";
+
+/// A private dbus-daemon, killed on drop.
+struct TestBus {
+  child: Child,
+  address: String,
+  _dir: SysfsFixture,
+}
+
+impl Drop for TestBus {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+impl TestBus {
+  /// None when dbus-daemon isn't installed.
+  fn start() -> Option<Self> {
+    Self::with_policy("<busconfig/>")
+  }
+
+  /// Open to every user, with `policy` on top of allowing everything.
+  fn with_policy(policy: &str) -> Option<Self> {
+    let dir = SysfsFixture::new().unwrap();
+    fs::set_permissions(dir.dir(), fs::Permissions::from_mode(0o755)).unwrap();
+    let policy_path = dir.dir().join("policy.conf");
+    fs::write(&policy_path, policy).unwrap();
+    let config = dir.dir().join("bus.conf");
+    fs::write(
+      &config,
+      format!(
+        r#"<busconfig>
+  <listen>unix:path={}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow user="*"/>
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+  <include>{}</include>
+</busconfig>
+"#,
+        dir.dir().join("bus").display(),
+        policy_path.display()
+      ),
+    )
+    .unwrap();
+
+    let mut child = match Command::new("dbus-daemon")
+      .arg(format!("--config-file={}", config.display()))
+      .args(["--nofork", "--print-address"])
+      .stdout(Stdio::piped())
+      .spawn()
+    {
+      Ok(child) => child,
+      Err(e) => {
+        eprintln!("skipping, can't run dbus-daemon: {}", e);
+        return None;
+      }
+    };
+
+    let mut address = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+      .read_line(&mut address)
+      .unwrap();
+
+    Some(Self {
+      child,
+      address: address.trim().to_string(),
+      _dir: dir,
+    })
+  }
+
+  fn service(&self) -> PowerProfilesService {
+    let connection = Connection::connect(&self.address).unwrap();
+    PowerProfilesService::start(connection, "amd-pstate-epp", "platform_profile").unwrap()
+  }
+}
+
+/// Runs `client` on its own thread while answering bus calls, returns its result and every
+/// request the service made to the daemon.
+fn serve<T: Send + 'static>(
+  service: &mut PowerProfilesService,
+  active_profile: &str,
+  client: impl FnOnce() -> T + Send + 'static,
+) -> (T, Vec<ProfileRequest>) {
+  let client = thread::spawn(client);
+  let mut requests = vec![];
+  loop {
+    let finished = client.is_finished();
+    thread::sleep(Duration::from_millis(5));
+    if let Some(request) = service.process(active_profile).unwrap() {
+      requests.push(request);
+    }
+    if finished {
+      return (client.join().unwrap(), requests);
+    }
+  }
+}
+
+/// Runs `client` as nobody, None when this process can't switch users. The raw syscall only
+/// changes the calling thread's ids, unlike libc's setresuid, so it's meant for `serve`'s client.
+fn as_nobody<T>(client: impl FnOnce() -> T) -> Option<T> {
+  if unsafe { libc::syscall(libc::SYS_setresuid, 65534, 65534, 65534) } != 0 {
+    return None;
+  }
+  Some(client())
+}
+
+fn call(connection: &mut Connection, interface: &str, member: &str, body: Vec<Value>) -> Message {
+  connection
+    .call(Message::method_call(NAME, PATH, interface, member, body))
+    .unwrap()
+}
+
+fn set_profile(address: &str, profile: &str) -> Result<Message, DBusError> {
+  Connection::connect(address)?.call(Message::method_call(
+    NAME,
+    PATH,
+    "org.freedesktop.DBus.Properties",
+    "Set",
+    vec![
+      Value::Str(NAME.to_string()),
+      Value::Str("ActiveProfile".to_string()),
+      Value::Variant(Box::new(Value::Str(profile.to_string()))),
+    ],
+  ))
+}
+
+fn hold(connection: &mut Connection, profile: &str) -> u32 {
+  let reply = call(
+    connection,
+    NAME,
+    "HoldProfile",
+    vec![
+      Value::Str(profile.to_string()),
+      Value::Str("testing".to_string()),
+      Value::Str("powereg.test".to_string()),
+    ],
+  );
+  reply.body[0].as_u32().unwrap()
+}
+
+#[test]
+fn properties_are_exported() {
+  let Some(bus) = TestBus::start() else {
+    return;
+  };
+  let mut service = bus.service();
+
+  let address = bus.address.clone();
+  let (properties, _) = serve(&mut service, "balanced", move || {
+    let mut connection = Connection::connect(&address).unwrap();
+    call(
+      &mut connection,
+      "org.freedesktop.DBus.Properties",
+      "GetAll",
+      vec![Value::Str(NAME.to_string())],
+    )
+  });
+
+  let properties = &properties.body[0];
+  assert_eq!(
+    properties
+      .dict_get("ActiveProfile")
+      .and_then(|v| v.as_str()),
+    Some("balanced")
+  );
+  let Some(Value::Array(_, profiles)) = properties.dict_get("Profiles") else {
+    panic!("Profiles missing");
+  };
+  let names: Vec<_> = profiles
+    .iter()
+    .filter_map(|p| p.dict_get("Profile").and_then(|v| v.as_str()))
+    .collect();
+  assert_eq!(names, ["power-saver", "balanced", "performance"]);
+  assert_eq!(
+    profiles[0].dict_get("CpuDriver").and_then(|v| v.as_str()),
+    Some("amd-pstate-epp")
+  );
+}
+
+#[test]
+fn selecting_a_profile_drives_the_daemon() {
+  let Some(bus) = TestBus::start() else {
+    return;
+  };
+  let mut service = bus.service();
+
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "balanced", move || {
+    set_profile(&address, "performance")
+  });
+  result.unwrap();
+  assert_eq!(requests, [ProfileRequest::Force(State::Performance)]);
+
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "performance", move || {
+    set_profile(&address, "balanced")
+  });
+  result.unwrap();
+  assert_eq!(requests, [ProfileRequest::Automatic]);
+
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "balanced", move || {
+    set_profile(&address, "turbo")
+  });
+  assert!(matches!(result, Err(DBusError::Remote(name, _)) if name.ends_with("InvalidArgs")));
+  assert!(requests.is_empty());
+}
+
+#[test]
+fn holds_override_the_selection_until_released() {
+  let Some(bus) = TestBus::start() else {
+    return;
+  };
+  let mut service = bus.service();
+
+  let mut gamer = Connection::connect(&bus.address).unwrap();
+  let (gamer, requests) = serve(&mut service, "balanced", move || {
+    hold(&mut gamer, "performance");
+    gamer
+  });
+  assert_eq!(requests, [ProfileRequest::Force(State::Performance)]);
+
+  // power-saver holds win
+  let mut saver = Connection::connect(&bus.address).unwrap();
+  let ((saver, cookie), requests) = serve(&mut service, "performance", move || {
+    let cookie = hold(&mut saver, "power-saver");
+    (saver, cookie)
+  });
+  assert_eq!(requests, [ProfileRequest::Force(State::Powersave)]);
+  assert_eq!(service.holds().len(), 2);
+
+  let (_, requests) = serve(&mut service, "power-saver", move || {
+    let mut saver = saver;
+    call(&mut saver, NAME, "ReleaseProfile", vec![Value::U32(cookie)]);
+  });
+  assert_eq!(requests, [ProfileRequest::Force(State::Performance)]);
+
+  // holds end when their owner leaves the bus
+  let (_, requests) = serve(&mut service, "performance", move || {
+    drop(gamer);
+    thread::sleep(Duration::from_millis(100));
+  });
+  assert_eq!(requests, [ProfileRequest::Automatic]);
+  assert!(service.holds().is_empty());
+}
+
+#[test]
+fn second_instance_cannot_take_the_names() {
+  let Some(bus) = TestBus::start() else {
+    return;
+  };
+  let _service = bus.service();
+
+  let connection = Connection::connect(&bus.address).unwrap();
+  assert!(PowerProfilesService::start(connection, "acpi-cpufreq", "platform_profile").is_err());
+}
+
+#[test]
+fn undecodable_calls_are_rejected_without_losing_the_bus() {
+  let Some(bus) = TestBus::start() else {
+    return;
+  };
+  let mut service = bus.service();
+
+  // two words make one double on the wire, "ad" is a signature powereg can't decode
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "balanced", move || {
+    Connection::connect(&address)?.call(Message::method_call(
+      NAME,
+      PATH,
+      NAME,
+      "HoldProfile",
+      vec![Value::Array(
+        "d".to_string(),
+        vec![Value::U32(0), Value::U32(0)],
+      )],
+    ))
+  });
+  assert!(matches!(result, Err(DBusError::Remote(name, _)) if name.ends_with("InvalidArgs")));
+  assert!(requests.is_empty());
+
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "balanced", move || {
+    set_profile(&address, "performance")
+  });
+  result.unwrap();
+  assert_eq!(requests, [ProfileRequest::Force(State::Performance)]);
+}
+
+#[test]
+fn only_root_can_change_the_profile() {
+  let Some(bus) = TestBus::with_policy(DBUS_POLICY) else {
+    return;
+  };
+  let mut service = bus.service();
+
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "balanced", move || {
+    as_nobody(|| {
+      let mut connection = Connection::connect(&address).unwrap();
+      let set = set_profile(&address, "performance");
+      let hold = connection.call(Message::method_call(
+        NAME,
+        PATH,
+        NAME,
+        "HoldProfile",
+        vec![
+          Value::Str("performance".to_string()),
+          Value::Str("testing".to_string()),
+          Value::Str("powereg.test".to_string()),
+        ],
+      ));
+      let active = call(
+        &mut connection,
+        "org.freedesktop.DBus.Properties",
+        "Get",
+        vec![
+          Value::Str(NAME.to_string()),
+          Value::Str("ActiveProfile".to_string()),
+        ],
+      );
+      (set, hold, active)
+    })
+  });
+  let Some((set, hold, active)) = result else {
+    eprintln!("skipping, can't switch to an unprivileged user");
+    return;
+  };
+  let denied = |result: Result<Message, DBusError>| matches!(result, Err(DBusError::Remote(name, _)) if name.ends_with("AccessDenied"));
+  assert!(denied(set));
+  assert!(denied(hold));
+  assert_eq!(
+    active.body[0].as_str(),
+    Some("balanced"),
+    "reading stays open"
+  );
+  assert!(requests.is_empty());
+
+  let address = bus.address.clone();
+  let (result, requests) = serve(&mut service, "balanced", move || {
+    set_profile(&address, "performance")
+  });
+  result.unwrap();
+  assert_eq!(requests, [ProfileRequest::Force(State::Performance)]);
+}
@@ -0,0 +1,156 @@
+use powereg::fixture::SysfsFixture;
+use powereg::powercap::Powercap;
+use powereg::snapshot::restore_snapshot;
+use powereg::system_state::{State, SystemState};
+use powereg::utils::Config;
+use std::fs;
+use std::time::{Duration, Instant};
+
+fn set_energy(fixture: &SysfsFixture, id: &str, energy_uj: u64) {
+  fixture
+    .write(
+      &format!("/sys/class/powercap/{id}/energy_uj"),
+      &energy_uj.to_string(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn zones_and_subzones_are_named_after_their_package() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  fixture
+    .add_rapl_zone("intel-rapl:0:0", "core", 500_000)
+    .unwrap();
+  fixture
+    .add_rapl_zone("intel-rapl:0:1", "uncore", 100_000)
+    .unwrap();
+  fixture
+    .add_rapl_zone("intel-rapl:0:2", "dram", 100_000)
+    .unwrap();
+  fixture.add_rapl_zone("intel-rapl:1", "psys", 0).unwrap();
+  // the control type itself has no counter
+  fixture
+    .write("/sys/class/powercap/intel-rapl/enabled", "1")
+    .unwrap();
+
+  let powercap = Powercap::discover(&fixture.root());
+  let names: Vec<&str> = powercap.zones().iter().map(|z| z.name.as_str()).collect();
+  assert_eq!(
+    names,
+    [
+      "package-0",
+      "package-0/core",
+      "package-0/uncore",
+      "package-0/dram",
+      "psys"
+    ]
+  );
+
+  let fixture = SysfsFixture::desktop_amd().unwrap();
+  fixture.remove("/sys/class/powercap/intel-rapl:0").unwrap();
+  fixture.add_rapl_zone("amd-rapl:0", "package-0", 0).unwrap();
+  fixture.add_rapl_zone("amd-rapl:0:0", "core", 0).unwrap();
+  let powercap = Powercap::discover(&fixture.root());
+  let names: Vec<&str> = powercap.zones().iter().map(|z| z.name.as_str()).collect();
+  assert_eq!(names, ["package-0", "package-0/core"]);
+}
+
+#[test]
+fn watts_come_from_deltas_between_samples() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  fixture.add_rapl_zone("intel-rapl:0:0", "core", 0).unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let powercap = state.cpu_states.powercap();
+  let start = Instant::now();
+  let at = |s: u64| start + Duration::from_secs(s);
+
+  powercap.sample(at(0));
+  assert_eq!(powercap.package_watts(), None);
+  assert_eq!(state.cpu_states.read_cpu_power_draw().unwrap(), 0.0);
+
+  // 30 J over 2 s
+  set_energy(&fixture, "intel-rapl:0", 31_000_000);
+  set_energy(&fixture, "intel-rapl:0:0", 20_000_000);
+  powercap.sample(at(2));
+  assert_eq!(powercap.package_watts(), Some(15.0));
+  assert_eq!(powercap.zones()[1].watts(), Some(10.0));
+  assert_eq!(state.cpu_states.read_cpu_power_draw().unwrap(), 15.0);
+
+  // wrapped past max_energy_range_uj (262143328850)
+  set_energy(&fixture, "intel-rapl:0", 262_143_328_850 - 1_000_000);
+  powercap.sample(at(3));
+  set_energy(&fixture, "intel-rapl:0", 4_000_000);
+  powercap.sample(at(4));
+  assert_eq!(powercap.package_watts(), Some(5.0));
+}
+
+#[test]
+fn profiles_set_power_limits_within_the_firmware_max() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  state.save_snapshot().unwrap();
+  let rapl = "/sys/class/powercap/intel-rapl:0";
+  let path = fixture.dir().join("config.toml");
+  fs::write(
+    &path,
+    "[profiles.performance]\npackage_power_limit_w = 35\nburst_power_limit_w = 64\n\n\
+     [profiles.powersave]\npackage_power_limit_w = 8\nburst_power_limit_w = 12\n",
+  )
+  .unwrap();
+  Config::parse(path.to_str().unwrap())
+    .unwrap()
+    .apply(&state)
+    .unwrap();
+
+  state.apply_state(State::Performance).unwrap();
+  // PL1 is capped at constraint_0_max_power_uw, PL2 reports no max
+  assert_eq!(
+    fixture
+      .read(&format!("{rapl}/constraint_0_power_limit_uw"))
+      .unwrap(),
+    "28000000"
+  );
+  assert_eq!(
+    fixture
+      .read(&format!("{rapl}/constraint_1_power_limit_uw"))
+      .unwrap(),
+    "64000000"
+  );
+  assert!(state
+    .cpu_states
+    .to_string()
+    .contains("package-0 power limit PL2: 64.00 W"));
+
+  state.apply_state(State::Powersave).unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{rapl}/constraint_0_power_limit_uw"))
+      .unwrap(),
+    "8000000"
+  );
+
+  restore_snapshot(&state.snapshot_path()).unwrap();
+  assert_eq!(
+    fixture
+      .read(&format!("{rapl}/constraint_0_power_limit_uw"))
+      .unwrap(),
+    "15000000"
+  );
+  assert_eq!(
+    fixture
+      .read(&format!("{rapl}/constraint_1_power_limit_uw"))
+      .unwrap(),
+    "25000000"
+  );
+}
+
+#[test]
+fn power_limits_are_skipped_without_rapl_constraints() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  state
+    .cpu_states
+    .set_power_limits(Some(15), Some(25))
+    .unwrap();
+  assert!(!state.cpu_states.to_string().contains("power limit"));
+}
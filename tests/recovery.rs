@@ -0,0 +1,56 @@
+use powereg::cpu::CpuStatesError;
+use powereg::daemon::Backoff;
+use powereg::signals::SignalFd;
+use powereg::system_state::SystemStateError;
+use powereg::utils::PersFdError;
+use std::io;
+use std::time::Duration;
+
+#[test]
+fn sysfs_hiccups_are_transient() {
+  let transient = [
+    SystemStateError::GeneralIoErr(io::Error::from_raw_os_error(libc::EBUSY)),
+    SystemStateError::CpuStatesErr(CpuStatesError::EmptyProcStat),
+    SystemStateError::CpuStatesErr(CpuStatesError::PersFdErr(PersFdError::ReadErr(
+      io::Error::from_raw_os_error(libc::ENODEV),
+    ))),
+  ];
+  for e in transient {
+    assert!(e.is_transient(), "{e}");
+  }
+
+  let fatal = [
+    SystemStateError::UnsupportedErr("no cpufreq".to_string()),
+    SystemStateError::CpuStatesErr(CpuStatesError::Unsupported),
+    SystemStateError::CpuStatesErr(CpuStatesError::PersFdErr(PersFdError::WriteErr(
+      io::Error::from_raw_os_error(libc::EACCES),
+    ))),
+  ];
+  for e in fatal {
+    assert!(!e.is_transient(), "{e}");
+  }
+}
+
+#[test]
+fn backoff_doubles_until_it_gives_up() {
+  let mut backoff = Backoff::default();
+  let delays: Vec<Duration> = std::iter::from_fn(|| backoff.next_delay()).collect();
+
+  assert_eq!(delays.len() as u32, Backoff::MAX_RETRIES);
+  assert_eq!(delays[..3], [1, 2, 4].map(Duration::from_secs));
+  assert!(delays.iter().all(|d| *d <= Duration::from_secs(30)));
+  assert_eq!(backoff.next_delay(), None);
+
+  backoff.reset();
+  assert_eq!(backoff.next_delay(), Some(Duration::from_secs(1)));
+}
+
+#[test]
+fn signals_are_read_from_the_fd() {
+  let signals = SignalFd::new(&[libc::SIGUSR1]).unwrap();
+  assert!(signals.read().is_empty());
+
+  unsafe { libc::raise(libc::SIGUSR1) };
+  assert_eq!(signals.read(), [libc::SIGUSR1]);
+  assert!(signals.read().is_empty());
+}
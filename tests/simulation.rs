@@ -0,0 +1,422 @@
+use powereg::cpu::{PerCore, ScalingGoverner, EPP};
+use powereg::events::Event;
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::{State, SystemState};
+use powereg::utils::Config;
+
+fn read_cpus(fixture: &SysfsFixture, n: usize, file: &str) -> Vec<String> {
+  (0..n)
+    .map(|i| {
+      fixture
+        .read(&format!(
+          "/sys/devices/system/cpu/cpu{}/cpufreq/{}",
+          i, file
+        ))
+        .unwrap()
+    })
+    .collect()
+}
+
+#[test]
+fn thinkpad_amd_starts_in_performance_on_ac() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+
+  assert_eq!(*system_state.state.borrow(), State::Performance);
+  assert!(read_cpus(&fixture, 8, "scaling_governor")
+    .iter()
+    .all(|g| g == "performance"));
+  assert_eq!(
+    fixture.read("/sys/firmware/acpi/platform_profile").unwrap(),
+    "performance"
+  );
+}
+
+#[test]
+fn set_powersave_mode_writes_every_knob() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.set_powersave_mode().unwrap();
+
+  assert!(read_cpus(&fixture, 8, "scaling_governor")
+    .iter()
+    .all(|g| g == "powersave"));
+  assert!(read_cpus(&fixture, 8, "energy_performance_preference")
+    .iter()
+    .all(|epp| epp == "power"));
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/cpufreq/boost")
+      .unwrap(),
+    "0"
+  );
+  assert_eq!(
+    fixture.read("/sys/firmware/acpi/platform_profile").unwrap(),
+    "low-power"
+  );
+}
+
+#[test]
+fn intel_boost_goes_through_no_turbo() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  fixture
+    .set_adapter_online("ucsi-source-psy-USBC000:001", true)
+    .unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+
+  system_state.set_performance_mode(true).unwrap();
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/intel_pstate/no_turbo")
+      .unwrap(),
+    "0"
+  );
+
+  system_state.set_powersave_mode().unwrap();
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/intel_pstate/no_turbo")
+      .unwrap(),
+    "1"
+  );
+}
+
+#[test]
+fn passive_pstate_is_left_alone() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  fixture
+    .write("/sys/devices/system/cpu/intel_pstate/status", "passive")
+    .unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/intel_pstate/status")
+      .unwrap(),
+    "passive"
+  );
+  assert!(!system_state.cpu_states.capabilities().epp);
+  system_state.set_powersave_mode().unwrap();
+  assert!(read_cpus(&fixture, 4, "scaling_governor")
+    .iter()
+    .all(|g| g == "powersave"));
+}
+
+#[test]
+fn disabled_pstate_falls_back_to_acpi_cpufreq() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  fixture
+    .write("/sys/devices/system/cpu/amd_pstate/status", "disable")
+    .unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+  assert_eq!(system_state.cpu_states.driver_name(), "acpi-cpufreq");
+  assert!(!system_state.cpu_states.capabilities().epp);
+
+  system_state.set_powersave_mode().unwrap();
+  assert!(read_cpus(&fixture, 8, "scaling_governor")
+    .iter()
+    .all(|g| g == "powersave"));
+  assert_eq!(
+    fixture
+      .read("/sys/devices/system/cpu/cpufreq/boost")
+      .unwrap(),
+    "0"
+  );
+
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  fixture
+    .write("/sys/devices/system/cpu/intel_pstate/status", "off")
+    .unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  assert_eq!(system_state.cpu_states.driver_name(), "acpi-cpufreq");
+}
+
+#[test]
+fn config_apply_sets_thresholds_on_every_battery() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+
+  let config_path = fixture.dir().join("config.toml");
+  std::fs::write(
+    &config_path,
+    "[battery]\nstart_threshold = 75\nstop_threshold = 80\n",
+  )
+  .unwrap();
+  let config = Config::parse(config_path.to_str().unwrap()).unwrap();
+  config.apply(&system_state).unwrap();
+
+  for battery in ["BAT0", "BAT1"] {
+    let dir = format!("/sys/class/power_supply/{}", battery);
+    assert_eq!(
+      fixture
+        .read(&format!("{}/charge_control_start_threshold", dir))
+        .unwrap(),
+      "75"
+    );
+    assert_eq!(
+      fixture
+        .read(&format!("{}/charge_control_end_threshold", dir))
+        .unwrap(),
+      "80"
+    );
+  }
+}
+
+#[test]
+fn config_apply_rejects_unsupported_start_threshold() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+
+  let config_path = fixture.dir().join("config.toml");
+  std::fs::write(
+    &config_path,
+    "[battery]\nstart_threshold = 75\nstop_threshold = 80\n",
+  )
+  .unwrap();
+  let config = Config::parse(config_path.to_str().unwrap()).unwrap();
+  let e = config.apply(&system_state).unwrap_err().to_string();
+  assert!(
+    e.contains("battery.start_threshold is not supported"),
+    "{e}"
+  );
+
+  std::fs::write(&config_path, "[battery]\nstop_threshold = 80\n").unwrap();
+  let config = Config::parse(config_path.to_str().unwrap()).unwrap();
+  assert_eq!(config.charge_start_threshold, None);
+  config.apply(&system_state).unwrap();
+  assert_eq!(
+    fixture
+      .read("/sys/class/power_supply/BAT0/charge_control_end_threshold")
+      .unwrap(),
+    "80"
+  );
+}
+
+#[test]
+fn missing_platform_profile_is_skipped() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  fixture
+    .remove("/sys/firmware/acpi/platform_profile")
+    .unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+
+  system_state.set_powersave_mode().unwrap();
+  assert!(read_cpus(&fixture, 8, "scaling_governor")
+    .iter()
+    .all(|g| g == "powersave"));
+  assert!(!system_state.battery_states.has_platform_profile());
+  assert!(!system_state
+    .knob_paths()
+    .iter()
+    .any(|path| path.ends_with("platform_profile")));
+  assert!(!system_state.to_string().contains("platform profile"));
+}
+
+#[test]
+fn unplugging_switches_to_powersave() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+
+  fixture.set_adapter_online("AC", false).unwrap();
+  Event::PeriodicCheck.handle_event(&system_state).unwrap();
+
+  assert_eq!(*system_state.state.borrow(), State::Powersave);
+  assert!(read_cpus(&fixture, 8, "scaling_governor")
+    .iter()
+    .all(|g| g == "powersave"));
+}
+
+#[test]
+fn desktop_without_battery_runs_on_ac() {
+  let fixture = SysfsFixture::desktop_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+
+  assert!(!system_state.battery_states.has_battery());
+  assert!(system_state.on_ac().unwrap());
+  assert_eq!(*system_state.state.borrow(), State::Performance);
+}
+
+#[test]
+fn cores_that_disagree_are_reported_per_core() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.set_powersave_mode().unwrap();
+  assert_eq!(
+    system_state.cpu_states.read_scaling_governer().unwrap(),
+    PerCore::Uniform(ScalingGoverner::Powersave)
+  );
+
+  let cpu3 = "/sys/devices/system/cpu/cpu3/cpufreq";
+  fixture
+    .write(&format!("{cpu3}/scaling_governor"), "schedutil")
+    .unwrap();
+  fixture
+    .write(
+      &format!("{cpu3}/energy_performance_preference"),
+      "performance",
+    )
+    .unwrap();
+
+  let governor = system_state.cpu_states.read_scaling_governer().unwrap();
+  assert_eq!(governor.uniform(), None);
+  let cpus = system_state.cpu_states.online_cpus();
+  assert_eq!(governor.drifted(&ScalingGoverner::Powersave, &cpus), [3]);
+  let PerCore::Mixed(epp) = system_state.cpu_states.read_epp().unwrap() else {
+    panic!("epp should differ on cpu3");
+  };
+  assert_eq!(epp[&0], EPP::Power);
+  assert_eq!(epp[&3], EPP::Performance);
+}
+
+#[test]
+fn drifted_cores_are_reconverged() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  *system_state.state.borrow_mut() = State::Powersave;
+  system_state.set_powersave_mode().unwrap();
+  assert!(system_state.detect_drift().unwrap().is_empty());
+
+  fixture
+    .write(
+      "/sys/devices/system/cpu/cpu5/cpufreq/scaling_governor",
+      "performance",
+    )
+    .unwrap();
+  let drift = system_state.detect_drift().unwrap();
+  assert_eq!(drift.len(), 1);
+  assert!(
+    drift[0].starts_with("scaling governer on cpu [5]"),
+    "{drift:?}"
+  );
+
+  system_state.reconverge().unwrap();
+  assert!(system_state.detect_drift().unwrap().is_empty());
+  assert!(read_cpus(&fixture, 8, "scaling_governor")
+    .iter()
+    .all(|g| g == "powersave"));
+}
+
+#[test]
+fn offline_cores_are_skipped_and_picked_up_when_onlined() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  fixture.set_cpu_online(2, false).unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  assert_eq!(system_state.cpu_states.online_cpus(), [0, 1, 3]);
+
+  system_state.set_powersave_mode().unwrap();
+  let cpu2 = "/sys/devices/system/cpu/cpu2/cpufreq/scaling_governor";
+  fixture.write(cpu2, "performance").unwrap();
+  assert!(!system_state.cpu_states.rescan().unwrap());
+
+  fixture.set_cpu_online(2, true).unwrap();
+  assert!(system_state.cpu_states.rescan().unwrap());
+  assert_eq!(system_state.cpu_states.online_cpus(), [0, 1, 2, 3]);
+  // came back up with its old governor and epp
+  let drift = system_state.detect_drift().unwrap();
+  assert_eq!(drift.len(), 2);
+  assert!(
+    drift.iter().all(|line| line.contains("cpu [2]")),
+    "{drift:?}"
+  );
+
+  *system_state.state.borrow_mut() = State::Powersave;
+  system_state.reconverge().unwrap();
+  assert_eq!(fixture.read(cpu2).unwrap(), "powersave");
+}
+
+#[test]
+fn cores_sharing_a_policy_are_written_once() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  // cpu0 and cpu1 share policy0, like on some arm and older intel machines
+  fixture
+    .remove("/sys/devices/system/cpu/cpufreq/policy1")
+    .unwrap();
+  fixture
+    .remove("/sys/devices/system/cpu/cpu1/cpufreq")
+    .unwrap();
+  fixture
+    .write(
+      "/sys/devices/system/cpu/cpufreq/policy0/affected_cpus",
+      "0 1",
+    )
+    .unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+
+  assert_eq!(
+    system_state.cpu_states.online_cpus(),
+    (0..8).collect::<Vec<_>>()
+  );
+  let governors = system_state
+    .knob_paths()
+    .into_iter()
+    .filter(|path| path.ends_with("scaling_governor"))
+    .count();
+  assert_eq!(governors, 7);
+}
+
+#[test]
+fn a_hot_cpu_on_ac_switches_to_balanced() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+  let tctl = "/sys/class/hwmon/hwmon0/temp1_input";
+  let check = |millidegrees: &str| {
+    fixture.write(tctl, millidegrees).unwrap();
+    Event::PeriodicCheck.handle_event(&system_state).unwrap();
+    *system_state.state.borrow()
+  };
+
+  assert_eq!(check("84000"), State::Performance);
+  assert_eq!(check("90000"), State::Balanced);
+  assert_eq!(
+    fixture.read("/sys/firmware/acpi/platform_profile").unwrap(),
+    "balanced"
+  );
+  // stays until it's back to normal_cpu_temp
+  assert_eq!(check("80000"), State::Balanced);
+  assert_eq!(check("75000"), State::Performance);
+
+  assert_eq!(check("95000"), State::Balanced);
+  fixture.set_adapter_online("AC", false).unwrap();
+  assert_eq!(check("95000"), State::Powersave);
+}
+
+#[test]
+fn throttling_holds_the_target_temperature() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let system_state = SystemState::init(&fixture.root()).unwrap();
+  system_state.post_init().unwrap();
+  system_state.policy.borrow_mut().throttle = true;
+  let tctl = "/sys/class/hwmon/hwmon0/temp1_input";
+  let check = |millidegrees: &str| {
+    fixture.write(tctl, millidegrees).unwrap();
+    Event::PeriodicCheck.handle_event(&system_state).unwrap();
+    read_cpus(&fixture, 8, "scaling_max_freq")[0].clone()
+  };
+  let boost = || {
+    fixture
+      .read("/sys/devices/system/cpu/cpufreq/boost")
+      .unwrap()
+  };
+
+  // boost goes first, then 10% of the 400-4000 MHz range per check
+  assert_eq!(check("90000"), "4000000");
+  assert_eq!(boost(), "0");
+  assert_eq!(check("90000"), "3640000");
+  assert_eq!(check("82000"), "3280000");
+  // within the hysteresis below the 80°C target
+  assert_eq!(check("78000"), "3280000");
+  assert_eq!(check("76000"), "3640000");
+  assert!(system_state.detect_drift().unwrap().is_empty());
+
+  // cooling down lifts the cap along with balanced
+  assert_eq!(check("70000"), "4000000");
+  assert_eq!(*system_state.state.borrow(), State::Performance);
+  assert_eq!(system_state.throttle.borrow().level(), 0);
+}
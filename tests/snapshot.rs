@@ -0,0 +1,86 @@
+use powereg::fixture::SysfsFixture;
+use powereg::snapshot::{restore_snapshot, Knob, Snapshot};
+use powereg::system_state::{State, SystemState};
+use std::fs;
+use std::path::Path;
+
+fn read_knobs(state: &SystemState) -> Vec<String> {
+  state
+    .knob_paths()
+    .iter()
+    .map(|path| fs::read_to_string(path).unwrap().trim().to_string())
+    .collect()
+}
+
+#[test]
+fn restore_writes_back_the_original_settings() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let original = read_knobs(&state);
+
+  let snapshot = state.save_snapshot().unwrap();
+  assert_eq!(snapshot.knobs.len(), state.knob_paths().len());
+  assert!(Path::new(&state.snapshot_path()).exists());
+
+  state.apply_state(State::Powersave).unwrap();
+  state.battery_states.set_charge_start_threshold(40).unwrap();
+  state.battery_states.set_charge_stop_threshold(60).unwrap();
+  assert_ne!(read_knobs(&state), original);
+
+  restore_snapshot(&state.snapshot_path()).unwrap();
+  assert_eq!(read_knobs(&state), original);
+  assert!(!Path::new(&state.snapshot_path()).exists());
+}
+
+#[test]
+fn a_saved_snapshot_is_kept() {
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let governor = "/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor";
+  let original = fixture.read(governor).unwrap();
+
+  state.save_snapshot().unwrap();
+  state.apply_state(State::Performance).unwrap();
+  assert_ne!(fixture.read(governor).unwrap(), original);
+
+  // a restarted daemon mustn't take its own settings for the original ones
+  let snapshot = state.save_snapshot().unwrap();
+  let knob = snapshot
+    .knobs
+    .iter()
+    .find(|knob| knob.path.ends_with("cpufreq/policy0/scaling_governor"))
+    .unwrap();
+  assert_eq!(knob.value, original);
+}
+
+#[test]
+fn failed_restores_keep_the_snapshot() {
+  let fixture = SysfsFixture::new().unwrap();
+  let path = fixture.dir().join("snapshot.toml");
+  let path = path.to_str().unwrap();
+  let written = fixture.dir().join("knob").to_str().unwrap().to_string();
+
+  Snapshot {
+    knobs: vec![
+      Knob {
+        path: written.clone(),
+        value: "1".to_string(),
+      },
+      Knob {
+        path: fixture
+          .dir()
+          .join("missing/knob")
+          .to_str()
+          .unwrap()
+          .to_string(),
+        value: "1".to_string(),
+      },
+    ],
+  }
+  .save(path)
+  .unwrap();
+
+  assert!(restore_snapshot(path).is_err());
+  assert_eq!(fs::read_to_string(&written).unwrap(), "1");
+  assert!(Path::new(path).exists());
+}
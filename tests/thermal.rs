@@ -0,0 +1,97 @@
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::SystemState;
+use powereg::thermal::Thermal;
+use powereg::utils::Config;
+use std::fs;
+
+fn names(thermal: &Thermal) -> Vec<&str> {
+  thermal.sensors().iter().map(|s| s.name.as_str()).collect()
+}
+
+#[test]
+fn cpu_sensor_is_picked_by_priority() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let thermal = Thermal::discover(&fixture.root());
+  assert_eq!(
+    names(&thermal),
+    [
+      "k10temp/Tctl",
+      "thinkpad/CPU",
+      "thinkpad/GPU",
+      "thermal/acpitz"
+    ]
+  );
+  assert_eq!(thermal.cpu_sensor().unwrap().name, "k10temp/Tctl");
+  assert_eq!(thermal.read_cpu_temp().unwrap(), 52);
+
+  // Tdie is the real die temperature, Tctl can carry an offset
+  fixture.add_hwmon("k10temp", &[("Tdie", 48000)]).unwrap();
+  let thermal = Thermal::discover(&fixture.root());
+  assert_eq!(thermal.cpu_sensor().unwrap().name, "k10temp/Tdie");
+
+  let fixture = SysfsFixture::intel_laptop().unwrap();
+  let thermal = Thermal::discover(&fixture.root());
+  assert_eq!(thermal.cpu_sensor().unwrap().name, "coretemp/Package id 0");
+  assert_eq!(thermal.read_cpu_temp().unwrap(), 61);
+}
+
+#[test]
+fn thermal_zones_are_the_fallback() {
+  let fixture = SysfsFixture::desktop_amd().unwrap();
+  let thermal = Thermal::discover(&fixture.root());
+  assert_eq!(thermal.cpu_sensor().unwrap().name, "thermal/acpitz");
+  assert_eq!(thermal.read_cpu_temp().unwrap(), 45);
+
+  fixture
+    .write("/sys/class/thermal/thermal_zone1/type", "x86_pkg_temp")
+    .unwrap();
+  fixture
+    .write("/sys/class/thermal/thermal_zone1/temp", "57000")
+    .unwrap();
+  // unlabeled inputs are named after the file
+  fixture
+    .write("/sys/class/hwmon/hwmon0/name", "nvme")
+    .unwrap();
+  fixture
+    .write("/sys/class/hwmon/hwmon0/temp1_input", "38000")
+    .unwrap();
+  let thermal = Thermal::discover(&fixture.root());
+  assert_eq!(
+    names(&thermal),
+    ["nvme/temp1", "thermal/acpitz", "thermal/x86_pkg_temp"]
+  );
+  assert_eq!(thermal.cpu_sensor().unwrap().name, "thermal/x86_pkg_temp");
+
+  fixture.remove("/sys/class/thermal").unwrap();
+  fixture.remove("/sys/class/hwmon").unwrap();
+  let thermal = Thermal::discover(&fixture.root());
+  assert!(thermal.cpu_sensor().is_none());
+  assert!(thermal.read_cpu_temp().is_err());
+}
+
+#[test]
+fn config_picks_the_sensor() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let path = fixture.dir().join("config.toml");
+
+  fs::write(&path, "[thermal]\nsensor = \"thinkpad/CPU\"\n").unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  config.apply(&state).unwrap();
+  assert_eq!(state.cpu_states.read_cpu_temp().unwrap(), 50);
+
+  // dropping the key goes back to the detected sensor
+  fs::write(&path, "").unwrap();
+  let detected = Config::parse(path.to_str().unwrap()).unwrap();
+  assert_eq!(
+    detected.diff(&config),
+    ["thermal.sensor: thinkpad/CPU -> detected"]
+  );
+  detected.changes_from(&config).apply(&state).unwrap();
+  assert_eq!(state.cpu_states.read_cpu_temp().unwrap(), 52);
+
+  fs::write(&path, "[thermal]\nsensor = \"coretemp/Package id 0\"\n").unwrap();
+  let config = Config::parse(path.to_str().unwrap()).unwrap();
+  assert!(config.apply(&state).is_err());
+  assert_eq!(state.cpu_states.read_cpu_temp().unwrap(), 52);
+}
@@ -0,0 +1,68 @@
+use powereg::battery::TimeEstimate;
+use powereg::fixture::SysfsFixture;
+use powereg::system_state::SystemState;
+
+fn set_battery(fixture: &SysfsFixture, status: &str, power_uw: u64) {
+  for battery in ["BAT0", "BAT1"] {
+    let dir = format!("/sys/class/power_supply/{battery}");
+    fixture.write(&format!("{dir}/status"), status).unwrap();
+    fixture
+      .write(&format!("{dir}/power_now"), &power_uw.to_string())
+      .unwrap();
+  }
+}
+
+#[test]
+fn time_to_empty_uses_the_smoothed_power_draw() {
+  // 42.5 Wh + 30 Wh left
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let battery_states = &state.battery_states;
+  assert_eq!(battery_states.estimate_time(), None);
+
+  set_battery(&fixture, "Discharging", 7_500_000);
+  battery_states.sample();
+  let estimate = battery_states.estimate_time().unwrap();
+  // 72.5 Wh at 15 W
+  assert_eq!(estimate.secs, 17_400);
+  assert_eq!(estimate.to_string(), "4h 50m to empty");
+
+  // a spike only moves the average part of the way: 15 W + 0.3 * (30 W - 15 W)
+  set_battery(&fixture, "Discharging", 15_000_000);
+  battery_states.sample();
+  assert_eq!(battery_states.estimate_time().unwrap().secs, 13_384);
+}
+
+#[test]
+fn time_to_full_stops_at_the_threshold() {
+  let fixture = SysfsFixture::thinkpad_amd().unwrap();
+  let state = SystemState::init(&fixture.root()).unwrap();
+  let battery_states = &state.battery_states;
+  battery_states.set_charge_stop_threshold(80).unwrap();
+
+  set_battery(&fixture, "Discharging", 30_000_000);
+  battery_states.sample();
+  // switching to charging starts the average over
+  set_battery(&fixture, "Charging", 7_500_000);
+  battery_states.sample();
+  // 80 Wh of 100 Wh at the threshold, 7.5 Wh to go at 15 W
+  assert_eq!(
+    battery_states.estimate_time(),
+    Some(TimeEstimate {
+      secs: 1800,
+      charging: true,
+      target: 80,
+    })
+  );
+  assert_eq!(
+    battery_states.estimate_time().unwrap().to_string(),
+    "30m to 80%"
+  );
+
+  battery_states.set_charge_stop_threshold(70).unwrap();
+  assert_eq!(battery_states.estimate_time(), None);
+
+  set_battery(&fixture, "Not charging", 0);
+  battery_states.sample();
+  assert_eq!(battery_states.estimate_time(), None);
+}